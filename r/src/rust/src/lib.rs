@@ -3,9 +3,17 @@ use arrow::ffi_stream::ArrowArrayStreamReader;
 
 use arrow_extendr::to::*;
 use arrow_extendr::from::*;
+use arrow_extendr::batches::RecordBatches;
+use arrow_extendr::chunked::ChunkedArray;
+use arrow_extendr::factor::Factor;
+use arrow_extendr::metadata::{schema_metadata, set_schema_metadata};
 use extendr_api::{prelude::*};
 
-use arrow::array::Int32Array;
+use arrow::array::{
+    Array, ArrayRef, BinaryViewArray, Int32Array, ListViewArray, RunArray, Scalar, StringViewArray,
+};
+use arrow::buffer::{NullBuffer, ScalarBuffer};
+use arrow::datatypes::Int32Type;
 
 #[extendr]
 /// @export
@@ -53,6 +61,22 @@ fn test_record_batch() -> Result<Robj>{
     batch.to_arrow_robj()
 }
 
+#[extendr]
+/// @export
+fn test_record_batch_array() -> Result<Robj>{
+    let id_array = Int32Array::from(vec![1, 2, 3, 4, 5]);
+    let schema = Schema::new(vec![
+        Field::new("id", DataType::Int32, false)
+    ]);
+
+    let batch = RecordBatch::try_new(
+        Arc::new(schema),
+        vec![Arc::new(id_array)]
+    ).unwrap();
+
+    batch.to_arrow_array_robj()
+}
+
 #[extendr]
 /// @export
 fn test_schema() -> Result<Robj> {
@@ -120,6 +144,177 @@ fn test_from_array_steam_reader(rb: Robj) {
     }
 }
 
+// round trips a length-1 vector, `nanoarrow_array` or `{arrow}` Scalar
+#[extendr]
+/// @export
+fn test_scalar(x: Robj) -> Result<Robj> {
+    let scalar = Scalar::<ArrayRef>::from_arrow_robj(&x).unwrap();
+    scalar.to_arrow_robj()
+}
+
+// round trips a factor or dictionary array, keeping its levels and `ordered`
+#[extendr]
+/// @export
+fn test_factor(x: Robj) -> Result<Robj> {
+    let factor = Factor::from_arrow_robj(&x).map_err(arrow_error)?;
+    factor.to_arrow_robj()
+}
+
+fn view_policy(views: &str) -> Result<ViewPolicy> {
+    match views {
+        "auto" => Ok(ViewPolicy::Auto),
+        "keep" => Ok(ViewPolicy::Keep),
+        "cast" => Ok(ViewPolicy::Cast),
+        _ => Err(Error::Other(format!("unknown view policy `{views}`"))),
+    }
+}
+
+// exports a `Utf8View` array with the view policy "auto", "keep" or "cast"
+#[extendr]
+/// @export
+fn test_string_view(views: &str) -> Result<Robj> {
+    let array = StringViewArray::from(vec![
+        Some("a"),
+        None,
+        Some("a string longer than twelve bytes"),
+    ]);
+    array
+        .to_data()
+        .to_arrow_robj_with(&ExportOptions::default().with_views(view_policy(views)?))
+}
+
+// exports a `BinaryView` array with the view policy "auto", "keep" or "cast"
+#[extendr]
+/// @export
+fn test_binary_view(views: &str) -> Result<Robj> {
+    let values: Vec<Option<&[u8]>> = vec![Some(b"ab"), None, Some(&[0u8; 20])];
+    BinaryViewArray::from(values)
+        .to_data()
+        .to_arrow_robj_with(&ExportOptions::default().with_views(view_policy(views)?))
+}
+
+// exports the list view [[3, 4], NULL, [], [1, 2, 3]], which is always cast to a list
+#[extendr]
+/// @export
+fn test_list_view() -> Result<Robj> {
+    let field = Arc::new(Field::new("item", DataType::Int32, true));
+    let array = ListViewArray::try_new(
+        field,
+        ScalarBuffer::from(vec![2, 0, 0, 0]),
+        ScalarBuffer::from(vec![2, 0, 0, 3]),
+        Arc::new(Int32Array::from(vec![1, 2, 3, 4])),
+        Some(NullBuffer::from(vec![true, false, true, true])),
+    )
+    .map_err(arrow_error)?;
+
+    array
+        .to_data()
+        .to_arrow_robj_with(&ExportOptions::default().with_views(ViewPolicy::Keep))
+}
+
+// what `ViewPolicy::Auto` decides for the installed R packages
+#[extendr]
+/// @export
+fn test_supports_views() -> bool {
+    arrow_extendr::backend::supports_views()
+}
+
+// imports an array and returns its arrow-rs type
+#[extendr]
+/// @export
+fn test_import_type(x: Robj) -> Result<String> {
+    let data = ArrayData::from_arrow_robj(&x).map_err(arrow_error)?;
+    Ok(data.data_type().to_string())
+}
+
+// exports a run-end encoded array decoded for R, `{nanoarrow}` sees a string vector
+#[extendr]
+/// @export
+fn test_run_end() -> Result<Robj> {
+    let array: RunArray<Int32Type> = vec!["a", "a", "b", "b", "b"].into_iter().collect();
+    array
+        .to_data()
+        .to_arrow_robj_with(&ExportOptions::default().with_types(TypePolicy::r_friendly()))
+}
+
+// reads the schema metadata without importing the data, then tags the object
+// and checks that the existing keys and the tag are read back
+#[extendr]
+/// @export
+fn test_metadata(x: Robj) -> Result<Robj> {
+    let mut metadata = schema_metadata(&x).map_err(arrow_error)?;
+    metadata.insert("arrow_extendr".into(), "test".into());
+
+    let tagged = set_schema_metadata(&x, metadata.clone()).map_err(arrow_error)?;
+    if schema_metadata(&tagged).map_err(arrow_error)? != metadata {
+        return Err(Error::Other("the metadata was not written".into()));
+    }
+
+    Ok(tagged)
+}
+
+// exports an empty stream that keeps its schema
+#[extendr]
+/// @export
+fn test_empty_batches() -> Result<Robj> {
+    let schema = Schema::new(vec![
+        Field::new("id", DataType::Int32, false),
+        Field::new("name", DataType::Utf8, true),
+    ]);
+    RecordBatches::empty(Arc::new(schema)).into_arrow_robj()
+}
+
+// re-exports a chunked array as a single column stream without concatenating it
+#[extendr]
+/// @export
+fn test_chunked(x: Robj) -> Result<Robj> {
+    let chunked = ChunkedArray::from_arrow_robj(&x).map_err(arrow_error)?;
+    rprintln!("{} chunks, {} rows", chunked.num_chunks(), chunked.len());
+    chunked.into_arrow_robj()
+}
+
+static RUNTIME: std::sync::OnceLock<tokio::runtime::Runtime> = std::sync::OnceLock::new();
+
+// registers `x` as the table `tbl` and returns the result of `sql` as a lazy stream
+#[extendr]
+/// @export
+fn test_sql(x: Robj, sql: &str) -> Result<Robj> {
+    let runtime = RUNTIME.get_or_init(|| tokio::runtime::Runtime::new().unwrap());
+    let ctx = datafusion::prelude::SessionContext::new();
+
+    arrow_extendr::datafusion::register_robj(&ctx, "tbl", &x).map_err(arrow_error)?;
+    arrow_extendr::datafusion::sql_to_robj(&ctx, sql, runtime.handle())
+}
+
+// Share semantics: the R object must still be usable afterwards, these
+// return what was imported so R can compare it with the object
+#[extendr]
+/// @export
+fn test_from_array_shared(x: Robj) -> Result<i32> {
+    let data = ArrayData::from_arrow_robj_shared(&x).map_err(arrow_error)?;
+    Ok(data.len() as i32)
+}
+
+#[extendr]
+/// @export
+fn test_from_schema_shared(x: Robj) -> Result<i32> {
+    let schema = Schema::from_arrow_robj_shared(&x).map_err(arrow_error)?;
+    Ok(schema.fields().len() as i32)
+}
+
+#[extendr]
+/// @export
+fn test_from_array_stream_shared(x: Robj) -> Result<i32> {
+    let rb = ArrowArrayStreamReader::from_arrow_robj_shared(&x).map_err(arrow_error)?;
+
+    let mut n = 0;
+    for chunk in rb {
+        n += chunk.map_err(arrow_error)?.num_rows();
+    }
+
+    Ok(n as i32)
+}
+
 #[extendr]
 /// @export
 fn process_stream(stream: Robj) -> i32 {
@@ -147,6 +342,7 @@ extendr_module! {
     fn test_f64;
     fn test_field;
     fn test_record_batch;
+    fn test_record_batch_array;
     fn test_schema;
     fn test_datatype;
 
@@ -157,6 +353,21 @@ extendr_module! {
     fn test_from_array;
     fn test_from_recordbatch;
     fn test_from_array_steam_reader;
+    fn test_scalar;
+    fn test_factor;
+    fn test_string_view;
+    fn test_binary_view;
+    fn test_list_view;
+    fn test_supports_views;
+    fn test_import_type;
+    fn test_run_end;
+    fn test_metadata;
+    fn test_empty_batches;
+    fn test_chunked;
+    fn test_sql;
+    fn test_from_array_shared;
+    fn test_from_schema_shared;
+    fn test_from_array_stream_shared;
 
     // 
     fn process_stream;
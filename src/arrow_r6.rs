@@ -0,0 +1,198 @@
+//! Export arrow-rs structs as `{arrow}` R6 objects
+//!
+//! The traits `ToArrowR6Robj` and `IntoArrowR6Robj` mirror `ToArrowRobj` and
+//! `IntoArrowRobj` but produce objects from the `{arrow}` R package instead of
+//! `{nanoarrow}` S3 objects. The C structs are handed to the `import_from_c()`
+//! methods of the R6 classes so no intermediate `nanoarrow` object is created.
+//!
+//! ```ignore
+//! fn batch_to_arrow(batch: RecordBatch) -> Result<Robj> {
+//!     batch.to_arrow_r6_robj()
+//! }
+//! ```
+//!
+//! |           arrow-rs struct           |          R object          |
+//! | ----------------------------------- | -------------------------- |
+//! | `ArrayData`                         | `arrow::Array`             |
+//! | `PrimitiveArray<T>`                 | `arrow::Array`             |
+//! | `Field`                             | `arrow::Field`             |
+//! | `DataType`                          | `arrow::DataType`          |
+//! | `Schema`                            | `arrow::Schema`            |
+//! | `RecordBatch`                       | `arrow::RecordBatch`       |
+//! | `Vec<RecordBatch>`                  | `arrow::Table`             |
+//! | `ArrowArrayStreamReader`            | `arrow::RecordBatchReader` |
+//! | `Box<dyn RecordBatchReader + Send>` | `arrow::RecordBatchReader` |
+//! | `RecordBatchIterator<I>`            | `arrow::RecordBatchReader` |
+//!
+//! **Requires `arrow` to be installed**. Only available with the `r-arrow` feature.
+use arrow::{
+    array::{Array, ArrayData, PrimitiveArray, StructArray},
+    datatypes::{ArrowPrimitiveType, DataType, Field, Schema, SchemaBuilder},
+    error::ArrowError,
+    ffi::{to_ffi, FFI_ArrowArray, FFI_ArrowSchema},
+    ffi_stream::{ArrowArrayStreamReader, FFI_ArrowArrayStream},
+    record_batch::{RecordBatch, RecordBatchIterator, RecordBatchReader},
+};
+use extendr_api::prelude::*;
+
+use crate::{backend::r_function, to::arrow_error};
+
+/// Calls `arrow::<class>$import_from_c()`
+///
+/// Pointer addresses are passed as strings. Requires `{arrow}` to be installed.
+pub fn import_from_c(class: &str, args: Pairlist) -> Result<Robj> {
+    r_function(&format!("arrow::{class}$import_from_c"))?.call(args)
+}
+
+/// Moves an `FFI_ArrowSchema` into an `arrow::Schema`, `arrow::Field` or `arrow::DataType`
+pub(crate) fn schema_to_r6(class: &str, ffi_schema: FFI_ArrowSchema) -> Result<Robj> {
+    let schema_ptr = &ffi_schema as *const FFI_ArrowSchema as usize;
+    import_from_c(class, pairlist!(schema_ptr.to_string()))
+}
+
+/// Moves an `FFI_ArrowArray` and its `FFI_ArrowSchema` into an `arrow::Array` or `arrow::RecordBatch`
+pub(crate) fn array_to_r6(
+    class: &str,
+    ffi_array: FFI_ArrowArray,
+    ffi_schema: FFI_ArrowSchema,
+) -> Result<Robj> {
+    let array_ptr = &ffi_array as *const FFI_ArrowArray as usize;
+    let schema_ptr = &ffi_schema as *const FFI_ArrowSchema as usize;
+    import_from_c(
+        class,
+        pairlist!(array_ptr.to_string(), schema_ptr.to_string()),
+    )
+}
+
+/// Moves a `RecordBatchReader` into an `arrow::RecordBatchReader`
+pub(crate) fn reader_to_r6(reader: Box<dyn RecordBatchReader + Send>) -> Result<Robj> {
+    let mut stream = FFI_ArrowArrayStream::new(reader);
+    let stream_ptr = (&mut stream) as *mut FFI_ArrowArrayStream as usize;
+    import_from_c("RecordBatchReader", pairlist!(stream_ptr.to_string()))
+}
+
+/// Convert an Arrow struct to an `{arrow}` R6 object
+///
+/// Does not consume `self`.
+///
+/// **Requires `arrow` to be available**.
+pub trait ToArrowR6Robj {
+    fn to_arrow_r6_robj(&self) -> Result<Robj>;
+}
+
+impl ToArrowR6Robj for ArrayData {
+    fn to_arrow_r6_robj(&self) -> Result<Robj> {
+        let (ffi_array, ffi_schema) = to_ffi(self).map_err(arrow_error)?;
+        array_to_r6("Array", ffi_array, ffi_schema)
+    }
+}
+
+impl<T: ArrowPrimitiveType> ToArrowR6Robj for PrimitiveArray<T> {
+    fn to_arrow_r6_robj(&self) -> Result<Robj> {
+        self.to_data().to_arrow_r6_robj()
+    }
+}
+
+impl ToArrowR6Robj for Field {
+    fn to_arrow_r6_robj(&self) -> Result<Robj> {
+        let ffi_schema = FFI_ArrowSchema::try_from(self).map_err(arrow_error)?;
+        schema_to_r6("Field", ffi_schema)
+    }
+}
+
+impl ToArrowR6Robj for DataType {
+    fn to_arrow_r6_robj(&self) -> Result<Robj> {
+        let ffi_schema = FFI_ArrowSchema::try_from(self).map_err(arrow_error)?;
+        schema_to_r6("DataType", ffi_schema)
+    }
+}
+
+impl ToArrowR6Robj for Schema {
+    fn to_arrow_r6_robj(&self) -> Result<Robj> {
+        let ffi_schema = FFI_ArrowSchema::try_from(self).map_err(arrow_error)?;
+        schema_to_r6("Schema", ffi_schema)
+    }
+}
+
+impl ToArrowR6Robj for RecordBatch {
+    fn to_arrow_r6_robj(&self) -> Result<Robj> {
+        let data = StructArray::from(self.clone()).into_data();
+        let ffi_array = FFI_ArrowArray::new(&data);
+        let ffi_schema = FFI_ArrowSchema::try_from(self.schema().as_ref()).map_err(arrow_error)?;
+
+        array_to_r6("RecordBatch", ffi_array, ffi_schema)
+    }
+}
+
+/// Convert an Arrow struct to an `{arrow}` R6 object
+///
+/// Consumes `self`.
+///
+/// **Requires `arrow` to be available**.
+pub trait IntoArrowR6Robj {
+    fn into_arrow_r6_robj(self) -> Result<Robj>;
+}
+
+// macro to implement `IntoArrowR6Robj` for those that have `ToArrowR6Robj` implemented
+macro_rules! impl_into_arrow_r6 {
+    ($t:ident) => {
+        impl IntoArrowR6Robj for $t {
+            fn into_arrow_r6_robj(self) -> Result<Robj> {
+                self.to_arrow_r6_robj()
+            }
+        }
+    };
+}
+
+impl_into_arrow_r6!(ArrayData);
+impl_into_arrow_r6!(Field);
+impl_into_arrow_r6!(Schema);
+impl_into_arrow_r6!(DataType);
+impl_into_arrow_r6!(RecordBatch);
+
+impl<T: ArrowPrimitiveType> IntoArrowR6Robj for PrimitiveArray<T> {
+    fn into_arrow_r6_robj(self) -> Result<Robj> {
+        self.to_arrow_r6_robj()
+    }
+}
+
+impl IntoArrowR6Robj for ArrowArrayStreamReader {
+    fn into_arrow_r6_robj(self) -> Result<Robj> {
+        reader_to_r6(Box::new(self))
+    }
+}
+
+impl IntoArrowR6Robj for Box<dyn RecordBatchReader + Send> {
+    fn into_arrow_r6_robj(self) -> Result<Robj> {
+        reader_to_r6(self)
+    }
+}
+
+impl<I> IntoArrowR6Robj for RecordBatchIterator<I>
+where
+    I: IntoIterator<Item = std::result::Result<RecordBatch, ArrowError>> + Send + 'static,
+    <I as IntoIterator>::IntoIter: Send,
+{
+    fn into_arrow_r6_robj(self) -> Result<Robj> {
+        reader_to_r6(Box::new(self))
+    }
+}
+
+/// Collects the batches into an `arrow::Table`
+impl IntoArrowR6Robj for Vec<RecordBatch> {
+    fn into_arrow_r6_robj(self) -> Result<Robj> {
+        let schema = match self.first() {
+            Some(batch) => batch.schema(),
+            None => SchemaBuilder::new().finish().into(),
+        };
+
+        let reader = RecordBatchIterator::new(self.into_iter().map(Ok), schema);
+        let reader = reader.into_arrow_r6_robj()?;
+
+        reader
+            .dollar("read_table")?
+            .as_function()
+            .ok_or_else(|| Error::Other("`$read_table()` must be a function".into()))?
+            .call(pairlist!())
+    }
+}
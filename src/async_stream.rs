@@ -0,0 +1,345 @@
+//! Bridge R array streams and async Rust
+//!
+//! Requires the `async` feature.
+//!
+//! An `ArrowArrayStreamReader` imported from R calls back into R for every
+//! batch so it can only be read on the R main thread. `stream_channel()`
+//! splits it into a `StreamPump`, which is driven on the R thread, and a
+//! `RecordBatchReceiverStream` that implements `futures::Stream` and can be
+//! moved to any task. The channel is bounded so the R thread stops reading
+//! when async code falls behind.
+//!
+//! ```ignore
+//! #[extendr]
+//! fn count_rows(stream: Robj) -> Result<i32> {
+//!     let reader = ArrowArrayStreamReader::from_arrow_robj(&stream).map_err(arrow_error)?;
+//!     let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+//!
+//!     let n = drive_stream(reader, 4, runtime.handle(), |mut batches| async move {
+//!         let mut n = 0;
+//!         while let Some(batch) = batches.next().await {
+//!             n += batch.unwrap().num_rows();
+//!         }
+//!         n
+//!     })
+//!     .map_err(arrow_error)?;
+//!
+//!     Ok(n as i32)
+//! }
+//! ```
+//!
+//! `drive_stream()` polls the pump and `f` together with `Handle::block_on()`
+//! so it works on both current-thread and multi-thread runtimes.
+//!
+//! Going the other way, `BlockingStreamReader` wraps a `Stream` of
+//! `RecordBatch`es so it can be returned to R with `into_arrow_robj()`. Each
+//! time R pulls a batch the stream is polled with `Handle::block_on()`, which
+//! cannot run spawned tasks on a current-thread runtime, so it requires a
+//! multi-thread runtime.
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use arrow::{
+    datatypes::SchemaRef,
+    ffi_stream::ArrowArrayStreamReader,
+    record_batch::{RecordBatch, RecordBatchReader},
+};
+use extendr_api::prelude::*;
+use futures::{Stream, StreamExt};
+use tokio::{
+    runtime::{Handle, RuntimeFlavor},
+    sync::mpsc,
+};
+
+use crate::{from::ErrArrowRobj, to::IntoArrowRobj};
+
+type BatchResult = std::result::Result<RecordBatch, ErrArrowRobj>;
+
+/// Creates a bounded channel fed from an imported R stream
+///
+/// At most `capacity` batches are buffered before `StreamPump::run()` blocks.
+/// Returns an error when `capacity` is 0.
+pub fn stream_channel(
+    reader: ArrowArrayStreamReader,
+    capacity: usize,
+) -> std::result::Result<(StreamPump, RecordBatchReceiverStream), ErrArrowRobj> {
+    if capacity == 0 {
+        return Err(ErrArrowRobj::InvalidArgumentError(
+            "the capacity of a stream channel must be at least 1".into(),
+        ));
+    }
+
+    let schema = reader.schema();
+    let (tx, rx) = mpsc::channel(capacity);
+
+    Ok((
+        StreamPump { reader, tx },
+        RecordBatchReceiverStream { schema, rx },
+    ))
+}
+
+/// Reads batches from R and sends them to a `RecordBatchReceiverStream`
+///
+/// Must be run on the R main thread.
+pub struct StreamPump {
+    reader: ArrowArrayStreamReader,
+    tx: mpsc::Sender<BatchResult>,
+}
+
+impl StreamPump {
+    /// Sends every batch, blocking while the channel is full
+    ///
+    /// Stops early when the receiving stream is dropped. Errors from the R
+    /// stream are forwarded to the receiver and end the pump.
+    ///
+    /// Must not be called from within a runtime. The receiver has to be
+    /// polled on another thread, e.g. by a task on a multi-thread runtime,
+    /// otherwise a full channel blocks forever. Prefer `drive_stream()`.
+    pub fn run(self) {
+        for batch in self.reader {
+            let is_err = batch.is_err();
+
+            if self.tx.blocking_send(batch).is_err() || is_err {
+                break;
+            }
+        }
+    }
+
+    /// Sends every batch, waiting while the channel is full
+    ///
+    /// Reads from R between awaits, so the future must be polled on the R
+    /// main thread, e.g. with `Handle::block_on()`.
+    pub async fn run_async(self) {
+        for batch in self.reader {
+            let is_err = batch.is_err();
+
+            if self.tx.send(batch).await.is_err() || is_err {
+                break;
+            }
+        }
+    }
+}
+
+/// A `futures::Stream` of `RecordBatch`es read from R by a `StreamPump`
+pub struct RecordBatchReceiverStream {
+    schema: SchemaRef,
+    rx: mpsc::Receiver<BatchResult>,
+}
+
+impl RecordBatchReceiverStream {
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
+
+impl Stream for RecordBatchReceiverStream {
+    type Item = BatchResult;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+/// Processes an imported R stream with async code
+///
+/// The future returned by `f` and the pump are polled together on the calling
+/// (R) thread with `handle.block_on()`, so any runtime flavor works. Tasks
+/// that `f` spawns run on the runtime as usual. Returns the output of `f`
+/// once both finish.
+///
+/// Returns an error when `capacity` is 0 or when called from within a
+/// runtime, where `block_on()` would panic.
+pub fn drive_stream<F, Fut, T>(
+    reader: ArrowArrayStreamReader,
+    capacity: usize,
+    handle: &Handle,
+    f: F,
+) -> std::result::Result<T, ErrArrowRobj>
+where
+    F: FnOnce(RecordBatchReceiverStream) -> Fut,
+    Fut: Future<Output = T>,
+{
+    if Handle::try_current().is_ok() {
+        return Err(ErrArrowRobj::InvalidArgumentError(
+            "`drive_stream()` cannot be called from within a runtime".into(),
+        ));
+    }
+
+    let (pump, stream) = stream_channel(reader, capacity)?;
+    let ((), out) = handle.block_on(futures::future::join(pump.run_async(), f(stream)));
+
+    Ok(out)
+}
+
+/// Returns an error unless `handle` belongs to a multi-thread runtime
+pub fn check_multi_thread(handle: &Handle) -> std::result::Result<(), ErrArrowRobj> {
+    if handle.runtime_flavor() == RuntimeFlavor::CurrentThread {
+        return Err(ErrArrowRobj::InvalidArgumentError(
+            "a multi-thread runtime is required, found a current-thread runtime".into(),
+        ));
+    }
+    Ok(())
+}
+
+/// A `RecordBatchReader` that blocks on an async `Stream`
+///
+/// The runtime behind `handle` must be multi-threaded, which `new()` checks,
+/// and outlive the reader since R may pull batches long after the stream is
+/// exported.
+pub struct BlockingStreamReader<S> {
+    schema: SchemaRef,
+    stream: Pin<Box<S>>,
+    handle: Handle,
+}
+
+impl<S> BlockingStreamReader<S>
+where
+    S: Stream<Item = BatchResult> + Send + 'static,
+{
+    pub fn new(
+        schema: SchemaRef,
+        stream: S,
+        handle: Handle,
+    ) -> std::result::Result<Self, ErrArrowRobj> {
+        check_multi_thread(&handle)?;
+
+        Ok(Self {
+            schema,
+            stream: Box::pin(stream),
+            handle,
+        })
+    }
+}
+
+impl<S> Iterator for BlockingStreamReader<S>
+where
+    S: Stream<Item = BatchResult> + Send + 'static,
+{
+    type Item = BatchResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.handle.block_on(self.stream.next())
+    }
+}
+
+impl<S> RecordBatchReader for BlockingStreamReader<S>
+where
+    S: Stream<Item = BatchResult> + Send + 'static,
+{
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
+
+impl<S> IntoArrowRobj for BlockingStreamReader<S>
+where
+    S: Stream<Item = BatchResult> + Send + 'static,
+{
+    fn into_arrow_robj(self) -> Result<Robj> {
+        let reader: Box<dyn RecordBatchReader + Send> = Box::new(self);
+        reader.into_arrow_robj()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::{
+        array::Int32Array,
+        datatypes::{DataType, Field, Schema},
+        ffi_stream::FFI_ArrowArrayStream,
+        record_batch::RecordBatchIterator,
+    };
+
+    use super::*;
+
+    fn reader(n_batches: i32) -> ArrowArrayStreamReader {
+        let schema = Arc::new(Schema::new(vec![Field::new("x", DataType::Int32, false)]));
+        let batches = (0..n_batches)
+            .map(|i| {
+                RecordBatch::try_new(schema.clone(), vec![Arc::new(Int32Array::from(vec![i; 3]))])
+            })
+            .collect::<Vec<_>>();
+        let batches = RecordBatchIterator::new(batches, schema);
+
+        ArrowArrayStreamReader::try_new(FFI_ArrowArrayStream::new(Box::new(batches))).unwrap()
+    }
+
+    async fn count_rows(mut batches: RecordBatchReceiverStream) -> usize {
+        let mut n = 0;
+        while let Some(batch) = batches.next().await {
+            n += batch.unwrap().num_rows();
+        }
+        n
+    }
+
+    #[test]
+    fn drives_a_stream_on_a_current_thread_runtime() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        // more batches than the channel holds
+        let n = drive_stream(reader(10), 2, runtime.handle(), count_rows).unwrap();
+        assert_eq!(n, 30);
+    }
+
+    #[test]
+    fn drives_a_stream_on_a_multi_thread_runtime() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let n = drive_stream(reader(10), 2, runtime.handle(), |batches| async move {
+            tokio::spawn(count_rows(batches)).await.unwrap()
+        })
+        .unwrap();
+        assert_eq!(n, 30);
+    }
+
+    #[test]
+    fn rejects_an_empty_channel() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        assert!(stream_channel(reader(1), 0).is_err());
+        let error = drive_stream(reader(1), 0, runtime.handle(), count_rows).unwrap_err();
+        assert!(error.to_string().contains("at least 1"));
+    }
+
+    #[test]
+    fn cannot_drive_a_stream_within_a_runtime() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = runtime.enter();
+
+        let error = drive_stream(reader(1), 1, runtime.handle(), count_rows).unwrap_err();
+        assert!(error.to_string().contains("within a runtime"));
+    }
+
+    #[test]
+    fn blocking_reader_requires_a_multi_thread_runtime() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let (pump, stream) = stream_channel(reader(1), 1).unwrap();
+        drop(pump);
+
+        let schema = stream.schema();
+        assert!(BlockingStreamReader::new(schema, stream, runtime.handle().clone()).is_err());
+    }
+
+    #[test]
+    fn blocking_reader_reads_a_stream() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (pump, stream) = stream_channel(reader(3), 4).unwrap();
+        pump.run();
+
+        let schema = stream.schema();
+        let reader = BlockingStreamReader::new(schema, stream, runtime.handle().clone()).unwrap();
+        assert_eq!(
+            reader.map(|batch| batch.unwrap().num_rows()).sum::<usize>(),
+            9
+        );
+    }
+}
@@ -0,0 +1,223 @@
+//! Choose which R packages are used to move Arrow data in and out of R
+//!
+//! The backend is selected at compile time with cargo features.
+//!
+//! |   feature   |         backend        |                               R objects                               |
+//! | ----------- | ---------------------- | --------------------------------------------------------------------- |
+//! | `nanoarrow` | `Backend::Nanoarrow`   | `{nanoarrow}` objects, created and read by calling `{nanoarrow}`      |
+//! | `native`    | `Backend::Native`      | `{nanoarrow}` objects, created and read without any R package         |
+//! | `r-arrow`   | `Backend::Arrow`       | `{arrow}` R6 objects, see the `arrow_r6` module                       |
+//!
+//! `nanoarrow` and `r-arrow` are enabled by default. When several backends are
+//! enabled the first one in the table above is used by `ToArrowRobj` and
+//! `IntoArrowRobj`. For a nanoarrow-only build use
+//! `default-features = false, features = ["nanoarrow"]`, for an arrow-only
+//! build `features = ["r-arrow"]` and for a build without R package
+//! dependencies `features = ["native"]`.
+//!
+//! Conversions return an error when an R package they need is not installed.
+//! Call `check_backend()` to check up front, e.g. in `.onLoad()`.
+//!
+//! ```ignore
+//! #[extendr]
+//! fn check_arrow_backend() -> Result<()> {
+//!     check_backend()
+//! }
+//! ```
+use std::sync::{Mutex, OnceLock};
+
+use extendr_api::prelude::*;
+
+#[cfg(not(any(feature = "nanoarrow", feature = "native", feature = "r-arrow")))]
+compile_error!("one of the `nanoarrow`, `native` or `r-arrow` features must be enabled");
+
+/// How arrow-rs structs are converted into R objects
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// Calls functions from `{nanoarrow}`
+    Nanoarrow,
+    /// Creates `{nanoarrow}` compatible external pointers directly
+    Native,
+    /// Calls the `import_from_c()` methods of `{arrow}` R6 classes
+    Arrow,
+}
+
+impl Backend {
+    /// The R package that must be installed, if any
+    pub fn package(&self) -> Option<&'static str> {
+        match self {
+            Backend::Nanoarrow => Some("nanoarrow"),
+            Backend::Native => None,
+            Backend::Arrow => Some("arrow"),
+        }
+    }
+}
+
+/// The backend selected by the enabled features
+#[cfg(feature = "nanoarrow")]
+pub const BACKEND: Backend = Backend::Nanoarrow;
+/// The backend selected by the enabled features
+#[cfg(all(feature = "native", not(feature = "nanoarrow")))]
+pub const BACKEND: Backend = Backend::Native;
+/// The backend selected by the enabled features
+#[cfg(not(any(feature = "nanoarrow", feature = "native")))]
+pub const BACKEND: Backend = Backend::Arrow;
+
+/// Returns an error if the R package needed by `BACKEND` is not installed
+pub fn check_backend() -> Result<()> {
+    match BACKEND.package() {
+        Some(pkg) => require_package(pkg),
+        None => Ok(()),
+    }
+}
+
+// only packages that were found are cached so they can be installed mid-session
+static INSTALLED: OnceLock<Mutex<Vec<String>>> = OnceLock::new();
+
+/// Checks if an R package is installed with `requireNamespace()`
+pub fn is_installed(pkg: &str) -> bool {
+    let installed = INSTALLED.get_or_init(Default::default);
+
+    if installed.lock().unwrap().iter().any(|p| p == pkg) {
+        return true;
+    }
+
+    let found = R!("requireNamespace")
+        .ok()
+        .and_then(|f| f.as_function())
+        .and_then(|f| f.call(pairlist!(pkg, quietly = true)).ok())
+        .and_then(|res| res.as_bool())
+        .unwrap_or(false);
+
+    if found {
+        installed.lock().unwrap().push(pkg.to_string());
+    }
+
+    found
+}
+
+/// Returns an error if an R package is not installed
+pub fn require_package(pkg: &str) -> Result<()> {
+    if is_installed(pkg) {
+        return Ok(());
+    }
+
+    Err(Error::Other(format!(
+        "the `{pkg}` R package must be installed"
+    )))
+}
+
+/// The version of an installed R package from `utils::packageVersion()`
+pub fn package_version(pkg: &str) -> Option<String> {
+    if !is_installed(pkg) {
+        return None;
+    }
+
+    eval_string(&format!("as.character(utils::packageVersion('{pkg}'))"))
+        .ok()
+        .and_then(|res| res.as_str().map(str::to_string))
+}
+
+/// Checks if an installed R package is at least `version`
+pub fn package_version_at_least(pkg: &str, version: &str) -> bool {
+    package_version(pkg).is_some_and(|found| version_at_least(&found, version))
+}
+
+/// Compares R package versions such as `"0.6.0.9000"` component by component
+fn version_at_least(version: &str, min: &str) -> bool {
+    let parse = |v: &str| {
+        v.split(['.', '-'])
+            .map(|part| part.parse::<u64>().unwrap_or(0))
+            .collect::<Vec<_>>()
+    };
+    let (mut version, mut min) = (parse(version), parse(min));
+
+    let len = version.len().max(min.len());
+    version.resize(len, 0);
+    min.resize(len, 0);
+
+    version >= min
+}
+
+/// The first version of `{nanoarrow}` that reads view types
+pub const NANOARROW_VIEWS_VERSION: &str = "0.6.0";
+/// The first version of `{arrow}` that reads view types
+pub const ARROW_VIEWS_VERSION: &str = "16.0.0";
+
+/// Checks if the R package used by `BACKEND` can read view types such as `Utf8View`
+///
+/// The native backend creates `{nanoarrow}` objects so `{nanoarrow}` is
+/// checked if it is installed.
+pub fn supports_views() -> bool {
+    backend_supports_views(BACKEND, package_version)
+}
+
+// `version` returns the version of an R package, or `None` if it is not installed
+fn backend_supports_views(backend: Backend, version: impl Fn(&str) -> Option<String>) -> bool {
+    let at_least = |pkg: &str, min: &str| version(pkg).map(|found| version_at_least(&found, min));
+
+    match backend {
+        Backend::Nanoarrow => at_least("nanoarrow", NANOARROW_VIEWS_VERSION).unwrap_or(false),
+        Backend::Native => at_least("nanoarrow", NANOARROW_VIEWS_VERSION).unwrap_or(true),
+        Backend::Arrow => at_least("arrow", ARROW_VIEWS_VERSION).unwrap_or(false),
+    }
+}
+
+/// Looks up an R function such as `"nanoarrow::nanoarrow_allocate_schema"`
+///
+/// The package is checked with `require_package()` first so a missing package
+/// is an error rather than a panic.
+pub fn r_function(name: &str) -> Result<Function> {
+    if let Some((pkg, _)) = name.split_once("::") {
+        require_package(pkg)?;
+    }
+
+    eval_string(name)?
+        .as_function()
+        .ok_or_else(|| Error::Other(format!("`{name}()` must be a function")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn installed(
+        versions: &'static [(&'static str, &'static str)],
+    ) -> impl Fn(&str) -> Option<String> {
+        move |pkg| {
+            versions
+                .iter()
+                .find(|(name, _)| *name == pkg)
+                .map(|(_, version)| version.to_string())
+        }
+    }
+
+    #[test]
+    fn compares_package_versions() {
+        assert!(version_at_least("0.6.0", "0.6.0"));
+        assert!(version_at_least("0.6.0.9000", "0.6.0"));
+        assert!(version_at_least("0.10.0", "0.6.0"));
+        assert!(version_at_least("16.1", "16.0.0"));
+        assert!(!version_at_least("0.5.0.1", "0.6.0"));
+        assert!(!version_at_least("15.0.2-1", "16.0.0"));
+    }
+
+    #[test]
+    fn detects_view_support_of_each_backend() {
+        let old = installed(&[("nanoarrow", "0.5.0"), ("arrow", "15.0.1")]);
+        let new = installed(&[("nanoarrow", "0.6.0"), ("arrow", "17.0.0")]);
+        let none = installed(&[]);
+
+        assert!(!backend_supports_views(Backend::Nanoarrow, &old));
+        assert!(backend_supports_views(Backend::Nanoarrow, &new));
+        assert!(!backend_supports_views(Backend::Nanoarrow, &none));
+
+        assert!(!backend_supports_views(Backend::Arrow, &old));
+        assert!(backend_supports_views(Backend::Arrow, &new));
+
+        // the native backend only needs `{nanoarrow}` to read views if it is installed
+        assert!(!backend_supports_views(Backend::Native, &old));
+        assert!(backend_supports_views(Backend::Native, &new));
+        assert!(backend_supports_views(Backend::Native, &none));
+    }
+}
@@ -0,0 +1,223 @@
+//! Record batches with an explicit schema
+//!
+//! `IntoArrowRobj` for `Vec<RecordBatch>` takes the schema of the first batch,
+//! so an empty vector is exported as a stream without columns.
+//! `RecordBatches` carries its own `SchemaRef` and exports a stream with that
+//! schema even when there are no batches.
+//!
+//! |     arrow-rs struct      |                                 R object                                |
+//! | ------------------------ | ----------------------------------------------------------------------- |
+//! | `RecordBatches` (import) | `nanoarrow_array_stream`, `arrow::RecordBatchReader`, or `arrow::Table` |
+//! | `RecordBatches` (export) | `nanoarrow_array_stream`                                                |
+//!
+//! Every batch is checked against the schema by `RecordBatches::try_new()`,
+//! so a mismatch is reported in Rust rather than when R reads the stream.
+//!
+//! ```ignore
+//! fn query(sql: &str) -> Result<Robj> {
+//!     let (schema, batches) = run_query(sql);
+//!     RecordBatches::try_new(schema, batches)
+//!         .map_err(arrow_error)?
+//!         .into_arrow_robj()
+//! }
+//! ```
+use std::sync::Arc;
+
+use arrow::{
+    datatypes::{Schema, SchemaRef},
+    ffi_stream::ArrowArrayStreamReader,
+    record_batch::{RecordBatch, RecordBatchIterator, RecordBatchReader},
+};
+use extendr_api::prelude::*;
+
+use crate::{
+    from::{ErrArrowRobj, FromArrowRobj, ImportOptions},
+    rewrite::{restore_batch, restore_field},
+    to::{ExportOptions, IntoArrowRobj, ToArrowRobj},
+};
+
+/// A possibly empty `Vec<RecordBatch>` that all share one schema
+#[derive(Debug, Clone)]
+pub struct RecordBatches {
+    schema: SchemaRef,
+    batches: Vec<RecordBatch>,
+}
+
+impl RecordBatches {
+    /// Creates `RecordBatches` after checking every batch against `schema`
+    ///
+    /// Batches must have the same column names as `schema` and pass the checks
+    /// of `RecordBatch::try_new()`, i.e. the same column types and no nulls in
+    /// non-nullable fields. They are given `schema`, including its metadata.
+    /// Errors name the index of the first batch that does not match.
+    pub fn try_new(
+        schema: SchemaRef,
+        batches: Vec<RecordBatch>,
+    ) -> std::result::Result<Self, ErrArrowRobj> {
+        let batches = batches
+            .into_iter()
+            .enumerate()
+            .map(|(i, batch)| check_batch(&schema, &batch, i))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        Ok(Self { schema, batches })
+    }
+
+    /// Creates `RecordBatches` without any batch
+    pub fn empty(schema: SchemaRef) -> Self {
+        Self {
+            schema,
+            batches: vec![],
+        }
+    }
+
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    pub fn batches(&self) -> &[RecordBatch] {
+        &self.batches
+    }
+
+    pub fn num_rows(&self) -> usize {
+        self.batches.iter().map(|batch| batch.num_rows()).sum()
+    }
+
+    pub fn into_inner(self) -> (SchemaRef, Vec<RecordBatch>) {
+        (self.schema, self.batches)
+    }
+}
+
+// gives the `i`th batch `schema`; `RecordBatch::try_new()` checks the number,
+// types and nulls of the columns but not their names
+fn check_batch(
+    schema: &SchemaRef,
+    batch: &RecordBatch,
+    i: usize,
+) -> std::result::Result<RecordBatch, ErrArrowRobj> {
+    for (field, batch_field) in schema.fields().iter().zip(batch.schema_ref().fields()) {
+        if field.name() != batch_field.name() {
+            return Err(ErrArrowRobj::SchemaError(format!(
+                "batch {i} has column `{}` but the schema expects `{}`",
+                batch_field.name(),
+                field.name()
+            )));
+        }
+    }
+
+    RecordBatch::try_new(schema.clone(), batch.columns().to_vec())
+        .map_err(|e| ErrArrowRobj::SchemaError(format!("batch {i} does not match the schema: {e}")))
+}
+
+impl FromArrowRobj for RecordBatches {
+    fn from_arrow_robj(robj: &Robj) -> std::result::Result<Self, ErrArrowRobj> {
+        Self::from_arrow_robj_with(robj, &ImportOptions::default())
+    }
+
+    fn from_arrow_robj_with(
+        robj: &Robj,
+        options: &ImportOptions,
+    ) -> std::result::Result<Self, ErrArrowRobj> {
+        let reader = ArrowArrayStreamReader::from_arrow_robj_with(robj, options)?;
+        let schema = reader.schema();
+        let batches = reader.collect::<std::result::Result<Vec<_>, _>>()?;
+
+        if !options.restore_types {
+            return Ok(Self { schema, batches });
+        }
+
+        let fields = schema
+            .fields()
+            .iter()
+            .map(|field| restore_field(field))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let schema = Arc::new(Schema::new(fields).with_metadata(schema.metadata().clone()));
+        let batches = batches
+            .iter()
+            .map(restore_batch)
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        Self::try_new(schema, batches)
+    }
+}
+
+impl ToArrowRobj for RecordBatches {
+    fn to_arrow_robj(&self) -> Result<Robj> {
+        self.clone().into_arrow_robj()
+    }
+
+    fn to_arrow_robj_with(&self, options: &ExportOptions) -> Result<Robj> {
+        self.clone().into_arrow_robj_with(options)
+    }
+}
+
+impl IntoArrowRobj for RecordBatches {
+    fn into_arrow_robj(self) -> Result<Robj> {
+        self.into_arrow_robj_with(&ExportOptions::default())
+    }
+
+    fn into_arrow_robj_with(self, options: &ExportOptions) -> Result<Robj> {
+        if !self.batches.is_empty() {
+            return self.batches.into_arrow_robj_with(options);
+        }
+
+        let empty = RecordBatchIterator::new(
+            Vec::<std::result::Result<RecordBatch, ErrArrowRobj>>::new(),
+            self.schema,
+        );
+        empty.into_arrow_robj_with(options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::{
+        array::{ArrayRef, Int32Array, StringArray},
+        datatypes::{DataType, Field},
+    };
+
+    use super::*;
+
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![Field::new("x", DataType::Int32, false)]))
+    }
+
+    fn batch(name: &str, column: ArrayRef) -> RecordBatch {
+        RecordBatch::try_from_iter_with_nullable([(name, column, true)]).unwrap()
+    }
+
+    #[test]
+    fn gives_batches_the_schema() {
+        let batches = vec![batch("x", Arc::new(Int32Array::from(vec![1, 2])))];
+        let batches = RecordBatches::try_new(schema(), batches).unwrap();
+
+        assert_eq!(batches.num_rows(), 2);
+        assert_eq!(batches.batches()[0].schema(), schema());
+    }
+
+    #[test]
+    fn reports_the_batch_that_does_not_match() {
+        let ok = batch("x", Arc::new(Int32Array::from(vec![1])));
+
+        let renamed = vec![ok.clone(), batch("y", Arc::new(Int32Array::from(vec![1])))];
+        let error = RecordBatches::try_new(schema(), renamed).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Schema error: batch 1 has column `y` but the schema expects `x`"
+        );
+
+        let retyped = vec![
+            ok.clone(),
+            batch("x", Arc::new(StringArray::from(vec!["a"]))),
+        ];
+        let error = RecordBatches::try_new(schema(), retyped).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Schema error: batch 1 does not match the schema"));
+
+        let nulls = vec![ok, batch("x", Arc::new(Int32Array::from(vec![None])))];
+        let error = RecordBatches::try_new(schema(), nulls).unwrap_err();
+        assert!(error.to_string().contains("batch 1"));
+        assert!(error.to_string().contains("non-nullable"));
+    }
+}
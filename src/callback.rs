@@ -0,0 +1,63 @@
+//! Call an R function with each batch of a stream
+//!
+//! `for_each_batch()` reads an imported stream one batch at a time and passes
+//! each batch to an R function as a struct `nanoarrow_array`, so the whole
+//! stream is never materialised. This is useful for progress reporting or for
+//! R-side sinks such as `DBI::dbAppendTable()`.
+//!
+//! ```ignore
+//! #[extendr]
+//! fn append_stream(stream: Robj, f: Function) -> Result<List> {
+//!     let reader = ArrowArrayStreamReader::from_arrow_robj(&stream).map_err(arrow_error)?;
+//!     let res = for_each_batch(reader, &f, None, Ok)?;
+//!     Ok(List::from_values(res))
+//! }
+//! ```
+//!
+//! ```r
+//! append_stream(stream, function(batch) {
+//!   DBI::dbAppendTable(con, "tbl", as.data.frame(batch))
+//! })
+//! ```
+use arrow::{ffi_stream::ArrowArrayStreamReader, record_batch::RecordBatch};
+use extendr_api::prelude::*;
+
+use crate::{
+    from::ErrArrowRobj,
+    to::{arrow_error, ToArrowArrayRobj},
+};
+
+/// Calls `f` with every batch of `reader` and collects the results
+///
+/// Each batch is first passed through `preprocess`, use `Ok` to pass batches
+/// through unchanged. If `f` returns an object identical to `sentinel` the
+/// stream is not read any further and the sentinel is not included in the
+/// results.
+///
+/// Must be called on the R main thread.
+pub fn for_each_batch<P>(
+    reader: ArrowArrayStreamReader,
+    f: &Function,
+    sentinel: Option<&Robj>,
+    mut preprocess: P,
+) -> Result<Vec<Robj>>
+where
+    P: FnMut(RecordBatch) -> std::result::Result<RecordBatch, ErrArrowRobj>,
+{
+    let mut res = Vec::new();
+
+    for batch in reader {
+        let batch = batch.and_then(&mut preprocess).map_err(arrow_error)?;
+        let array = batch.to_arrow_array_robj()?;
+
+        let out = f.call(pairlist!(array))?;
+
+        if sentinel.is_some_and(|s| &out == s) {
+            break;
+        }
+
+        res.push(out);
+    }
+
+    Ok(res)
+}
@@ -0,0 +1,388 @@
+//! A column split into several arrays of the same type
+//!
+//! arrow-rs has no chunked array. `ChunkedArray` holds a `Field` and the
+//! `ArrayRef` chunks of a single column, e.g. an `arrow::ChunkedArray` or the
+//! only column of a stream, without concatenating them.
+//!
+//! |      arrow-rs struct       |                                             R object                                             |
+//! | -------------------------- | ------------------------------------------------------------------------------------------------ |
+//! | `ChunkedArray` (import)    | `arrow::ChunkedArray`, single column stream or `arrow::Table`, `nanoarrow_array`, `arrow::Array` |
+//! | `ChunkedArray` (export)    | single column `nanoarrow_array_stream`                                                           |
+//! | `ChunkedArray` (`r-arrow`) | `arrow::ChunkedArray` with `ToArrowR6Robj`                                                       |
+//!
+//! A stream is read with the name, nullability and metadata of its column. An
+//! array becomes a single chunk and an `arrow::ChunkedArray` a nameless field.
+//!
+//! ```ignore
+//! fn count_nulls(x: Robj) -> Result<i32> {
+//!     let chunked = ChunkedArray::from_arrow_robj(&x).map_err(arrow_error)?;
+//!     Ok(chunked.iter().map(|chunk| chunk.null_count() as i32).sum())
+//! }
+//! ```
+use std::sync::Arc;
+
+use arrow::{
+    array::{make_array, new_empty_array, Array, ArrayData, ArrayRef},
+    compute::concat,
+    datatypes::{DataType, Field, FieldRef, Schema},
+    record_batch::RecordBatch,
+};
+use extendr_api::prelude::*;
+
+use crate::{
+    batches::RecordBatches,
+    from::{r_error, ErrArrowRobj, FromArrowRobj, ImportOptions},
+    polars,
+    to::{arrow_error, ExportOptions, IntoArrowRobj, ToArrowRobj},
+};
+
+#[cfg(feature = "r-arrow")]
+use crate::{
+    arrow_r6::{IntoArrowR6Robj, ToArrowR6Robj},
+    backend::r_function,
+};
+
+/// A shared `ChunkedArray`
+pub type ChunkedArrayRef = Arc<ChunkedArray>;
+
+/// The chunks of a single column and its `Field`
+#[derive(Debug, Clone)]
+pub struct ChunkedArray {
+    field: FieldRef,
+    chunks: Vec<ArrayRef>,
+}
+
+impl ChunkedArray {
+    /// Creates a `ChunkedArray` after checking that every chunk has the type of `field`
+    pub fn try_new(
+        field: FieldRef,
+        chunks: Vec<ArrayRef>,
+    ) -> std::result::Result<Self, ErrArrowRobj> {
+        if let Some(chunk) = chunks
+            .iter()
+            .find(|chunk| chunk.data_type() != field.data_type())
+        {
+            return Err(ErrArrowRobj::InvalidArgumentError(format!(
+                "chunks must be of type {}, found {}",
+                field.data_type(),
+                chunk.data_type()
+            )));
+        }
+
+        Ok(Self { field, chunks })
+    }
+
+    /// Creates a `ChunkedArray` with a nameless, nullable field from at least one chunk
+    pub fn from_chunks(chunks: Vec<ArrayRef>) -> std::result::Result<Self, ErrArrowRobj> {
+        let Some(first) = chunks.first() else {
+            return Err(ErrArrowRobj::InvalidArgumentError(
+                "cannot infer the type of a `ChunkedArray` without chunks, use `ChunkedArray::empty()`"
+                    .into(),
+            ));
+        };
+
+        let field = Arc::new(Field::new("", first.data_type().clone(), true));
+        Self::try_new(field, chunks)
+    }
+
+    /// Creates a `ChunkedArray` without any chunk
+    pub fn empty(field: FieldRef) -> Self {
+        Self {
+            field,
+            chunks: vec![],
+        }
+    }
+
+    pub fn field(&self) -> &FieldRef {
+        &self.field
+    }
+
+    pub fn data_type(&self) -> &DataType {
+        self.field.data_type()
+    }
+
+    pub fn chunks(&self) -> &[ArrayRef] {
+        &self.chunks
+    }
+
+    pub fn num_chunks(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// The total number of elements
+    pub fn len(&self) -> usize {
+        self.chunks.iter().map(|chunk| chunk.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn null_count(&self) -> usize {
+        self.chunks.iter().map(|chunk| chunk.null_count()).sum()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, ArrayRef> {
+        self.chunks.iter()
+    }
+
+    /// Concatenates every chunk into a single array
+    pub fn concat(&self) -> std::result::Result<ArrayRef, ErrArrowRobj> {
+        match self.chunks.as_slice() {
+            [] => Ok(new_empty_array(self.data_type())),
+            [chunk] => Ok(chunk.clone()),
+            chunks => {
+                let chunks = chunks
+                    .iter()
+                    .map(|chunk| chunk.as_ref())
+                    .collect::<Vec<_>>();
+                concat(&chunks)
+            }
+        }
+    }
+
+    /// Appends the chunks of `other`, which must have the same type
+    pub fn append(&mut self, other: ChunkedArray) -> std::result::Result<(), ErrArrowRobj> {
+        if other.data_type() != self.data_type() {
+            return Err(ErrArrowRobj::InvalidArgumentError(format!(
+                "cannot append a `ChunkedArray` of type {} to one of type {}",
+                other.data_type(),
+                self.data_type()
+            )));
+        }
+
+        self.chunks.extend(other.chunks);
+        Ok(())
+    }
+
+    /// One single column batch per chunk
+    pub fn to_record_batches(&self) -> std::result::Result<RecordBatches, ErrArrowRobj> {
+        let schema = Arc::new(Schema::new(vec![self.field.clone()]));
+        let batches = self
+            .chunks
+            .iter()
+            .map(|chunk| RecordBatch::try_new(schema.clone(), vec![chunk.clone()]))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        RecordBatches::try_new(schema, batches)
+    }
+}
+
+impl From<ArrayRef> for ChunkedArray {
+    fn from(array: ArrayRef) -> Self {
+        let field = Arc::new(Field::new("", array.data_type().clone(), true));
+        Self {
+            field,
+            chunks: vec![array],
+        }
+    }
+}
+
+impl IntoIterator for ChunkedArray {
+    type Item = ArrayRef;
+    type IntoIter = std::vec::IntoIter<ArrayRef>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.chunks.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a ChunkedArray {
+    type Item = &'a ArrayRef;
+    type IntoIter = std::slice::Iter<'a, ArrayRef>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.chunks.iter()
+    }
+}
+
+/// Imports the chunks of an `arrow::ChunkedArray` from its `$chunks` and `$type`
+fn chunked_from_r6(
+    robj: &Robj,
+    options: &ImportOptions,
+) -> std::result::Result<ChunkedArray, ErrArrowRobj> {
+    let data_type =
+        DataType::from_arrow_robj_with(&robj.dollar("type").map_err(r_error)?, options)?;
+    let chunks = robj
+        .dollar("chunks")
+        .map_err(r_error)?
+        .as_list()
+        .ok_or_else(|| ErrArrowRobj::ParseError("`$chunks` must be a list".into()))?
+        .values()
+        .map(|chunk| ArrayData::from_arrow_robj_with(&chunk, options).map(make_array))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+
+    ChunkedArray::try_new(Arc::new(Field::new("", data_type, true)), chunks)
+}
+
+impl FromArrowRobj for ChunkedArray {
+    fn from_arrow_robj(robj: &Robj) -> std::result::Result<Self, ErrArrowRobj> {
+        Self::from_arrow_robj_with(robj, &ImportOptions::default())
+    }
+
+    fn from_arrow_robj_with(
+        robj: &Robj,
+        options: &ImportOptions,
+    ) -> std::result::Result<Self, ErrArrowRobj> {
+        if robj.inherits("ChunkedArray") {
+            return chunked_from_r6(robj, options);
+        }
+
+        let is_stream = ["nanoarrow_array_stream", "RecordBatchReader", "Table"]
+            .iter()
+            .any(|cls| robj.inherits(cls));
+
+        if !is_stream && !polars::is_polars(robj) {
+            let data = ArrayData::from_arrow_robj_with(robj, options)?;
+            return Ok(ChunkedArray::from(make_array(data)));
+        }
+
+        let (schema, batches) = RecordBatches::from_arrow_robj_with(robj, options)?.into_inner();
+
+        let [field] = schema.fields().as_ref() else {
+            return Err(ErrArrowRobj::ParseError(format!(
+                "a `ChunkedArray` must be read from a single column stream, found {} columns",
+                schema.fields().len()
+            )));
+        };
+
+        let chunks = batches
+            .iter()
+            .map(|batch| batch.column(0).clone())
+            .collect::<Vec<_>>();
+
+        ChunkedArray::try_new(field.clone(), chunks)
+    }
+}
+
+/// Exported as a single column `nanoarrow_array_stream` with one batch per chunk
+impl ToArrowRobj for ChunkedArray {
+    fn to_arrow_robj(&self) -> Result<Robj> {
+        self.to_arrow_robj_with(&ExportOptions::default())
+    }
+
+    fn to_arrow_robj_with(&self, options: &ExportOptions) -> Result<Robj> {
+        self.to_record_batches()
+            .map_err(arrow_error)?
+            .into_arrow_robj_with(options)
+    }
+}
+
+impl IntoArrowRobj for ChunkedArray {
+    fn into_arrow_robj(self) -> Result<Robj> {
+        self.to_arrow_robj()
+    }
+
+    fn into_arrow_robj_with(self, options: &ExportOptions) -> Result<Robj> {
+        self.to_arrow_robj_with(options)
+    }
+}
+
+/// Exported as an `arrow::ChunkedArray`, the field name is dropped
+#[cfg(feature = "r-arrow")]
+impl ToArrowR6Robj for ChunkedArray {
+    fn to_arrow_r6_robj(&self) -> Result<Robj> {
+        let mut args = self
+            .chunks
+            .iter()
+            .map(|chunk| Ok(("", chunk.to_data().to_arrow_r6_robj()?)))
+            .collect::<Result<Vec<_>>>()?;
+        args.push(("type", self.data_type().to_arrow_r6_robj()?));
+
+        r_function("arrow::ChunkedArray$create")?.call(Pairlist::from_pairs(args))
+    }
+}
+
+#[cfg(feature = "r-arrow")]
+impl IntoArrowR6Robj for ChunkedArray {
+    fn into_arrow_r6_robj(self) -> Result<Robj> {
+        self.to_arrow_r6_robj()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::{
+        array::{AsArray, Int32Array, StringArray},
+        datatypes::Int32Type,
+    };
+
+    use super::*;
+
+    fn ints(values: Vec<i32>) -> ArrayRef {
+        Arc::new(Int32Array::from(values))
+    }
+
+    #[test]
+    fn rejects_chunks_of_another_type() {
+        let field = Arc::new(Field::new("x", DataType::Int32, true));
+        let chunks = vec![ints(vec![1]), Arc::new(StringArray::from(vec!["a"]))];
+
+        let error = ChunkedArray::try_new(field, chunks).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("chunks must be of type Int32, found Utf8"));
+    }
+
+    #[test]
+    fn handles_empty_chunk_lists() {
+        assert!(ChunkedArray::from_chunks(vec![]).is_err());
+
+        let field = Arc::new(Field::new("x", DataType::Utf8, true));
+        let chunked = ChunkedArray::try_new(field.clone(), vec![]).unwrap();
+        assert_eq!(chunked.num_chunks(), 0);
+        assert!(chunked.is_empty());
+
+        let array = chunked.concat().unwrap();
+        assert_eq!(array.data_type(), &DataType::Utf8);
+        assert!(array.is_empty());
+
+        let batches = ChunkedArray::empty(field).to_record_batches().unwrap();
+        assert!(batches.batches().is_empty());
+        assert_eq!(batches.schema().field(0).name(), "x");
+    }
+
+    #[test]
+    fn concatenates_chunks() {
+        let chunked =
+            ChunkedArray::from_chunks(vec![ints(vec![1, 2]), ints(vec![]), ints(vec![3])]).unwrap();
+        assert_eq!(chunked.len(), 3);
+
+        let array = chunked.concat().unwrap();
+        assert_eq!(
+            array.as_primitive::<Int32Type>(),
+            &Int32Array::from(vec![1, 2, 3])
+        );
+    }
+
+    #[test]
+    fn appends_chunks_of_the_same_type() {
+        let mut chunked = ChunkedArray::from_chunks(vec![ints(vec![1])]).unwrap();
+        chunked
+            .append(ChunkedArray::from_chunks(vec![ints(vec![2, 3])]).unwrap())
+            .unwrap();
+        assert_eq!(chunked.num_chunks(), 2);
+        assert_eq!(chunked.len(), 3);
+
+        let strings = Arc::new(StringArray::from(vec!["a"])) as ArrayRef;
+        let error = chunked
+            .append(ChunkedArray::from_chunks(vec![strings]).unwrap())
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("cannot append a `ChunkedArray` of type Utf8 to one of type Int32"));
+        assert_eq!(chunked.num_chunks(), 2);
+    }
+
+    #[test]
+    fn converts_to_one_batch_per_chunk() {
+        let field = Arc::new(Field::new("x", DataType::Int32, false));
+        let chunked = ChunkedArray::try_new(field, vec![ints(vec![1, 2]), ints(vec![3])]).unwrap();
+
+        let batches = chunked.to_record_batches().unwrap();
+        assert_eq!(batches.batches().len(), 2);
+        assert_eq!(batches.num_rows(), 3);
+        assert_eq!(batches.schema().field(0), chunked.field().as_ref());
+        assert_eq!(batches.batches()[1].column(0), &chunked.chunks()[1]);
+    }
+}
@@ -0,0 +1,416 @@
+//! Call arrow-rs compute kernels with R objects
+//!
+//! `call_kernel()` imports its arguments with `FromArrowRobj`, dispatches to
+//! the named kernel in `arrow::compute` and exports the result with
+//! `ToArrowRobj`, i.e. as a `nanoarrow_array`, or an `arrow::Array` with the
+//! `r-arrow` backend. A single extendr function is enough to give an R package
+//! access to every kernel in [`KERNELS`].
+//!
+//! ```ignore
+//! #[extendr]
+//! fn arrow_kernel(name: &str, args: List) -> Result<Robj> {
+//!     let args = args.values().collect::<Vec<_>>();
+//!     call_kernel(name, &args)
+//! }
+//! ```
+//!
+//! ```r
+//! x <- nanoarrow::as_nanoarrow_array(c(3L, 1L, 2L))
+//! idx <- arrow_kernel("sort_to_indices", list(x))
+//! arrow_kernel("take", list(x, idx))
+//! arrow_kernel("add", list(x, 10L))
+//! ```
+//!
+//! |                   kernel                  |         arguments         |
+//! | ----------------------------------------- | ------------------------- |
+//! | `filter`                                  | array, boolean predicate  |
+//! | `take`                                    | array, integer indices    |
+//! | `sort_to_indices`                         | array                     |
+//! | `cast`                                    | array, `nanoarrow_schema` |
+//! | `concat`                                  | one or more arrays        |
+//! | `add`, `sub`, `mul`, `div`, `rem`         | two arrays or scalars     |
+//! | `eq`, `neq`, `lt`, `lt_eq`, `gt`, `gt_eq` | two arrays or scalars     |
+//! | `and`, `or`                               | two boolean arrays        |
+//! | `not`, `is_null`, `is_not_null`, `length` | one array                 |
+//!
+//! Arguments of the arithmetic and comparison kernels that have length 1, and
+//! `arrow::Scalar`s, are treated as scalars and recycled, as in R. A scalar is
+//! cast to the type of the other argument when that keeps its value, so
+//! `add(x, 10)` works when `x` is an integer array. Otherwise numeric
+//! arguments are both cast to `Float64`, so `add(x, 0.5)` returns doubles as
+//! in R, and other arguments are rejected rather than losing values.
+//!
+//! **Requires the R package of the selected backend**, see the `backend` module.
+use arrow::{
+    array::{make_array, Array, ArrayData, ArrayRef, BooleanArray, Datum, Scalar},
+    compute::{
+        self, cast, cast_with_options,
+        kernels::{cmp, numeric},
+        CastOptions,
+    },
+    datatypes::DataType,
+    error::ArrowError,
+};
+use extendr_api::prelude::*;
+use std::sync::Arc;
+
+use crate::{
+    from::{ErrArrowRobj, FromArrowRobj},
+    to::{arrow_error, ToArrowRobj},
+};
+
+/// Names of the kernels supported by `call_kernel()`
+pub const KERNELS: [&str; 22] = [
+    "filter",
+    "take",
+    "sort_to_indices",
+    "cast",
+    "concat",
+    "add",
+    "sub",
+    "mul",
+    "div",
+    "rem",
+    "eq",
+    "neq",
+    "lt",
+    "lt_eq",
+    "gt",
+    "gt_eq",
+    "and",
+    "or",
+    "not",
+    "is_null",
+    "is_not_null",
+    "length",
+];
+
+/// Calls the compute kernel `name` with `args`
+///
+/// Returns the result as a `nanoarrow_array`, or an `arrow::Array` with the
+/// `r-arrow` backend. Unknown kernels, the wrong number of arguments and
+/// arguments of the wrong type are an error.
+pub fn call_kernel(name: &str, args: &[Robj]) -> Result<Robj> {
+    kernel(name, args)
+        .map_err(arrow_error)?
+        .to_data()
+        .to_arrow_robj()
+}
+
+/// Dispatches to the kernel `name` without converting the result to an `Robj`
+pub fn kernel(name: &str, args: &[Robj]) -> std::result::Result<ArrayRef, ErrArrowRobj> {
+    let res: ArrayRef = match name {
+        "filter" => {
+            let [values, predicate] = arity::<2>(name, args)?;
+            let predicate = as_boolean(import_array(predicate)?, "predicate")?;
+            compute::filter(&import_array(values)?, &predicate)?
+        }
+        "take" => {
+            let [values, indices] = arity::<2>(name, args)?;
+            compute::take(&import_array(values)?, &import_array(indices)?, None)?
+        }
+        "sort_to_indices" => {
+            let [values] = arity::<1>(name, args)?;
+            Arc::new(compute::sort_to_indices(
+                &import_array(values)?,
+                None,
+                None,
+            )?)
+        }
+        "cast" => {
+            let [values, to_type] = arity::<2>(name, args)?;
+            cast(&import_array(values)?, &DataType::from_arrow_robj(to_type)?)?
+        }
+        "concat" => {
+            if args.is_empty() {
+                return Err(arity_error(name, "at least 1", 0));
+            }
+            let arrays = args
+                .iter()
+                .map(import_array)
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+            let arrays = arrays.iter().map(|a| a.as_ref()).collect::<Vec<_>>();
+            compute::concat(&arrays)?
+        }
+        "add" | "sub" | "mul" | "div" | "rem" => {
+            let [lhs, rhs] = arity::<2>(name, args)?;
+            let (lhs, rhs) = import_operands(lhs, rhs)?;
+            let f = match name {
+                "add" => numeric::add,
+                "sub" => numeric::sub,
+                "mul" => numeric::mul,
+                "div" => numeric::div,
+                _ => numeric::rem,
+            };
+            f(lhs.as_datum(), rhs.as_datum())?
+        }
+        "eq" | "neq" | "lt" | "lt_eq" | "gt" | "gt_eq" => {
+            let [lhs, rhs] = arity::<2>(name, args)?;
+            let (lhs, rhs) = import_operands(lhs, rhs)?;
+            let f = match name {
+                "eq" => cmp::eq,
+                "neq" => cmp::neq,
+                "lt" => cmp::lt,
+                "lt_eq" => cmp::lt_eq,
+                "gt" => cmp::gt,
+                _ => cmp::gt_eq,
+            };
+            Arc::new(f(lhs.as_datum(), rhs.as_datum())?)
+        }
+        "and" | "or" => {
+            let [lhs, rhs] = arity::<2>(name, args)?;
+            let lhs = as_boolean(import_array(lhs)?, "lhs")?;
+            let rhs = as_boolean(import_array(rhs)?, "rhs")?;
+            let f = match name {
+                "and" => compute::and,
+                _ => compute::or,
+            };
+            Arc::new(f(&lhs, &rhs)?)
+        }
+        "not" => {
+            let [values] = arity::<1>(name, args)?;
+            Arc::new(compute::not(&as_boolean(import_array(values)?, "x")?)?)
+        }
+        "is_null" | "is_not_null" => {
+            let [values] = arity::<1>(name, args)?;
+            let values = import_array(values)?;
+            let f = match name {
+                "is_null" => compute::is_null,
+                _ => compute::is_not_null,
+            };
+            Arc::new(f(&values)?)
+        }
+        "length" => {
+            let [values] = arity::<1>(name, args)?;
+            compute::kernels::length::length(&import_array(values)?)?
+        }
+        _ => {
+            return Err(ErrArrowRobj::InvalidArgumentError(format!(
+                "unknown kernel `{name}`, expected one of {}",
+                KERNELS.join(", ")
+            )))
+        }
+    };
+
+    Ok(res)
+}
+
+/// An argument of an arithmetic or comparison kernel
+enum DatumArg {
+    Array(ArrayRef),
+    Scalar(Scalar<ArrayRef>),
+}
+
+impl DatumArg {
+    fn as_datum(&self) -> &dyn Datum {
+        match self {
+            DatumArg::Array(array) => array,
+            DatumArg::Scalar(scalar) => scalar,
+        }
+    }
+
+    fn data_type(&self) -> &DataType {
+        match self {
+            DatumArg::Array(array) => array.data_type(),
+            DatumArg::Scalar(scalar) => scalar.get().0.data_type(),
+        }
+    }
+
+    /// Casts a scalar to `to_type` if that keeps its value, arrays are left as they are
+    ///
+    /// Returns `None` when the value would be lost, e.g. the fraction of `0.5`
+    /// cast to an integer or a number that overflows.
+    fn cast_scalar(&self, to_type: &DataType) -> Option<Self> {
+        let scalar = match self {
+            DatumArg::Array(array) => return Some(DatumArg::Array(array.clone())),
+            DatumArg::Scalar(scalar) => scalar,
+        };
+
+        let (array, _) = scalar.get();
+        if array.data_type() == to_type {
+            return Some(DatumArg::Scalar(scalar.clone()));
+        }
+
+        let options = CastOptions {
+            safe: false,
+            ..Default::default()
+        };
+        let cast = cast_with_options(array, to_type, &options).ok()?;
+        let back = cast_with_options(&cast, array.data_type(), &options).ok()?;
+
+        // `ArrayData` compares values, not buffers
+        if back.to_data() != array.to_data() {
+            return None;
+        }
+
+        Some(DatumArg::Scalar(Scalar::new(cast)))
+    }
+
+    /// Casts an array or scalar to `to_type`
+    fn cast(self, to_type: &DataType) -> std::result::Result<Self, ErrArrowRobj> {
+        match self {
+            DatumArg::Array(array) => Ok(DatumArg::Array(cast(&array, to_type)?)),
+            DatumArg::Scalar(scalar) => {
+                let array = cast(scalar.get().0, to_type)?;
+                Ok(DatumArg::Scalar(Scalar::new(array)))
+            }
+        }
+    }
+}
+
+fn import_array(robj: &Robj) -> std::result::Result<ArrayRef, ErrArrowRobj> {
+    ArrayData::from_arrow_robj(robj).map(make_array)
+}
+
+/// `arrow::Scalar`s and length-1 arguments become a `Scalar`
+fn import_datum(robj: &Robj) -> std::result::Result<DatumArg, ErrArrowRobj> {
+    if robj.inherits("Scalar") {
+        return Scalar::<ArrayRef>::from_arrow_robj(robj).map(DatumArg::Scalar);
+    }
+
+    let array = import_array(robj)?;
+
+    if array.len() == 1 {
+        return Ok(DatumArg::Scalar(Scalar::new(array)));
+    }
+
+    Ok(DatumArg::Array(array))
+}
+
+/// Imports both operands and casts a scalar to the type of the other operand
+fn import_operands(
+    lhs: &Robj,
+    rhs: &Robj,
+) -> std::result::Result<(DatumArg, DatumArg), ErrArrowRobj> {
+    unify_operands(import_datum(lhs)?, import_datum(rhs)?)
+}
+
+fn unify_operands(
+    lhs: DatumArg,
+    rhs: DatumArg,
+) -> std::result::Result<(DatumArg, DatumArg), ErrArrowRobj> {
+    let (lhs_type, rhs_type) = (lhs.data_type().clone(), rhs.data_type().clone());
+
+    match (&lhs, &rhs) {
+        (DatumArg::Array(_), DatumArg::Scalar(_)) => {
+            if let Some(rhs) = rhs.cast_scalar(&lhs_type) {
+                return Ok((lhs, rhs));
+            }
+        }
+        (DatumArg::Scalar(_), _) => {
+            if let Some(lhs) = lhs.cast_scalar(&rhs_type) {
+                return Ok((lhs, rhs));
+            }
+        }
+        _ => return Ok((lhs, rhs)),
+    }
+
+    // as in R, integers and doubles are combined as doubles
+    if lhs_type.is_numeric() && rhs_type.is_numeric() {
+        return Ok((lhs.cast(&DataType::Float64)?, rhs.cast(&DataType::Float64)?));
+    }
+
+    Err(ErrArrowRobj::InvalidArgumentError(format!(
+        "cannot combine {lhs_type} and {rhs_type} without losing values"
+    )))
+}
+
+fn as_boolean(array: ArrayRef, arg: &str) -> std::result::Result<BooleanArray, ErrArrowRobj> {
+    array
+        .as_any()
+        .downcast_ref::<BooleanArray>()
+        .cloned()
+        .ok_or_else(|| {
+            ArrowError::InvalidArgumentError(format!(
+                "`{arg}` must be a boolean array, found {}",
+                array.data_type()
+            ))
+        })
+}
+
+/// Checks that a kernel received exactly `N` arguments
+fn arity<'a, const N: usize>(
+    name: &str,
+    args: &'a [Robj],
+) -> std::result::Result<&'a [Robj; N], ErrArrowRobj> {
+    args.try_into()
+        .map_err(|_| arity_error(name, &N.to_string(), args.len()))
+}
+
+fn arity_error(name: &str, expected: &str, found: usize) -> ErrArrowRobj {
+    ArrowError::InvalidArgumentError(format!(
+        "kernel `{name}` takes {expected} argument(s), found {found}"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::array::{AsArray, Float64Array, Int32Array, StringArray};
+    use arrow::datatypes::{Float64Type, Int32Type};
+
+    use super::*;
+
+    #[test]
+    fn casts_a_scalar_to_the_type_of_the_array() {
+        let lhs = DatumArg::Array(Arc::new(Int32Array::from(vec![1, 2, 3])));
+        let rhs = DatumArg::Scalar(Scalar::new(Arc::new(Float64Array::from(vec![10.0]))));
+
+        let (lhs, rhs) = unify_operands(lhs, rhs).unwrap();
+        assert_eq!(rhs.data_type(), &DataType::Int32);
+
+        let res = numeric::add(lhs.as_datum(), rhs.as_datum()).unwrap();
+        assert_eq!(
+            res.as_primitive::<Int32Type>().values().to_vec(),
+            vec![11, 12, 13]
+        );
+    }
+
+    #[test]
+    fn casts_a_leading_scalar() {
+        let lhs = DatumArg::Scalar(Scalar::new(Arc::new(Int32Array::from(vec![1]))));
+        let rhs = DatumArg::Array(Arc::new(Float64Array::from(vec![0.5, 1.5])));
+
+        let (lhs, rhs) = unify_operands(lhs, rhs).unwrap();
+        assert_eq!(lhs.data_type(), &DataType::Float64);
+
+        let res = cmp::lt(lhs.as_datum(), rhs.as_datum()).unwrap();
+        assert_eq!(res, BooleanArray::from(vec![false, true]));
+    }
+
+    #[test]
+    fn promotes_integers_to_doubles_for_fractional_scalars() {
+        let lhs = DatumArg::Array(Arc::new(Int32Array::from(vec![1, 2])));
+        let rhs = DatumArg::Scalar(Scalar::new(Arc::new(Float64Array::from(vec![0.5]))));
+
+        let (lhs, rhs) = unify_operands(lhs, rhs).unwrap();
+        assert_eq!(lhs.data_type(), &DataType::Float64);
+        assert_eq!(rhs.data_type(), &DataType::Float64);
+
+        let res = numeric::add(lhs.as_datum(), rhs.as_datum()).unwrap();
+        assert_eq!(
+            res.as_primitive::<Float64Type>().values().to_vec(),
+            vec![1.5, 2.5]
+        );
+    }
+
+    #[test]
+    fn promotes_integers_to_doubles_when_the_scalar_overflows() {
+        let lhs = DatumArg::Array(Arc::new(Int32Array::from(vec![1])));
+        let rhs = DatumArg::Scalar(Scalar::new(Arc::new(Float64Array::from(vec![1e12]))));
+
+        let (lhs, rhs) = unify_operands(lhs, rhs).unwrap();
+        let res = numeric::add(lhs.as_datum(), rhs.as_datum()).unwrap();
+        assert_eq!(res.as_primitive::<Float64Type>().value(0), 1e12 + 1.0);
+    }
+
+    #[test]
+    fn errors_when_a_non_numeric_scalar_loses_its_value() {
+        let lhs = DatumArg::Array(Arc::new(Int32Array::from(vec![1, 2])));
+        let rhs = DatumArg::Scalar(Scalar::new(Arc::new(StringArray::from(vec!["a"]))));
+
+        let Err(error) = unify_operands(lhs, rhs) else {
+            panic!("expected an error");
+        };
+        assert!(error.to_string().contains("without losing values"));
+    }
+}
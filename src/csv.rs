@@ -0,0 +1,191 @@
+//! Read and write CSV files as R array streams
+//!
+//! Requires the `csv` feature.
+//!
+//! ```ignore
+//! #[extendr]
+//! fn read_csv_stream(path: &str, schema: Nullable<Robj>) -> Result<Robj> {
+//!     let schema = schema.into_option();
+//!     read_csv(path, schema.as_ref(), &CsvReadOptions::default())
+//! }
+//! ```
+//!
+//! The file is not read up front. The returned `nanoarrow_array_stream`
+//! parses one batch at a time as R pulls from it. When no schema is provided
+//! it is inferred from the first `max_infer_records` rows.
+use std::{
+    fs::File,
+    io::{BufWriter, Seek, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use arrow::{
+    csv::{reader::Format, ReaderBuilder, WriterBuilder},
+    datatypes::Schema,
+    ffi_stream::ArrowArrayStreamReader,
+    record_batch::RecordBatchReader,
+};
+use extendr_api::prelude::*;
+
+use crate::{
+    from::{ErrArrowRobj, FromArrowRobj},
+    to::{arrow_error, IntoArrowRobj},
+};
+
+/// Options used when reading a CSV file
+#[derive(Debug, Clone)]
+pub struct CsvReadOptions {
+    pub has_header: bool,
+    pub delimiter: u8,
+    pub batch_size: usize,
+    /// Number of rows used for schema inference. `None` reads the whole file.
+    pub max_infer_records: Option<usize>,
+}
+
+impl Default for CsvReadOptions {
+    fn default() -> Self {
+        Self {
+            has_header: true,
+            delimiter: b',',
+            batch_size: 1024,
+            max_infer_records: Some(1000),
+        }
+    }
+}
+
+/// Options used when writing a CSV file
+#[derive(Debug, Clone)]
+pub struct CsvWriteOptions {
+    pub has_header: bool,
+    pub delimiter: u8,
+}
+
+impl Default for CsvWriteOptions {
+    fn default() -> Self {
+        Self {
+            has_header: true,
+            delimiter: b',',
+        }
+    }
+}
+
+/// Create a `RecordBatchReader` over a CSV file
+///
+/// `schema` can be any `Robj` supported by `Schema::from_arrow_robj()`. If it
+/// is `None` the schema is inferred from the file.
+pub fn csv_reader<P: AsRef<Path>>(
+    path: P,
+    schema: Option<&Robj>,
+    options: &CsvReadOptions,
+) -> std::result::Result<Box<dyn RecordBatchReader + Send>, ErrArrowRobj> {
+    let mut file = File::open(path)?;
+
+    let schema = match schema {
+        Some(robj) => Schema::from_arrow_robj(robj)?,
+        None => {
+            let format = Format::default()
+                .with_header(options.has_header)
+                .with_delimiter(options.delimiter);
+            let (schema, _) = format.infer_schema(&mut file, options.max_infer_records)?;
+            file.rewind()?;
+            schema
+        }
+    };
+
+    let reader = ReaderBuilder::new(Arc::new(schema))
+        .with_header(options.has_header)
+        .with_delimiter(options.delimiter)
+        .with_batch_size(options.batch_size)
+        .build(file)?;
+
+    Ok(Box::new(reader))
+}
+
+/// Read a CSV file into a lazy `nanoarrow_array_stream`
+pub fn read_csv<P: AsRef<Path>>(
+    path: P,
+    schema: Option<&Robj>,
+    options: &CsvReadOptions,
+) -> Result<Robj> {
+    csv_reader(path, schema, options)
+        .map_err(arrow_error)?
+        .into_arrow_robj()
+}
+
+/// Write every batch of an imported stream to a CSV file
+pub fn write_csv<P: AsRef<Path>>(
+    reader: ArrowArrayStreamReader,
+    path: P,
+    options: &CsvWriteOptions,
+) -> std::result::Result<(), ErrArrowRobj> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut writer = WriterBuilder::new()
+        .with_header(options.has_header)
+        .with_delimiter(options.delimiter)
+        .build(file);
+
+    for batch in reader {
+        writer.write(&batch?)?;
+    }
+
+    writer.into_inner().flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::{datatypes::DataType, ffi_stream::FFI_ArrowArrayStream};
+
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("arrow-extendr-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn infers_schema_and_reads_lazily() {
+        let path = temp_path("infer.csv");
+        std::fs::write(&path, "a,b\n1,x\n2,y\n3,z\n").unwrap();
+
+        let options = CsvReadOptions {
+            batch_size: 2,
+            ..Default::default()
+        };
+        let reader = csv_reader(&path, None, &options).unwrap();
+
+        let schema = reader.schema();
+        assert_eq!(schema.field(0).data_type(), &DataType::Int64);
+        assert_eq!(schema.field(1).data_type(), &DataType::Utf8);
+
+        let rows = reader
+            .map(|batch| batch.unwrap().num_rows())
+            .collect::<Vec<_>>();
+        assert_eq!(rows, vec![2, 1]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn writes_every_batch_of_a_stream() {
+        let input = temp_path("input.csv");
+        let output = temp_path("output.csv");
+        std::fs::write(&input, "a,b\n1,x\n2,y\n3,z\n").unwrap();
+
+        let options = CsvReadOptions {
+            batch_size: 1,
+            ..Default::default()
+        };
+        let reader = csv_reader(&input, None, &options).unwrap();
+        let stream = ArrowArrayStreamReader::try_new(FFI_ArrowArrayStream::new(reader)).unwrap();
+
+        write_csv(stream, &output, &CsvWriteOptions::default()).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "a,b\n1,x\n2,y\n3,z\n"
+        );
+
+        std::fs::remove_file(input).unwrap();
+        std::fs::remove_file(output).unwrap();
+    }
+}
@@ -0,0 +1,183 @@
+//! Query R data with SQL using DataFusion
+//!
+//! Requires the `datafusion` feature, which enables `async`.
+//!
+//! A stream imported from R calls back into R for every batch, so DataFusion,
+//! which reads its tables from worker threads, cannot pull from it directly.
+//! `register_robj()` drains the stream on the calling (R) thread into a
+//! `MemTable`. The result of a query is not collected: `sql_stream()` returns
+//! a `BlockingStreamReader` that runs the plan one batch at a time as R pulls
+//! from the exported `nanoarrow_array_stream`.
+//!
+//! |       function       |                                     input                                      |
+//! | -------------------- | ------------------------------------------------------------------------------ |
+//! | `register_robj()`    | `nanoarrow_array_stream`, `arrow::RecordBatchReader`, `arrow::Table`, and more |
+//! | `register_reader()`  | `ArrowArrayStreamReader`                                                       |
+//! | `register_batches()` | `Vec<RecordBatch>` or `RecordBatches`                                          |
+//!
+//! ```ignore
+//! #[extendr]
+//! fn query(x: Robj, sql: &str) -> Result<Robj> {
+//!     let runtime = RUNTIME.get_or_init(|| tokio::runtime::Runtime::new().unwrap());
+//!     let ctx = SessionContext::new();
+//!
+//!     register_robj(&ctx, "tbl", &x).map_err(arrow_error)?;
+//!     sql_to_robj(&ctx, sql, runtime.handle())
+//! }
+//! ```
+//!
+//! The runtime must be multi-threaded, which is checked, and outlive the
+//! returned stream, see `BlockingStreamReader`.
+use std::sync::Arc;
+
+use ::datafusion::{datasource::MemTable, error::DataFusionError, prelude::SessionContext};
+use arrow::{
+    datatypes::SchemaRef,
+    ffi_stream::ArrowArrayStreamReader,
+    record_batch::{RecordBatch, RecordBatchReader},
+};
+use extendr_api::prelude::*;
+use futures::{Stream, StreamExt};
+use tokio::runtime::Handle;
+
+use crate::{
+    async_stream::{check_multi_thread, BlockingStreamReader},
+    batches::RecordBatches,
+    from::{ErrArrowRobj, FromArrowRobj},
+    to::{arrow_error, IntoArrowRobj},
+};
+
+fn datafusion_error(e: DataFusionError) -> ErrArrowRobj {
+    ErrArrowRobj::ExternalError(Box::new(e))
+}
+
+/// Registers batches as the in-memory table `name`
+///
+/// The batches are kept in a single partition.
+pub fn register_batches(
+    ctx: &SessionContext,
+    name: &str,
+    batches: RecordBatches,
+) -> std::result::Result<(), ErrArrowRobj> {
+    let (schema, batches) = batches.into_inner();
+    let table = MemTable::try_new(schema, vec![batches]).map_err(datafusion_error)?;
+
+    ctx.register_table(name, Arc::new(table))
+        .map_err(datafusion_error)?;
+
+    Ok(())
+}
+
+/// Drains an imported R stream into the in-memory table `name`
+///
+/// Must be called on the R main thread.
+pub fn register_reader(
+    ctx: &SessionContext,
+    name: &str,
+    reader: ArrowArrayStreamReader,
+) -> std::result::Result<(), ErrArrowRobj> {
+    let schema = reader.schema();
+    let batches = reader.collect::<std::result::Result<Vec<_>, _>>()?;
+
+    register_batches(ctx, name, RecordBatches::try_new(schema, batches)?)
+}
+
+/// Imports an R object as the in-memory table `name`
+///
+/// Accepts the same objects as `RecordBatches`, e.g. a `nanoarrow_array_stream`
+/// or an `arrow::Table`. The schema is kept even when there are no batches.
+pub fn register_robj(
+    ctx: &SessionContext,
+    name: &str,
+    robj: &Robj,
+) -> std::result::Result<(), ErrArrowRobj> {
+    register_batches(ctx, name, RecordBatches::from_arrow_robj(robj)?)
+}
+
+/// Plans `sql` and returns a reader that executes it lazily on `handle`
+pub fn sql_stream(
+    ctx: &SessionContext,
+    sql: &str,
+    handle: &Handle,
+) -> std::result::Result<
+    BlockingStreamReader<
+        impl Stream<Item = std::result::Result<RecordBatch, ErrArrowRobj>> + Send + 'static,
+    >,
+    ErrArrowRobj,
+> {
+    check_multi_thread(handle)?;
+
+    let stream = handle
+        .block_on(async { ctx.sql(sql).await?.execute_stream().await })
+        .map_err(datafusion_error)?;
+
+    let schema: SchemaRef = stream.schema();
+    let stream = stream.map(|batch| batch.map_err(datafusion_error));
+
+    BlockingStreamReader::new(schema, stream, handle.clone())
+}
+
+/// Runs `sql` and returns the result as a lazy `nanoarrow_array_stream`
+pub fn sql_to_robj(ctx: &SessionContext, sql: &str, handle: &Handle) -> Result<Robj> {
+    sql_stream(ctx, sql, handle)
+        .map_err(arrow_error)?
+        .into_arrow_robj()
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::{
+        array::{AsArray, Int32Array},
+        datatypes::{DataType, Field, Int64Type, Schema},
+    };
+
+    use super::*;
+
+    #[test]
+    fn runs_sql_on_registered_batches() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let schema = Arc::new(Schema::new(vec![Field::new("x", DataType::Int32, false)]));
+        let batches = [vec![1, 2, 3], vec![4, 5]]
+            .into_iter()
+            .map(|x| {
+                RecordBatch::try_new(schema.clone(), vec![Arc::new(Int32Array::from(x))]).unwrap()
+            })
+            .collect();
+
+        let ctx = SessionContext::new();
+        register_batches(
+            &ctx,
+            "tbl",
+            RecordBatches::try_new(schema, batches).unwrap(),
+        )
+        .unwrap();
+
+        let reader = sql_stream(
+            &ctx,
+            "SELECT sum(x) AS total FROM tbl WHERE x > 1",
+            runtime.handle(),
+        )
+        .unwrap();
+        assert_eq!(reader.schema().field(0).name(), "total");
+
+        let batches = reader.collect::<std::result::Result<Vec<_>, _>>().unwrap();
+        let total = batches[0].column(0).as_primitive::<Int64Type>().value(0);
+        assert_eq!(total, 14);
+    }
+
+    #[test]
+    fn keeps_the_schema_of_an_empty_table() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let schema = Arc::new(Schema::new(vec![Field::new("x", DataType::Utf8, true)]));
+
+        let ctx = SessionContext::new();
+        register_batches(&ctx, "tbl", RecordBatches::empty(schema)).unwrap();
+
+        let reader = sql_stream(&ctx, "SELECT x FROM tbl", runtime.handle()).unwrap();
+        assert_eq!(reader.schema().field(0).data_type(), &DataType::Utf8);
+        assert_eq!(
+            reader.map(|batch| batch.unwrap().num_rows()).sum::<usize>(),
+            0
+        );
+    }
+}
@@ -0,0 +1,546 @@
+//! Arrow C Device Data Interface support
+//!
+//! Some producers only speak the [C Device Data Interface](https://arrow.apache.org/docs/format/CDeviceDataInterface.html)
+//! which wraps the usual `ArrowArray` with information about the device the
+//! buffers live on. arrow-rs only understands memory on the CPU so CPU device
+//! arrays and streams are fully supported and every other device type is
+//! rejected with an error.
+//!
+//! |      arrow-rs struct      |             R object            |
+//! | ------------------------- | ------------------------------- |
+//! | `DeviceArray`             | `nanoarrow_device_array`        |
+//! | `DeviceArrayStreamReader` | `nanoarrow_device_array_stream` |
+//!
+//! `ArrayData` and `ArrowArrayStreamReader` also accept the R objects above so
+//! existing code keeps working with device-only producers.
+//!
+//! **Requires a version of `{nanoarrow}` with device support**.
+use std::{
+    ffi::{c_char, c_int, c_void, CStr},
+    ptr,
+};
+
+use arrow::{
+    array::{ArrayData, StructArray},
+    datatypes::{DataType, SchemaRef},
+    ffi::{self, FFI_ArrowArray, FFI_ArrowSchema},
+    ffi_stream::FFI_ArrowArrayStream,
+    record_batch::{RecordBatch, RecordBatchIterator, RecordBatchReader},
+};
+use extendr_api::prelude::*;
+
+use crate::{
+    backend::r_function,
+    from::{
+        r_error, restore_pointer, schema_from_ffi, ErrArrowRobj, FromArrowRobj, ImportMode,
+        ImportOptions,
+    },
+    to::{
+        allocate_schema, arrow_error, move_pointer, set_array_schema, IntoArrowRobj, ToArrowRobj,
+    },
+};
+
+/// `ARROW_DEVICE_CPU` from the C Device Data Interface
+pub const ARROW_DEVICE_CPU: i32 = 1;
+
+/// ABI-compatible struct for `ArrowDeviceArray`
+#[repr(C)]
+#[derive(Debug)]
+#[allow(non_camel_case_types)]
+pub struct FFI_ArrowDeviceArray {
+    pub array: FFI_ArrowArray,
+    pub device_id: i64,
+    pub device_type: i32,
+    pub sync_event: *mut c_void,
+    pub reserved: [i64; 3],
+}
+
+unsafe impl Send for FFI_ArrowDeviceArray {}
+
+impl FFI_ArrowDeviceArray {
+    /// An empty struct to be filled by a producer
+    pub fn empty() -> Self {
+        Self {
+            array: FFI_ArrowArray::empty(),
+            device_id: -1,
+            device_type: ARROW_DEVICE_CPU,
+            sync_event: ptr::null_mut(),
+            reserved: [0; 3],
+        }
+    }
+
+    /// Wraps an `FFI_ArrowArray` whose buffers live on the CPU
+    pub fn new_cpu(array: FFI_ArrowArray) -> Self {
+        Self {
+            array,
+            ..Self::empty()
+        }
+    }
+}
+
+/// ABI-compatible struct for `ArrowDeviceArrayStream`
+#[repr(C)]
+#[derive(Debug)]
+#[allow(non_camel_case_types)]
+pub struct FFI_ArrowDeviceArrayStream {
+    pub device_type: i32,
+    pub get_schema: Option<unsafe extern "C" fn(*mut Self, *mut FFI_ArrowSchema) -> c_int>,
+    pub get_next: Option<unsafe extern "C" fn(*mut Self, *mut FFI_ArrowDeviceArray) -> c_int>,
+    pub get_last_error: Option<unsafe extern "C" fn(*mut Self) -> *const c_char>,
+    pub release: Option<unsafe extern "C" fn(*mut Self)>,
+    pub private_data: *mut c_void,
+}
+
+unsafe impl Send for FFI_ArrowDeviceArrayStream {}
+
+impl FFI_ArrowDeviceArrayStream {
+    /// An empty struct to be filled by a producer
+    pub fn empty() -> Self {
+        Self {
+            device_type: ARROW_DEVICE_CPU,
+            get_schema: None,
+            get_next: None,
+            get_last_error: None,
+            release: None,
+            private_data: ptr::null_mut(),
+        }
+    }
+
+    /// Exports a `RecordBatchReader` as a CPU device stream
+    pub fn new(reader: Box<dyn RecordBatchReader + Send>) -> Self {
+        let inner = Box::new(FFI_ArrowArrayStream::new(reader));
+
+        Self {
+            device_type: ARROW_DEVICE_CPU,
+            get_schema: Some(get_schema),
+            get_next: Some(get_next),
+            get_last_error: Some(get_last_error),
+            release: Some(release_stream),
+            private_data: Box::into_raw(inner) as *mut c_void,
+        }
+    }
+}
+
+impl Drop for FFI_ArrowDeviceArrayStream {
+    fn drop(&mut self) {
+        if let Some(release) = self.release {
+            unsafe { release(self) }
+        }
+    }
+}
+
+// errno returned by the callbacks below when a stream is released or malformed
+const EINVAL: c_int = 22;
+
+// the `FFI_ArrowArrayStream` in `private_data`, to which the callbacks below delegate
+unsafe fn inner_stream<'a>(
+    stream: *mut FFI_ArrowDeviceArrayStream,
+) -> Option<&'a mut FFI_ArrowArrayStream> {
+    if stream.is_null() {
+        return None;
+    }
+    ((*stream).private_data as *mut FFI_ArrowArrayStream).as_mut()
+}
+
+unsafe extern "C" fn get_schema(
+    stream: *mut FFI_ArrowDeviceArrayStream,
+    out: *mut FFI_ArrowSchema,
+) -> c_int {
+    let Some(inner) = inner_stream(stream) else {
+        return EINVAL;
+    };
+    let Some(get_schema) = inner.get_schema else {
+        return EINVAL;
+    };
+    get_schema(inner, out)
+}
+
+unsafe extern "C" fn get_next(
+    stream: *mut FFI_ArrowDeviceArrayStream,
+    out: *mut FFI_ArrowDeviceArray,
+) -> c_int {
+    let Some(inner) = inner_stream(stream) else {
+        return EINVAL;
+    };
+    let Some(get_next) = inner.get_next else {
+        return EINVAL;
+    };
+    let ret = get_next(inner, ptr::addr_of_mut!((*out).array));
+
+    (*out).device_id = -1;
+    (*out).device_type = ARROW_DEVICE_CPU;
+    (*out).sync_event = ptr::null_mut();
+    (*out).reserved = [0; 3];
+
+    ret
+}
+
+unsafe extern "C" fn get_last_error(stream: *mut FFI_ArrowDeviceArrayStream) -> *const c_char {
+    let Some(inner) = inner_stream(stream) else {
+        return ptr::null();
+    };
+    let Some(get_last_error) = inner.get_last_error else {
+        return ptr::null();
+    };
+    get_last_error(inner)
+}
+
+unsafe extern "C" fn release_stream(stream: *mut FFI_ArrowDeviceArrayStream) {
+    if stream.is_null() {
+        return;
+    }
+    let stream = &mut *stream;
+
+    if !stream.private_data.is_null() {
+        // dropping the inner stream releases it
+        drop(Box::from_raw(
+            stream.private_data as *mut FFI_ArrowArrayStream,
+        ));
+    }
+
+    stream.get_schema = None;
+    stream.get_next = None;
+    stream.get_last_error = None;
+    stream.private_data = ptr::null_mut();
+    stream.release = None;
+}
+
+fn check_cpu(device_type: i32) -> std::result::Result<(), ErrArrowRobj> {
+    if device_type != ARROW_DEVICE_CPU {
+        return Err(ErrArrowRobj::NotYetImplemented(format!(
+            "only CPU device arrays are supported, found device type {device_type}"
+        )));
+    }
+    Ok(())
+}
+
+/// An array that lives on the CPU device
+#[derive(Debug, Clone)]
+pub struct DeviceArray(pub ArrayData);
+
+impl From<ArrayData> for DeviceArray {
+    fn from(data: ArrayData) -> Self {
+        Self(data)
+    }
+}
+
+impl FromArrowRobj for DeviceArray {
+    fn from_arrow_robj(robj: &Robj) -> std::result::Result<Self, ErrArrowRobj> {
+        Self::from_arrow_robj_with(robj, &ImportOptions::default())
+    }
+
+    fn from_arrow_robj_with(
+        robj: &Robj,
+        options: &ImportOptions,
+    ) -> std::result::Result<Self, ErrArrowRobj> {
+        // regular arrays are already on the CPU
+        if !robj.inherits("nanoarrow_device_array") {
+            return ArrayData::from_arrow_robj_with(robj, options).map(DeviceArray);
+        }
+
+        let mut device_array = FFI_ArrowDeviceArray::empty();
+        let mut schema = FFI_ArrowSchema::empty();
+
+        let c_array_ptr = &mut device_array as *mut FFI_ArrowDeviceArray as usize;
+        let c_schema_ptr = &mut schema as *mut FFI_ArrowSchema as usize;
+
+        let robj_schema = r_function("nanoarrow::infer_nanoarrow_schema")
+            .and_then(|f| f.call(pairlist!(robj)))
+            .map_err(r_error)?;
+
+        device_export(robj, c_array_ptr)?;
+        device_export(&robj_schema, c_schema_ptr)?;
+
+        // the array is released when `device_array` is dropped
+        check_cpu(device_array.device_type)?;
+
+        let array = std::mem::replace(&mut device_array.array, FFI_ArrowArray::empty());
+        let data = unsafe { ffi::from_ffi(array, &schema)? };
+
+        if options.mode == ImportMode::Share {
+            let mut shared = FFI_ArrowDeviceArray::new_cpu(FFI_ArrowArray::new(&data));
+            restore_pointer(robj, &mut shared as *mut FFI_ArrowDeviceArray as usize)?;
+            restore_pointer(&robj_schema, c_schema_ptr)?;
+        }
+
+        Ok(DeviceArray(data))
+    }
+}
+
+impl ToArrowRobj for DeviceArray {
+    fn to_arrow_robj(&self) -> Result<Robj> {
+        let mut ffi_array = FFI_ArrowDeviceArray::new_cpu(FFI_ArrowArray::new(&self.0));
+        let mut ffi_schema = FFI_ArrowSchema::try_from(self.0.data_type()).map_err(arrow_error)?;
+
+        // the structs are moved out by `{nanoarrow}`, which marks them as released
+        let ffi_array_ptr = &mut ffi_array as *mut FFI_ArrowDeviceArray as usize;
+        let ffi_schema_ptr = &mut ffi_schema as *mut FFI_ArrowSchema as usize;
+
+        let arr_to_fill = allocate_device_array(pairlist!())?;
+        let schema_to_fill = allocate_schema(pairlist!())?;
+
+        move_pointer(pairlist!(ffi_array_ptr.to_string(), &arr_to_fill))?;
+        move_pointer(pairlist!(ffi_schema_ptr.to_string(), &schema_to_fill))?;
+
+        set_array_schema(&arr_to_fill, &schema_to_fill);
+
+        Ok(arr_to_fill)
+    }
+}
+
+impl IntoArrowRobj for DeviceArray {
+    fn into_arrow_robj(self) -> Result<Robj> {
+        self.to_arrow_robj()
+    }
+}
+
+/// Reads `RecordBatch`es from a CPU `FFI_ArrowDeviceArrayStream`
+#[derive(Debug)]
+pub struct DeviceArrayStreamReader {
+    stream: FFI_ArrowDeviceArrayStream,
+    schema: SchemaRef,
+}
+
+impl DeviceArrayStreamReader {
+    /// Takes ownership of `stream` and reads its schema
+    pub fn try_new(
+        mut stream: FFI_ArrowDeviceArrayStream,
+    ) -> std::result::Result<Self, ErrArrowRobj> {
+        if stream.release.is_none() {
+            return Err(ErrArrowRobj::CDataInterface(
+                "input stream is already released".into(),
+            ));
+        }
+
+        check_cpu(stream.device_type)?;
+
+        let get_schema = stream.get_schema.ok_or_else(|| {
+            ErrArrowRobj::CDataInterface("input stream has no `get_schema` callback".into())
+        })?;
+
+        let mut schema = FFI_ArrowSchema::empty();
+        let ret = unsafe { get_schema(&mut stream, &mut schema) };
+
+        if ret != 0 {
+            return Err(ErrArrowRobj::CDataInterface(format!(
+                "cannot get schema from input stream: {}",
+                last_error(&mut stream).unwrap_or_default()
+            )));
+        }
+
+        let schema = schema_from_ffi(&schema)?;
+
+        Ok(Self {
+            stream,
+            schema: schema.into(),
+        })
+    }
+}
+
+fn last_error(stream: &mut FFI_ArrowDeviceArrayStream) -> Option<String> {
+    let get_last_error = stream.get_last_error?;
+    let err = unsafe { get_last_error(stream) };
+
+    if err.is_null() {
+        return None;
+    }
+
+    Some(
+        unsafe { CStr::from_ptr(err) }
+            .to_string_lossy()
+            .into_owned(),
+    )
+}
+
+impl Iterator for DeviceArrayStreamReader {
+    type Item = std::result::Result<RecordBatch, ErrArrowRobj>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Some(get_next) = self.stream.get_next else {
+            return Some(Err(ErrArrowRobj::CDataInterface(
+                "input stream has no `get_next` callback".into(),
+            )));
+        };
+
+        let mut device_array = FFI_ArrowDeviceArray::empty();
+        let ret = unsafe { get_next(&mut self.stream, &mut device_array) };
+
+        if ret != 0 {
+            return Some(Err(ErrArrowRobj::CDataInterface(
+                last_error(&mut self.stream).unwrap_or_default(),
+            )));
+        }
+
+        // the end of the stream is signalled by a released array
+        if device_array.array.is_released() {
+            return None;
+        }
+
+        if let Err(e) = check_cpu(device_array.device_type) {
+            return Some(Err(e));
+        }
+
+        let array = std::mem::replace(&mut device_array.array, FFI_ArrowArray::empty());
+        let data_type = DataType::Struct(self.schema.fields().clone());
+
+        // rebuilt with the schema of the stream to keep its metadata
+        let res = unsafe { ffi::from_ffi_and_data_type(array, data_type) }.and_then(|data| {
+            let (_, columns, _) = StructArray::from(data).into_parts();
+            RecordBatch::try_new(self.schema.clone(), columns)
+        });
+
+        Some(res)
+    }
+}
+
+impl RecordBatchReader for DeviceArrayStreamReader {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
+
+impl FromArrowRobj for DeviceArrayStreamReader {
+    fn from_arrow_robj(robj: &Robj) -> std::result::Result<Self, ErrArrowRobj> {
+        Self::from_arrow_robj_with(robj, &ImportOptions::default())
+    }
+
+    fn from_arrow_robj_with(
+        robj: &Robj,
+        options: &ImportOptions,
+    ) -> std::result::Result<Self, ErrArrowRobj> {
+        if !robj.inherits("nanoarrow_device_array_stream") {
+            return Err(ErrArrowRobj::ParseError(
+                "did not find a `nanoarrow_device_array_stream`".into(),
+            ));
+        }
+
+        let mut stream = FFI_ArrowDeviceArrayStream::empty();
+        let c_stream_ptr = &mut stream as *mut FFI_ArrowDeviceArrayStream as usize;
+
+        device_export(robj, c_stream_ptr)?;
+        let reader = DeviceArrayStreamReader::try_new(stream)?;
+
+        if options.mode == ImportMode::Move {
+            return Ok(reader);
+        }
+
+        // streams can only be read once so share a copy of the collected
+        // batches, which are all held in memory, see `ImportMode::Share`
+        let schema = reader.schema();
+        let batches = reader.collect::<std::result::Result<Vec<_>, _>>()?;
+
+        let restored =
+            RecordBatchIterator::new(batches.clone().into_iter().map(Ok), schema.clone());
+        let mut restored = FFI_ArrowDeviceArrayStream::new(Box::new(restored));
+        restore_pointer(
+            robj,
+            &mut restored as *mut FFI_ArrowDeviceArrayStream as usize,
+        )?;
+
+        let batches = RecordBatchIterator::new(batches.into_iter().map(Ok), schema);
+        DeviceArrayStreamReader::try_new(FFI_ArrowDeviceArrayStream::new(Box::new(batches)))
+    }
+}
+
+/// Exports a `RecordBatchReader` as a `nanoarrow_device_array_stream` on the CPU
+impl IntoArrowRobj for DeviceArrayStreamReader {
+    fn into_arrow_robj(self) -> Result<Robj> {
+        let reader: Box<dyn RecordBatchReader + Send> = Box::new(self);
+        to_device_stream_robj(reader)
+    }
+}
+
+/// Exports any `RecordBatchReader` as a `nanoarrow_device_array_stream` on the CPU
+pub fn to_device_stream_robj(reader: Box<dyn RecordBatchReader + Send>) -> Result<Robj> {
+    let mut stream = FFI_ArrowDeviceArrayStream::new(reader);
+    let stream_ptr = (&mut stream) as *mut FFI_ArrowDeviceArrayStream as usize;
+
+    let stream_to_fill = allocate_device_array_stream(pairlist!())?;
+    move_pointer(pairlist!(stream_ptr.to_string(), &stream_to_fill))?;
+
+    Ok(stream_to_fill)
+}
+
+/// Calls `nanoarrow::nanoarrow_pointer_export()`
+///
+/// Device objects are always exported by `{nanoarrow}`, whatever the backend.
+fn device_export(source: &Robj, dest: usize) -> std::result::Result<(), ErrArrowRobj> {
+    r_function("nanoarrow::nanoarrow_pointer_export")
+        .and_then(|f| f.call(pairlist!(source, dest.to_string())))
+        .map_err(r_error)?;
+    Ok(())
+}
+
+/// Calls `nanoarrow::nanoarrow_allocate_device_array()`
+///
+/// Requires `{nanoarrow}` to be installed.
+pub fn allocate_device_array(args: Pairlist) -> Result<Robj> {
+    r_function("nanoarrow::nanoarrow_allocate_device_array")?.call(args)
+}
+
+/// Calls `nanoarrow::nanoarrow_allocate_device_array_stream()`
+///
+/// Requires `{nanoarrow}` to be installed.
+pub fn allocate_device_array_stream(args: Pairlist) -> Result<Robj> {
+    r_function("nanoarrow::nanoarrow_allocate_device_array_stream")?.call(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::array::{ArrayRef, Int32Array};
+
+    use super::*;
+
+    fn batch() -> RecordBatch {
+        let column: ArrayRef = Arc::new(Int32Array::from(vec![1, 2, 3]));
+        RecordBatch::try_from_iter([("x", column)]).unwrap()
+    }
+
+    fn device_stream() -> FFI_ArrowDeviceArrayStream {
+        let batch = batch();
+        let schema = batch.schema();
+        FFI_ArrowDeviceArrayStream::new(Box::new(RecordBatchIterator::new(vec![Ok(batch)], schema)))
+    }
+
+    #[test]
+    fn reads_batches_of_a_cpu_stream() {
+        let reader = DeviceArrayStreamReader::try_new(device_stream()).unwrap();
+        assert_eq!(reader.schema(), batch().schema());
+
+        let batches = reader.collect::<std::result::Result<Vec<_>, _>>().unwrap();
+        assert_eq!(batches, vec![batch()]);
+    }
+
+    #[test]
+    fn callbacks_return_einval_for_a_malformed_stream() {
+        let mut stream = device_stream();
+        let inner = unsafe { &mut *(stream.private_data as *mut FFI_ArrowArrayStream) };
+        inner.get_schema = None;
+        inner.get_next = None;
+        inner.get_last_error = None;
+
+        let mut schema = FFI_ArrowSchema::empty();
+        let mut array = FFI_ArrowDeviceArray::empty();
+        unsafe {
+            assert_eq!(get_schema(&mut stream, &mut schema), EINVAL);
+            assert_eq!(get_next(&mut stream, &mut array), EINVAL);
+            assert!(get_last_error(&mut stream).is_null());
+            assert_eq!(get_schema(ptr::null_mut(), &mut schema), EINVAL);
+        }
+    }
+
+    #[test]
+    fn reader_errors_for_missing_callbacks() {
+        let mut stream = device_stream();
+        stream.get_schema = None;
+        let error = DeviceArrayStreamReader::try_new(stream).unwrap_err();
+        assert!(error.to_string().contains("no `get_schema` callback"));
+
+        let mut reader = DeviceArrayStreamReader::try_new(device_stream()).unwrap();
+        reader.stream.get_next = None;
+        let error = reader.next().unwrap().unwrap_err();
+        assert!(error.to_string().contains("no `get_next` callback"));
+    }
+}
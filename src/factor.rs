@@ -0,0 +1,487 @@
+//! Convert dictionary-encoded arrays to and from R factors
+//!
+//! A `Factor` is a `DictionaryArray<Int32Type>` with `Utf8` values and the
+//! `ordered` flag of R factors. The R factor is converted directly, without
+//! any R package: the levels become the dictionary and the 1-based codes
+//! become the 0-based keys.
+//!
+//! |  arrow-rs struct  |                                      R object                                     |
+//! | ----------------- | --------------------------------------------------------------------------------- |
+//! | `Factor` (import) | `factor`, dictionary `nanoarrow_array` or `arrow::Array`, or single column stream |
+//! | `Factor` (export) | `factor` or `ordered`                                                             |
+//!
+//! Batches of a stream may each carry a different dictionary. They are
+//! unified into a single set of levels, in order of first appearance, when a
+//! `Factor` is created from a stream or with `Factor::unify()`. Use
+//! `unify_dictionaries()` to do the same for every dictionary column of a
+//! `Vec<RecordBatch>` before exporting it.
+//!
+//! The `ordered` flag is stored as the dictionary ordered flag of the C Data
+//! Interface. `Field` and `Schema` keep it when imported, see
+//! `Factor::field()` to create one.
+//!
+//! ```ignore
+//! fn sort_levels(x: Robj) -> Result<Robj> {
+//!     let factor = Factor::from_arrow_robj(&x).map_err(arrow_error)?;
+//!     factor.to_arrow_robj()
+//! }
+//! ```
+use std::{collections::HashMap, sync::Arc};
+
+use arrow::{
+    array::{
+        make_array, Array, ArrayData, ArrayRef, AsArray, DictionaryArray, Int32Array, StringArray,
+    },
+    compute::cast,
+    datatypes::{DataType, Field, Int32Type, Schema},
+    record_batch::RecordBatch,
+};
+use extendr_api::prelude::*;
+
+use crate::{
+    from::{nanoarrow_array_schema, r_error, ErrArrowRobj, FromArrowRobj, ImportOptions},
+    to::{arrow_error, IntoArrowRobj, ToArrowRobj},
+};
+
+/// A dictionary-encoded array with the levels and `ordered` flag of an R factor
+#[derive(Debug, Clone)]
+pub struct Factor {
+    pub array: DictionaryArray<Int32Type>,
+    pub ordered: bool,
+}
+
+impl Factor {
+    /// Creates a factor from any dictionary-encoded array
+    ///
+    /// Keys are cast to `Int32` and values to `Utf8`. Duplicate values are
+    /// merged and keys pointing to a null value become null.
+    pub fn try_new(array: ArrayRef, ordered: bool) -> std::result::Result<Self, ErrArrowRobj> {
+        Self::unify(&[array], ordered)
+    }
+
+    /// Combines dictionary-encoded chunks into one factor with unified levels
+    pub fn unify(chunks: &[ArrayRef], ordered: bool) -> std::result::Result<Self, ErrArrowRobj> {
+        let mut levels: Vec<String> = Vec::new();
+        let mut index: HashMap<String, i32> = HashMap::new();
+        let mut keys: Vec<Option<i32>> = Vec::new();
+
+        for chunk in chunks {
+            let dict = chunk.as_any_dictionary_opt().ok_or_else(|| {
+                ErrArrowRobj::InvalidArgumentError(format!(
+                    "expected a dictionary array, found {}",
+                    chunk.data_type()
+                ))
+            })?;
+
+            let values = cast(dict.values(), &DataType::Utf8)?;
+            let remap = values
+                .as_string::<i32>()
+                .iter()
+                .map(|value| {
+                    value.map(|value| {
+                        *index.entry(value.to_string()).or_insert_with(|| {
+                            levels.push(value.to_string());
+                            levels.len() as i32 - 1
+                        })
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            // keys of dictionaries imported over FFI are not validated
+            let chunk_keys = cast(dict.keys(), &DataType::Int32)?;
+            for key in chunk_keys.as_primitive::<Int32Type>().iter() {
+                let Some(key) = key else {
+                    keys.push(None);
+                    continue;
+                };
+
+                let level = usize::try_from(key)
+                    .ok()
+                    .and_then(|key| remap.get(key))
+                    .ok_or_else(|| {
+                        ErrArrowRobj::InvalidArgumentError(format!(
+                            "dictionary key {key} is out of range for {} values",
+                            remap.len()
+                        ))
+                    })?;
+                keys.push(*level);
+            }
+        }
+
+        let array =
+            DictionaryArray::try_new(Int32Array::from(keys), Arc::new(StringArray::from(levels)))?;
+
+        Ok(Self { array, ordered })
+    }
+
+    /// The levels of the factor
+    pub fn levels(&self) -> &StringArray {
+        self.array.values().as_string::<i32>()
+    }
+
+    /// A `Field` for the factor that carries the `ordered` flag
+    pub fn field(&self, name: &str) -> Field {
+        Field::new_dict(name, self.array.data_type().clone(), true, 0, self.ordered)
+    }
+}
+
+/// Rewrites every dictionary column so that all batches share one dictionary
+///
+/// Dictionary columns become `Dictionary(Int32, Utf8)` and the field keeps its
+/// `ordered` flag. Other columns are left unchanged.
+pub fn unify_dictionaries(
+    batches: &[RecordBatch],
+) -> std::result::Result<Vec<RecordBatch>, ErrArrowRobj> {
+    let Some(first) = batches.first() else {
+        return Ok(vec![]);
+    };
+
+    let schema = first.schema();
+    let mut columns: Vec<Vec<ArrayRef>> = vec![vec![]; batches.len()];
+    let mut fields = Vec::with_capacity(schema.fields().len());
+
+    for (i, field) in schema.fields().iter().enumerate() {
+        let chunks = batches
+            .iter()
+            .map(|batch| batch.column(i).clone())
+            .collect::<Vec<_>>();
+
+        if !matches!(field.data_type(), DataType::Dictionary(_, _)) {
+            fields.push(field.as_ref().clone());
+            columns.iter_mut().zip(chunks).for_each(|(c, x)| c.push(x));
+            continue;
+        }
+
+        let ordered = field.dict_is_ordered().unwrap_or(false);
+        let unified = Factor::unify(&chunks, ordered)?;
+
+        let mut offset = 0;
+        for (c, chunk) in columns.iter_mut().zip(&chunks) {
+            c.push(Arc::new(unified.array.slice(offset, chunk.len())));
+            offset += chunk.len();
+        }
+
+        fields.push(
+            unified
+                .field(field.name())
+                .with_nullable(field.is_nullable())
+                .with_metadata(field.metadata().clone()),
+        );
+    }
+
+    let schema = Arc::new(Schema::new(fields).with_metadata(schema.metadata().clone()));
+
+    columns
+        .into_iter()
+        .map(|c| RecordBatch::try_new(schema.clone(), c))
+        .collect()
+}
+
+/// Creates a `Factor` from an R factor without calling into R
+fn factor_from_r(robj: &Robj) -> std::result::Result<Factor, ErrArrowRobj> {
+    let codes = robj
+        .as_integer_slice()
+        .ok_or_else(|| ErrArrowRobj::ParseError("factor codes must be integers".into()))?;
+
+    let levels = robj
+        .levels()
+        .map(|levels| levels.collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    let keys = codes
+        .iter()
+        .map(|&code| (code != i32::MIN).then(|| code - 1))
+        .collect::<Int32Array>();
+
+    let array = DictionaryArray::try_new(keys, Arc::new(StringArray::from(levels)))?;
+
+    Ok(Factor {
+        array,
+        ordered: robj.inherits("ordered"),
+    })
+}
+
+impl FromArrowRobj for Factor {
+    fn from_arrow_robj(robj: &Robj) -> std::result::Result<Self, ErrArrowRobj> {
+        Self::from_arrow_robj_with(robj, &ImportOptions::default())
+    }
+
+    fn from_arrow_robj_with(
+        robj: &Robj,
+        options: &ImportOptions,
+    ) -> std::result::Result<Self, ErrArrowRobj> {
+        if robj.is_factor() {
+            return factor_from_r(robj);
+        }
+
+        let is_stream = ["nanoarrow_array_stream", "RecordBatchReader", "Table"]
+            .iter()
+            .any(|cls| robj.inherits(cls));
+
+        if is_stream {
+            let schema = Schema::from_arrow_robj_shared(robj)?;
+            let batches = Vec::<RecordBatch>::from_arrow_robj_with(robj, options)?;
+
+            let [field] = schema.fields().as_ref() else {
+                return Err(ErrArrowRobj::ParseError(format!(
+                    "a factor must be read from a single column stream, found {} columns",
+                    schema.fields().len()
+                )));
+            };
+
+            let chunks = batches
+                .iter()
+                .map(|batch| batch.column(0).clone())
+                .collect::<Vec<_>>();
+
+            return Factor::unify(&chunks, field.dict_is_ordered().unwrap_or(false));
+        }
+
+        // the ordered flag is read before the array is moved
+        let ordered = if robj.inherits("nanoarrow_array") {
+            let schema = nanoarrow_array_schema(robj).map_err(r_error)?;
+            Field::from_arrow_robj_shared(&schema)?
+                .dict_is_ordered()
+                .unwrap_or(false)
+        } else if robj.inherits("Array") {
+            robj.dollar("type")
+                .and_then(|t| t.dollar("ordered"))
+                .ok()
+                .and_then(|ordered| ordered.as_bool())
+                .unwrap_or(false)
+        } else {
+            false
+        };
+
+        let data = ArrayData::from_arrow_robj_with(robj, options)?;
+        Factor::try_new(make_array(data), ordered)
+    }
+}
+
+impl ToArrowRobj for Factor {
+    fn to_arrow_robj(&self) -> Result<Robj> {
+        // R factor codes are 1-based, `NA_integer_` is `i32::MIN`
+        let codes = self
+            .array
+            .keys()
+            .iter()
+            .map(|key| key.map(|key| key + 1).unwrap_or(i32::MIN))
+            .collect::<Vec<_>>();
+
+        // null levels, e.g. of a `Factor` built by hand, stay `NA`
+        let levels = self
+            .levels()
+            .iter()
+            .map(|level| level.map_or_else(Rstr::na, Rstr::from))
+            .collect::<Vec<_>>();
+
+        let mut robj: Robj = Integers::from_values(codes).into();
+        robj.set_attrib("levels", Strings::from_values(levels))?;
+
+        if self.ordered {
+            robj.set_class(["ordered", "factor"])?;
+        } else {
+            robj.set_class(["factor"])?;
+        }
+
+        Ok(robj)
+    }
+}
+
+impl IntoArrowRobj for Factor {
+    fn into_arrow_robj(self) -> Result<Robj> {
+        self.to_arrow_robj()
+    }
+}
+
+/// Converts any dictionary-encoded array into an R factor
+///
+/// Unlike `ToArrowRobj` for `ArrayData`, which returns a `nanoarrow_array`,
+/// this always returns a `factor` or `ordered`.
+pub fn dictionary_to_factor(array: ArrayRef, ordered: bool) -> Result<Robj> {
+    Factor::try_new(array, ordered)
+        .map_err(arrow_error)?
+        .to_arrow_robj()
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::{array::Int8Array, datatypes::Int8Type, ffi::FFI_ArrowSchema};
+
+    use super::*;
+    use crate::from::schema_from_ffi;
+
+    fn dictionary(keys: Vec<Option<i8>>, values: Vec<Option<&str>>) -> ArrayRef {
+        Arc::new(
+            DictionaryArray::<Int8Type>::try_new(
+                Int8Array::from(keys),
+                Arc::new(StringArray::from(values)),
+            )
+            .unwrap(),
+        )
+    }
+
+    fn decode(factor: &Factor) -> Vec<Option<String>> {
+        let levels = factor.levels();
+        factor
+            .array
+            .keys()
+            .iter()
+            .map(|key| key.map(|key| levels.value(key as usize).to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn errors_for_out_of_range_keys() {
+        for key in [-1, 2] {
+            // as if imported over FFI without validation
+            let array = unsafe {
+                DictionaryArray::<Int8Type>::new_unchecked(
+                    Int8Array::from(vec![Some(0), Some(key)]),
+                    Arc::new(StringArray::from(vec!["a", "b"])),
+                )
+            };
+
+            let error = Factor::try_new(Arc::new(array), false).unwrap_err();
+            assert!(error
+                .to_string()
+                .contains(&format!("key {key} is out of range")));
+        }
+    }
+
+    #[test]
+    fn maps_keys_to_levels_and_keeps_nulls() {
+        let array = dictionary(
+            vec![Some(1), None, Some(0), Some(2)],
+            vec![Some("a"), Some("b"), None],
+        );
+
+        let factor = Factor::try_new(array, false).unwrap();
+
+        assert_eq!(
+            factor.levels().iter().collect::<Vec<_>>(),
+            vec![Some("a"), Some("b")]
+        );
+        // a null key and a key pointing to a null value are both NA
+        assert_eq!(
+            factor.array.keys().iter().collect::<Vec<_>>(),
+            vec![Some(1), None, Some(0), None]
+        );
+    }
+
+    #[test]
+    fn unifies_levels_across_chunks_in_order_of_appearance() {
+        let chunks = [
+            dictionary(vec![Some(0), Some(1)], vec![Some("b"), Some("a")]),
+            dictionary(
+                vec![Some(1), Some(0), Some(2)],
+                vec![Some("c"), Some("a"), Some("a")],
+            ),
+        ];
+
+        let factor = Factor::unify(&chunks, false).unwrap();
+
+        assert_eq!(
+            factor.levels().iter().collect::<Vec<_>>(),
+            vec![Some("b"), Some("a"), Some("c")]
+        );
+        assert_eq!(
+            decode(&factor),
+            ["b", "a", "a", "c", "a"]
+                .map(|level| Some(level.to_string()))
+                .to_vec()
+        );
+    }
+
+    #[test]
+    fn rejects_arrays_that_are_not_dictionaries() {
+        let array: ArrayRef = Arc::new(Int32Array::from(vec![1, 2]));
+        assert!(Factor::try_new(array, false).is_err());
+    }
+
+    #[test]
+    fn unifies_dictionary_columns_of_batches() {
+        let dict_type = DataType::Dictionary(Box::new(DataType::Int8), Box::new(DataType::Utf8));
+        let schema = Arc::new(Schema::new(vec![
+            Field::new_dict("f", dict_type, true, 0, true),
+            Field::new("x", DataType::Int32, false),
+        ]));
+        let batches = [
+            (
+                vec![Some(0), None],
+                vec![Some("lo"), Some("hi")],
+                vec![1, 2],
+            ),
+            (
+                vec![Some(0), Some(1)],
+                vec![Some("hi"), Some("mid")],
+                vec![3, 4],
+            ),
+        ]
+        .map(|(keys, values, x)| {
+            RecordBatch::try_new(
+                schema.clone(),
+                vec![dictionary(keys, values), Arc::new(Int32Array::from(x))],
+            )
+            .unwrap()
+        });
+
+        let unified = unify_dictionaries(&batches).unwrap();
+
+        let field = unified[0].schema_ref().field(0).clone();
+        assert_eq!(field.dict_is_ordered(), Some(true));
+        assert_eq!(
+            field.data_type(),
+            &DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8))
+        );
+
+        let chunks = unified
+            .iter()
+            .map(|batch| batch.column(0).clone())
+            .collect::<Vec<_>>();
+        let dicts = chunks
+            .iter()
+            .map(|chunk| chunk.as_dictionary::<Int32Type>().values().clone())
+            .collect::<Vec<_>>();
+        assert_eq!(dicts[0].to_data(), dicts[1].to_data());
+
+        let factor = Factor::unify(&chunks, true).unwrap();
+        assert_eq!(
+            decode(&factor),
+            vec![
+                Some("lo".to_string()),
+                None,
+                Some("hi".to_string()),
+                Some("mid".to_string())
+            ]
+        );
+        assert_eq!(unified[1].column(1).as_ref(), &Int32Array::from(vec![3, 4]));
+    }
+
+    #[test]
+    fn keeps_the_ordered_flag_through_the_c_data_interface() {
+        let factor = Factor::try_new(dictionary(vec![Some(0)], vec![Some("a")]), true).unwrap();
+        let ordered = Arc::new(factor.field("f"));
+
+        // nested in a struct, a list and a map
+        let schema = Schema::new(vec![
+            factor.field("top"),
+            Field::new_struct("s", vec![ordered.clone()], true),
+            Field::new_list("l", ordered.as_ref().clone().with_name("item"), true),
+            Field::new_map(
+                "m",
+                "entries",
+                Field::new("key", DataType::Utf8, false),
+                ordered.as_ref().clone().with_name("value"),
+                false,
+                true,
+            ),
+        ]);
+
+        let c_schema = FFI_ArrowSchema::try_from(&schema).unwrap();
+        let imported = schema_from_ffi(&c_schema).unwrap();
+
+        assert_eq!(imported, schema);
+        assert_eq!(imported.field(0).dict_is_ordered(), Some(true));
+    }
+}
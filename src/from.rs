@@ -6,40 +6,156 @@
 //! }
 //! ```
 //!
-//! `Robj`s from `{nanoarrow}` and `{arrow}` are both supported.
+//! `Robj`s from `{nanoarrow}` and `{arrow}` are both supported. `{polars}`
+//! objects are also accepted, see the `polars` module.
 //!
-//! | arrow-rs struct          |             R object                            |
-//! | -------------------------| ----------------------------------------------- |
-//! | `Field`                  |`nanoarrow_schema` or `arrow::Field`             |
-//! | `Schema`                 |`nanoarrow_schema` or `arrow::Schema`            |
-//! | `DataType`               |`nanoarrow_schema` or `arrow::DataType`          |
-//! | `ArrayData`              |`nanoarrow_array` or `arrow::Array`              |
-//! | `RecordBatch`            |`nanoarrow_array_stream` or `arrow::RecordBatch` |
-//! | `ArrowArrayStreamReader` |`nanoarrow_array_stream`                         |
+//! |     arrow-rs struct      |                                   R object                                  |
+//! | ------------------------ | --------------------------------------------------------------------------- |
+//! | `Field`                  | `nanoarrow_schema` or `arrow::Field`                                        |
+//! | `Schema`                 | `nanoarrow_schema`, `nanoarrow_array_stream`, or `arrow::Schema`            |
+//! | `DataType`               | `nanoarrow_schema` or `arrow::DataType`                                     |
+//! | `ArrayData`              | `nanoarrow_array` or `arrow::Array`                                         |
+//! | `Scalar<ArrayRef>`       | length-1 `nanoarrow_array`, `arrow::Scalar`, or length-1 atomic vector      |
+//! | `RecordBatch`            | `nanoarrow_array_stream`, struct `nanoarrow_array`, or `arrow::RecordBatch` |
+//! | `ArrowArrayStreamReader` | `nanoarrow_array_stream`, `arrow::RecordBatchReader`, or `arrow::Table`     |
+//! | `Vec<RecordBatch>`       | `nanoarrow_array_stream`, `arrow::RecordBatchReader`, or `arrow::Table`     |
+//!
+//! Objects of any other class are converted with `{nanoarrow}` first: schemas
+//! with [`as_nanoarrow_schema()`], arrays with [`as_nanoarrow_array()`], and
+//! record batches and streams with [`as_nanoarrow_array_stream()`]. This lets
+//! S3 methods for classes such as `data.frame`, `tibble`, `sf` or `vctrs`
+//! vectors feed into Rust, as well as ADBC statements and duckdb results.
+//! Use `from_arrow_robj_strict()` to only accept the classes in the table above.
+//!
+//! CPU `nanoarrow_device_array`s and `nanoarrow_device_array_stream`s are accepted
+//! by `ArrayData` and `ArrowArrayStreamReader`, see the `device` module.
+//!
+//! R `factor`s are converted to dictionary arrays without `{nanoarrow}` by
+//! `Factor`, which also keeps the `ordered` flag, see the `factor` module.
 //!
 //! ### Notes
 //!
 //! In the case of creating a `RecordBatch` from a `nanoarrow_array_stream` only
 //! the first chunk is returned. If you expect more than one chunk, use `ArrowArrayStreamReader`.
 //!
+//! A `Schema` can be read from a `nanoarrow_array_stream`, or an `{arrow}`
+//! `RecordBatchReader`, `Table` or `RecordBatch`, without consuming it. Only the
+//! schema is requested and the R object remains usable.
+//!
+//! By default the data of `{nanoarrow}` objects is moved into Rust and the R
+//! object is released afterwards. Use `from_arrow_robj_shared()` or
+//! `ImportMode::Share` to keep it usable in R.
+//!
+//! An `ArrowArrayStreamReader` created from an R object calls back into R each
+//! time a batch is read, so it must only be iterated on the R main thread. Use
+//! `Vec<RecordBatch>` to drain the stream up front when the batches are consumed
+//! elsewhere, such as by a multi-threaded query engine.
+//!
 
 use arrow::{
-    array::{make_array, ArrayData},
+    array::{make_array, Array, ArrayData, ArrayRef, Scalar, StructArray},
+    compute::concat_batches,
     datatypes::{DataType, Field, Schema},
     error::ArrowError,
     ffi::{self, FFI_ArrowArray, FFI_ArrowSchema},
-    ffi_stream::{self, ArrowArrayStreamReader, FFI_ArrowArrayStream},
-    record_batch::RecordBatch,
+    ffi_stream::{ArrowArrayStreamReader, FFI_ArrowArrayStream},
+    record_batch::{RecordBatch, RecordBatchIterator, RecordBatchReader},
 };
 
+#[cfg(not(feature = "nanoarrow"))]
+use crate::native;
+use crate::{
+    backend::r_function,
+    device::{DeviceArray, DeviceArrayStreamReader},
+    polars, raw,
+    rewrite::{restore_array, restore_batch, restore_field},
+    to::{allocate_array_stream, move_pointer},
+};
 use extendr_api::prelude::*;
-use std::result::Result;
+use std::{result::Result, sync::Arc};
 
 /// Creates arrow-rs Structs from an Robj
 ///
-
+/// Implementors only need to provide `from_arrow_robj()`. Override
+/// `from_arrow_robj_with()` as well to honour `ImportOptions`, which the
+/// strict and shared variants rely on.
 pub trait FromArrowRobj: Sized {
     fn from_arrow_robj(robj: &Robj) -> Result<Self, ErrArrowRobj>;
+
+    /// Imports with `options`
+    ///
+    /// Ignores `options` unless overridden.
+    fn from_arrow_robj_with(robj: &Robj, options: &ImportOptions) -> Result<Self, ErrArrowRobj> {
+        let _ = options;
+        Self::from_arrow_robj(robj)
+    }
+
+    /// Only accepts the R classes that are explicitly supported
+    ///
+    /// Other objects return an error rather than being converted with
+    /// `{nanoarrow}`'s `as_nanoarrow_*()` generics.
+    fn from_arrow_robj_strict(robj: &Robj) -> Result<Self, ErrArrowRobj> {
+        Self::from_arrow_robj_with(robj, &ImportOptions::default().with_strict(true))
+    }
+
+    /// Leaves `{nanoarrow}` objects valid in R, see `ImportMode::Share`
+    ///
+    /// Streams are collected into memory first.
+    fn from_arrow_robj_shared(robj: &Robj) -> Result<Self, ErrArrowRobj> {
+        Self::from_arrow_robj_with(robj, &ImportOptions::default().with_mode(ImportMode::Share))
+    }
+}
+
+/// How `{nanoarrow}` objects are treated when they are imported
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ImportMode {
+    /// Data is moved out of the R object which is released afterwards
+    #[default]
+    Move,
+    /// The R object stays valid after the import
+    ///
+    /// Schemas are moved back into the R object once they have been read.
+    /// Arrays are replaced by a view that shares the imported buffers, which
+    /// are reference counted.
+    ///
+    /// **Streams are read to the end before the import returns.** A stream
+    /// can only be read once, so every batch is collected into memory and the
+    /// R object is replaced by a stream over the same batches. Do not share
+    /// streams that are larger than memory or that never end, and expect
+    /// errors from the producer to be raised by the import rather than while
+    /// reading. The same applies to `nanoarrow_device_array_stream`s.
+    Share,
+}
+
+/// Options that control how an `Robj` is imported
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ImportOptions {
+    /// When `true` unknown R classes are an error instead of being passed to
+    /// `nanoarrow::as_nanoarrow_schema()`, `as_nanoarrow_array()` or
+    /// `as_nanoarrow_array_stream()`.
+    pub strict: bool,
+    pub mode: ImportMode,
+    /// When `true` types rewritten on export are restored from the field
+    /// metadata, see the `rewrite` module. Streams read through an
+    /// `ArrowArrayStreamReader` are not restored.
+    pub restore_types: bool,
+}
+
+impl ImportOptions {
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn with_mode(mut self, mode: ImportMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn with_restore_types(mut self, restore_types: bool) -> Self {
+        self.restore_types = restore_types;
+        self
+    }
 }
 
 pub type ErrArrowRobj = ArrowError;
@@ -49,11 +165,14 @@ pub type ErrArrowRobj = ArrowError;
 /// Gets the address of a nanoarrow object as a string `Robj`
 /// Requires `{nanoarrow}` to be installed.
 pub fn nanoarrow_addr(robj: &Robj) -> Result<Robj, Error> {
-    R!("nanoarrow::nanoarrow_pointer_addr_chr")
-        .expect("`nanoarrow` must be installed")
-        .as_function()
-        .expect("`nanoarrow_pointer_addr_ch()` must be available")
-        .call(pairlist!(robj))
+    #[cfg(feature = "nanoarrow")]
+    {
+        r_function("nanoarrow::nanoarrow_pointer_addr_chr")?.call(pairlist!(robj))
+    }
+    #[cfg(not(feature = "nanoarrow"))]
+    {
+        native::pointer_addr(robj).map(|addr| addr.to_string().into())
+    }
 }
 
 /// Calls `nanoarrow::nanoarrow_pointer_export()`
@@ -61,242 +180,646 @@ pub fn nanoarrow_addr(robj: &Robj) -> Result<Robj, Error> {
 /// Exports a nanoarrow pointer from R to C
 /// Requires `{nanoarrow}` to be installed.
 pub fn nanoarrow_export(source: &Robj, dest: String) -> Result<Robj, Error> {
-    R!("nanoarrow::nanoarrow_pointer_export")
-        .expect("`nanoarrow` must be installed")
-        .as_function()
-        .expect("`nanoarrow_pointer_export()` must be available")
-        .call(pairlist!(source, dest))
+    #[cfg(feature = "nanoarrow")]
+    {
+        r_function("nanoarrow::nanoarrow_pointer_export")?.call(pairlist!(source, dest))
+    }
+    #[cfg(not(feature = "nanoarrow"))]
+    {
+        let addr = dest
+            .parse::<usize>()
+            .map_err(|_| Error::Other("destination must be an address string".into()))?;
+        unsafe { native::export_pointer(source, addr)? };
+        Ok(dest.into())
+    }
+}
+
+/// Calls `nanoarrow::infer_nanoarrow_schema()` on a `nanoarrow_array`
+///
+/// Requires `{nanoarrow}` to be installed, or a backend other than `nanoarrow`.
+pub fn nanoarrow_array_schema(array: &Robj) -> Result<Robj, Error> {
+    #[cfg(feature = "nanoarrow")]
+    {
+        r_function("nanoarrow::infer_nanoarrow_schema")?.call(pairlist!(array))
+    }
+    #[cfg(not(feature = "nanoarrow"))]
+    {
+        native::array_schema(array)
+    }
 }
 
 impl FromArrowRobj for Field {
     fn from_arrow_robj(robj: &Robj) -> Result<Self, ErrArrowRobj> {
-        // handle nanoarrow
-        if robj.inherits("nanoarrow_schema") {
-            let c_schema = FFI_ArrowSchema::empty();
-            let c_schema_ptr = &c_schema as *const FFI_ArrowSchema as usize;
+        Self::from_arrow_robj_with(robj, &ImportOptions::default())
+    }
 
-            let _ = nanoarrow_export(robj, c_schema_ptr.to_string());
+    fn from_arrow_robj_with(robj: &Robj, options: &ImportOptions) -> Result<Self, ErrArrowRobj> {
+        if robj.inherits("nanoarrow_schema") || robj.inherits("Field") {
+            return schema_from_raw(robj, options, |s| import_field(s, options));
+        }
 
-            let field = Field::try_from(&c_schema)?;
+        fallback(
+            robj,
+            options,
+            as_nanoarrow_schema,
+            "did not find a `Field` or `nanoarrow_schema`",
+        )
+    }
+}
 
-            return Ok(field);
+impl FromArrowRobj for DataType {
+    fn from_arrow_robj(robj: &Robj) -> Result<Self, ErrArrowRobj> {
+        Self::from_arrow_robj_with(robj, &ImportOptions::default())
+    }
+
+    fn from_arrow_robj_with(robj: &Robj, options: &ImportOptions) -> Result<Self, ErrArrowRobj> {
+        if robj.inherits("nanoarrow_schema") || robj.inherits("DataType") {
+            return schema_from_raw(robj, options, |s| {
+                if options.restore_types {
+                    return import_field(s, options).map(|f| f.data_type().clone());
+                }
+                DataType::try_from(s)
+            });
         }
 
-        let is_field = robj.inherits("Field");
+        fallback(
+            robj,
+            options,
+            as_nanoarrow_schema,
+            "did not find a `DataType` or `nanoarrow_schema`",
+        )
+    }
+}
+
+impl FromArrowRobj for Schema {
+    fn from_arrow_robj(robj: &Robj) -> Result<Self, ErrArrowRobj> {
+        Self::from_arrow_robj_with(robj, &ImportOptions::default())
+    }
 
-        if !(is_field) {
-            return Err(ErrArrowRobj::ParseError(
-                "did not find a `Field` or `nanoarrow_schema`".into(),
-            ));
+    fn from_arrow_robj_with(robj: &Robj, options: &ImportOptions) -> Result<Self, ErrArrowRobj> {
+        if robj.inherits("nanoarrow_schema") || robj.inherits("Schema") {
+            return schema_from_raw(robj, options, |s| {
+                let schema = schema_from_ffi(s)?;
+                if !options.restore_types {
+                    return Ok(schema);
+                }
+                let fields = schema
+                    .fields()
+                    .iter()
+                    .map(|f| restore_field(f))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Schema::new(fields).with_metadata(schema.metadata().clone()))
+            });
         }
 
-        let export_to_c = robj
-            .dollar("export_to_c")
-            .expect("export_to_c() method to be available")
-            .as_function()
-            .unwrap();
+        // only calls `get_schema` so the stream is still usable in R
+        if robj.inherits("nanoarrow_array_stream") {
+            #[cfg(feature = "nanoarrow")]
+            let schema = call_nanoarrow(
+                "nanoarrow::infer_nanoarrow_schema",
+                robj,
+                "nanoarrow_schema",
+            )?;
+            #[cfg(not(feature = "nanoarrow"))]
+            let schema = native::stream_schema(robj).map_err(r_error)?;
+
+            return Schema::from_arrow_robj_with(&schema, options);
+        }
 
-        let c_schema = FFI_ArrowSchema::empty();
-        let c_schema_ptr = &c_schema as *const FFI_ArrowSchema as usize;
+        // `{arrow}` tabular objects and readers have a `$schema` field
+        let is_tabular = ["RecordBatchReader", "Table", "RecordBatch"]
+            .iter()
+            .any(|cls| robj.inherits(cls));
 
-        let _ = export_to_c.call(pairlist!(c_schema_ptr.to_string()));
-        let field = Field::try_from(&c_schema)?;
+        if is_tabular {
+            let schema = robj.dollar("schema").map_err(r_error)?;
+            return Schema::from_arrow_robj_with(&schema, options);
+        }
 
-        Ok(field)
+        fallback(
+            robj,
+            options,
+            as_nanoarrow_schema,
+            "did not find a `Schema` or `nanoarrow_schema`",
+        )
     }
 }
 
-impl FromArrowRobj for DataType {
+// https://github.com/apache/arrow-rs/blob/200e8c80084442d9579e00967e407cd83191565d/arrow/src/pyarrow.rs#L248
+impl FromArrowRobj for ArrayData {
     fn from_arrow_robj(robj: &Robj) -> Result<Self, ErrArrowRobj> {
-        if robj.inherits("nanoarrow_schema") {
-            let c_schema = FFI_ArrowSchema::empty();
-            let c_schema_ptr = &c_schema as *const FFI_ArrowSchema as usize;
-
-            let _ = nanoarrow_export(robj, c_schema_ptr.to_string());
+        Self::from_arrow_robj_with(robj, &ImportOptions::default())
+    }
 
-            let field = DataType::try_from(&c_schema)?;
+    fn from_arrow_robj_with(robj: &Robj, options: &ImportOptions) -> Result<Self, ErrArrowRobj> {
+        if robj.inherits("nanoarrow_device_array") {
+            return DeviceArray::from_arrow_robj_with(robj, options).map(|x| x.0);
+        }
 
-            return Ok(field);
+        // a polars Series is exported as a single column stream
+        if robj.inherits("RPolarsSeries") {
+            let batch = RecordBatch::from_arrow_robj_with(robj, options)?;
+            return Ok(batch.column(0).to_data());
         }
 
-        let is_datatype = robj.inherits("DataType");
+        if robj.inherits("nanoarrow_array") || robj.inherits("Array") {
+            let mut array = FFI_ArrowArray::empty();
+            let mut schema = FFI_ArrowSchema::empty();
 
-        if !(is_datatype) {
-            return Err(ErrArrowRobj::ParseError(
-                "did not find a `DataType` or `nanoarrow_schema`".into(),
-            ));
+            let c_array_ptr = &mut array as *mut FFI_ArrowArray;
+            let c_schema_ptr = &mut schema as *mut FFI_ArrowSchema;
+
+            unsafe { raw::array_from_robj(robj, c_array_ptr.cast(), c_schema_ptr.cast()) }
+                .map_err(r_error)?;
+
+            let data = unsafe { ffi::from_ffi(array, &schema)? };
+            let restored = if options.restore_types {
+                Some(import_field(&schema, options)?)
+            } else {
+                None
+            };
+
+            if options.mode == ImportMode::Share && robj.inherits("nanoarrow_array") {
+                let robj_schema = nanoarrow_array_schema(robj).map_err(r_error)?;
+                restore_pointer(&robj_schema, c_schema_ptr as usize)?;
+
+                // the new array keeps the imported buffers alive
+                let mut shared = FFI_ArrowArray::new(&data);
+                restore_pointer(robj, &mut shared as *mut FFI_ArrowArray as usize)?;
+            }
+
+            if let Some(field) = restored {
+                return Ok(restore_array(&make_array(data), field.data_type())?.to_data());
+            }
+
+            return Ok(data);
         }
 
-        let export_to_c = robj
-            .dollar("export_to_c")
-            .expect("export_to_c() method to be available")
-            .as_function()
-            .unwrap();
+        fallback(
+            robj,
+            options,
+            as_nanoarrow_array,
+            "did not find a `Array` or `nanoarrow_array`",
+        )
+    }
+}
 
-        let c_schema = FFI_ArrowSchema::empty();
-        let c_schema_ptr = &c_schema as *const FFI_ArrowSchema as usize;
+/// Imports a length-1 array as a `Scalar` that can be passed to compute kernels as a `Datum`
+///
+/// Accepts length-1 `nanoarrow_array`s, `{arrow}` `Scalar`s and length-1
+/// logical, integer, double or character vectors.
+impl FromArrowRobj for Scalar<ArrayRef> {
+    fn from_arrow_robj(robj: &Robj) -> Result<Self, ErrArrowRobj> {
+        Self::from_arrow_robj_with(robj, &ImportOptions::default())
+    }
 
-        let _ = export_to_c.call(pairlist!(c_schema_ptr.to_string()));
-        let data_type = DataType::try_from(&c_schema)?;
+    fn from_arrow_robj_with(robj: &Robj, options: &ImportOptions) -> Result<Self, ErrArrowRobj> {
+        let data = if robj.inherits("Scalar") {
+            let array = robj
+                .dollar("as_array")
+                .and_then(|f| f.as_function().ok_or(Error::ExpectedFunction(f)))
+                .and_then(|f| f.call(pairlist!()))
+                .map_err(r_error)?;
+            ArrayData::from_arrow_robj_with(&array, options)?
+        } else if robj.class().is_none()
+            && (robj.is_logical() || robj.is_integer() || robj.is_real() || robj.is_string())
+        {
+            ArrayData::from_arrow_robj_with(&as_nanoarrow_array(robj)?, options)?
+        } else {
+            ArrayData::from_arrow_robj_with(robj, options)?
+        };
+
+        if data.len() != 1 {
+            return Err(ErrArrowRobj::ParseError(format!(
+                "a scalar must have length 1, found length {}",
+                data.len()
+            )));
+        }
 
-        Ok(data_type)
+        Ok(Scalar::new(make_array(data)))
     }
 }
 
-impl FromArrowRobj for Schema {
+/// If there are more than one RecordBatches in the stream, do not use this
+/// Use ArrowStreamReader instead
+impl FromArrowRobj for RecordBatch {
     fn from_arrow_robj(robj: &Robj) -> Result<Self, ErrArrowRobj> {
-        if robj.inherits("nanoarrow_schema") {
-            let c_schema = FFI_ArrowSchema::empty();
-            let c_schema_ptr = &c_schema as *const FFI_ArrowSchema as usize;
+        Self::from_arrow_robj_with(robj, &ImportOptions::default())
+    }
+
+    fn from_arrow_robj_with(robj: &Robj, options: &ImportOptions) -> Result<Self, ErrArrowRobj> {
+        let batch = record_batch_from_robj(robj, options)?;
 
-            let _ = nanoarrow_export(robj, c_schema_ptr.to_string());
+        if options.restore_types {
+            return restore_batch(&batch);
+        }
 
-            let field = Schema::try_from(&c_schema)?;
+        Ok(batch)
+    }
+}
 
-            return Ok(field);
+fn record_batch_from_robj(
+    robj: &Robj,
+    options: &ImportOptions,
+) -> Result<RecordBatch, ErrArrowRobj> {
+    // polars objects are chunked so every chunk is combined into one batch
+    if polars::is_polars(robj) {
+        let reader = ArrowArrayStreamReader::from_arrow_robj_with(robj, options)?;
+        let schema = reader.schema();
+        let batches = reader.collect::<Result<Vec<_>, _>>()?;
+        return concat_batches(&schema, &batches);
+    }
+
+    // struct arrays, e.g. from `ToArrowArrayRobj`
+    if robj.inherits("nanoarrow_array") {
+        // the schema metadata is the metadata of the struct field
+        let schema = nanoarrow_array_schema(robj).map_err(r_error)?;
+        let metadata = Field::from_arrow_robj_shared(&schema)?.metadata().clone();
+        let data = ArrayData::from_arrow_robj_with(robj, options)?;
+
+        if !matches!(data.data_type(), DataType::Struct(_)) {
+            return Err(ErrArrowRobj::ParseError(
+                "`nanoarrow_array` must be a struct to create a `RecordBatch`".into(),
+            ));
         }
 
-        let is_schema = robj.inherits("Schema");
+        let array = StructArray::from(data);
 
-        if !(is_schema) {
+        if array.null_count() > 0 {
             return Err(ErrArrowRobj::ParseError(
-                "did not find a `Schema` or `nanoarrow_schema`".into(),
+                "cannot create a `RecordBatch` from a struct with nulls".into(),
             ));
         }
 
-        let export_to_c = robj
-            .dollar("export_to_c")
-            .expect("export_to_c() method to be available")
-            .as_function()
-            .unwrap();
+        let batch = RecordBatch::from(array);
+        let schema = batch.schema_ref().as_ref().clone().with_metadata(metadata);
+        return batch.with_schema(Arc::new(schema));
+    }
 
-        let c_schema = FFI_ArrowSchema::empty();
-        let c_schema_ptr = &c_schema as *const FFI_ArrowSchema as usize;
+    if robj.inherits("nanoarrow_array_stream") {
+        let mut reader = ArrowArrayStreamReader::from_arrow_robj_with(robj, options)?;
 
-        let _ = export_to_c.call(pairlist!(c_schema_ptr.to_string()));
-        let schema = Schema::try_from(&c_schema)?;
+        return reader.next().unwrap_or_else(|| {
+            Err(ErrArrowRobj::ParseError(
+                "`nanoarrow_array_stream` has no batches".into(),
+            ))
+        });
+    }
+
+    let is_rb = robj.inherits("RecordBatch");
+
+    if !is_rb {
+        return fallback(
+            robj,
+            options,
+            as_nanoarrow_array_stream,
+            "did not find a `RecordBatch` or `nanoarrow_array_stream`",
+        );
+    }
+
+    // the batch is exported as a struct array along with its schema
+    let mut array = FFI_ArrowArray::empty();
+    let mut schema = FFI_ArrowSchema::empty();
 
-        Ok(schema)
+    unsafe {
+        raw::array_from_robj(
+            robj,
+            (&mut array as *mut FFI_ArrowArray).cast(),
+            (&mut schema as *mut FFI_ArrowSchema).cast(),
+        )
     }
+    .map_err(r_error)?;
+
+    let res = unsafe { ffi::from_ffi(array, &schema)? };
+    let schema = schema_from_ffi(&schema)?;
+
+    let res_arrays = res
+        .child_data()
+        .iter()
+        .map(|xi| make_array(xi.clone()))
+        .collect::<Vec<_>>();
+
+    let res = RecordBatch::try_new(schema.into(), res_arrays)?;
+
+    Ok(res)
 }
 
-// https://github.com/apache/arrow-rs/blob/200e8c80084442d9579e00967e407cd83191565d/arrow/src/pyarrow.rs#L248
-impl FromArrowRobj for ArrayData {
+impl FromArrowRobj for ArrowArrayStreamReader {
     fn from_arrow_robj(robj: &Robj) -> Result<Self, ErrArrowRobj> {
-        if robj.inherits("nanoarrow_array") {
-            let array = FFI_ArrowArray::empty();
-            let schema = FFI_ArrowSchema::empty();
+        Self::from_arrow_robj_with(robj, &ImportOptions::default())
+    }
 
-            let c_array_ptr = &array as *const FFI_ArrowArray as usize;
-            let c_schema_ptr = &schema as *const FFI_ArrowSchema as usize;
+    fn from_arrow_robj_with(robj: &Robj, options: &ImportOptions) -> Result<Self, ErrArrowRobj> {
+        // we need to allocate an empty schema and fetch it from the record batch
+        let mut stream = FFI_ArrowArrayStream::empty();
+        let c_stream_ptr = (&mut stream as *mut FFI_ArrowArrayStream).cast();
 
-            let robj_schema = R!("nanoarrow::infer_nanoarrow_schema")
-                .unwrap()
-                .as_function()
-                .unwrap()
-                .call(pairlist!(robj))
-                .expect("unable to infer nanoarrow schema");
+        if robj.inherits("nanoarrow_array_stream") {
+            unsafe { raw::stream_from_robj(robj, c_stream_ptr) }.map_err(r_error)?;
+            let reader = ArrowArrayStreamReader::try_new(stream)?;
 
-            let _ = nanoarrow_export(robj, c_array_ptr.to_string());
-            let _ = nanoarrow_export(&robj_schema, c_schema_ptr.to_string());
+            if options.mode == ImportMode::Share {
+                return share_stream(robj, reader);
+            }
 
-            return unsafe { ffi::from_ffi(array, &schema) };
+            return Ok(reader);
         }
 
-        let is_array = robj.inherits("Array");
+        // CPU device streams are re-exported as a regular stream
+        if robj.inherits("nanoarrow_device_array_stream") {
+            let reader = DeviceArrayStreamReader::from_arrow_robj_with(robj, options)?;
+            let reader: Box<dyn RecordBatchReader + Send> = Box::new(reader);
+            return ArrowArrayStreamReader::try_new(FFI_ArrowArrayStream::new(reader));
+        }
 
-        if !is_array {
-            return Err(ErrArrowRobj::ParseError("did not find a `Array`".into()));
+        if polars::is_polars(robj) {
+            let stream = polars::polars_to_stream(robj)?;
+            return ArrowArrayStreamReader::from_arrow_robj_with(&stream, options);
         }
 
-        // prepare a pointer to receive the Array struct
-        let array = FFI_ArrowArray::empty();
-        let schema = FFI_ArrowSchema::empty();
+        // an `{arrow}` Table is read through a RecordBatchReader
+        let reader = if robj.inherits("Table") {
+            r_function("arrow::as_record_batch_reader")
+                .and_then(|f| f.call(pairlist!(robj)))
+                .map_err(r_error)?
+        } else if robj.inherits("RecordBatchReader") {
+            robj.clone()
+        } else {
+            return fallback(
+                robj,
+                options,
+                as_nanoarrow_array_stream,
+                "did not find a `nanoarrow_array_stream`, `RecordBatchReader`, or `Table`",
+            );
+        };
+
+        unsafe { raw::stream_from_robj(&reader, c_stream_ptr) }.map_err(r_error)?;
 
-        let c_array_ptr = &array as *const FFI_ArrowArray as usize;
-        let c_schema_ptr = &schema as *const FFI_ArrowSchema as usize;
+        ArrowArrayStreamReader::try_new(stream)
+    }
+}
+
+/// Collects every `RecordBatch` from a stream
+///
+/// Accepts the same objects as `ArrowArrayStreamReader`. The stream is
+/// drained immediately on the calling (R) thread so the batches can be handed
+/// to code running on other threads, e.g. registered as an in-memory table
+/// with a query engine.
+impl FromArrowRobj for Vec<RecordBatch> {
+    fn from_arrow_robj(robj: &Robj) -> Result<Self, ErrArrowRobj> {
+        Self::from_arrow_robj_with(robj, &ImportOptions::default())
+    }
 
-        let export_to_c = robj
-            .dollar("export_to_c")
-            .expect("export_to_c() method to be available")
-            .as_function()
-            .unwrap();
+    fn from_arrow_robj_with(robj: &Robj, options: &ImportOptions) -> Result<Self, ErrArrowRobj> {
+        let batches = ArrowArrayStreamReader::from_arrow_robj_with(robj, options)?;
 
-        let _ = export_to_c.call(pairlist!(c_array_ptr.to_string(), c_schema_ptr.to_string()));
+        if options.restore_types {
+            return batches.map(|batch| restore_batch(&batch?)).collect();
+        }
 
-        unsafe { ffi::from_ffi(array, &schema) }
+        batches.collect()
     }
 }
 
-/// If there are more than one RecordBatches in the stream, do not use this
-/// Use ArrowStreamReader instead
-impl FromArrowRobj for RecordBatch {
-    fn from_arrow_robj(robj: &Robj) -> Result<Self, ErrArrowRobj> {
-        if robj.inherits("nanoarrow_array_stream") {
-            // we need to allocate an empty schema and fetch it from the record batch
-            let stream = ffi_stream::FFI_ArrowArrayStream::empty();
-            let c_stream_ptr = &stream as *const FFI_ArrowArrayStream as usize;
+/// Converts an `FFI_ArrowSchema` into a `Field` that keeps the dictionary ordered flag
+///
+/// `Field::try_from()` drops the flag, which holds the `ordered` attribute of R
+/// factors, so it is restored here and in the children of nested types.
+pub(crate) fn field_from_ffi(c_schema: &FFI_ArrowSchema) -> Result<Field, ErrArrowRobj> {
+    let field = Field::try_from(c_schema)?;
+
+    let child = || field_from_ffi(c_schema.child(0)).map(Arc::new);
+    let data_type = match field.data_type() {
+        DataType::Struct(_) => DataType::Struct(
+            c_schema
+                .children()
+                .map(field_from_ffi)
+                .collect::<Result<Vec<_>, _>>()?
+                .into(),
+        ),
+        DataType::List(_) => DataType::List(child()?),
+        DataType::LargeList(_) => DataType::LargeList(child()?),
+        DataType::ListView(_) => DataType::ListView(child()?),
+        DataType::LargeListView(_) => DataType::LargeListView(child()?),
+        DataType::FixedSizeList(_, size) => DataType::FixedSizeList(child()?, *size),
+        DataType::Map(_, sorted) => DataType::Map(child()?, *sorted),
+        data_type => data_type.clone(),
+    };
+
+    if !c_schema.dictionary_ordered() {
+        return Ok(field.with_data_type(data_type));
+    }
 
-            let _ = nanoarrow_export(robj, c_stream_ptr.to_string());
+    let ordered = Field::new_dict(field.name(), data_type, field.is_nullable(), 0, true);
+    Ok(ordered.with_metadata(field.metadata().clone()))
+}
 
-            let res = ArrowArrayStreamReader::try_new(stream)?;
-            let r2 = res.into_iter().map(|xi| xi.unwrap()).nth(0).unwrap();
+/// Converts an `FFI_ArrowSchema` into a `Schema` whose fields keep the dictionary ordered flag
+pub(crate) fn schema_from_ffi(c_schema: &FFI_ArrowSchema) -> Result<Schema, ErrArrowRobj> {
+    let schema = Schema::try_from(c_schema)?;
+    let fields = c_schema
+        .children()
+        .map(field_from_ffi)
+        .collect::<Result<Vec<_>, _>>()?;
 
-            return Ok(r2);
-        }
+    Ok(Schema::new(fields).with_metadata(schema.metadata().clone()))
+}
 
-        let is_rb = robj.inherits("RecordBatch");
+/// Converts an `FFI_ArrowSchema` into a `Field`, restoring rewritten types if requested
+fn import_field(
+    c_schema: &FFI_ArrowSchema,
+    options: &ImportOptions,
+) -> Result<Field, ErrArrowRobj> {
+    let field = field_from_ffi(c_schema)?;
 
-        if !is_rb {
-            return Err(ErrArrowRobj::ParseError(
-                "did not find a `RecordBatch` or `nanoarrow_array_stream`".into(),
-            ));
-        }
+    if options.restore_types {
+        return restore_field(&field);
+    }
 
-        // we need to allocate an empty schema and fetch it from the record batch
-        let array = FFI_ArrowArray::empty();
-        let schema = FFI_ArrowSchema::empty();
+    Ok(field)
+}
 
-        let c_array_ptr = &array as *const FFI_ArrowArray as usize;
-        let c_schema_ptr = &schema as *const FFI_ArrowSchema as usize;
+/// Moves the schema of `robj` into an `FFI_ArrowSchema` and converts it with `convert`
+///
+/// With `ImportMode::Share` the schema is moved back into a `nanoarrow_schema`
+/// afterwards.
+fn schema_from_raw<T>(
+    robj: &Robj,
+    options: &ImportOptions,
+    convert: impl FnOnce(&FFI_ArrowSchema) -> Result<T, ErrArrowRobj>,
+) -> Result<T, ErrArrowRobj> {
+    let mut c_schema = FFI_ArrowSchema::empty();
+    let c_schema_ptr = &mut c_schema as *mut FFI_ArrowSchema;
+
+    unsafe { raw::schema_from_robj(robj, c_schema_ptr.cast()) }.map_err(r_error)?;
+
+    let res = convert(&c_schema)?;
+
+    if options.mode == ImportMode::Share && robj.inherits("nanoarrow_schema") {
+        restore_pointer(robj, c_schema_ptr as usize)?;
+    }
 
-        let export_to_c = robj
-            .dollar("export_to_c")
-            .expect("export_to_c() method to be available")
-            .as_function()
-            .unwrap();
+    Ok(res)
+}
 
-        let _ = export_to_c.call(pairlist!(c_array_ptr.to_string(), c_schema_ptr.to_string()));
+/// Imports an object of an unknown class by first converting it with `{nanoarrow}`
+///
+/// Returns a `ParseError` with `msg` when `options.strict` is set.
+fn fallback<T: FromArrowRobj>(
+    robj: &Robj,
+    options: &ImportOptions,
+    convert: fn(&Robj) -> Result<Robj, ErrArrowRobj>,
+    msg: &str,
+) -> Result<T, ErrArrowRobj> {
+    if options.strict {
+        return Err(ErrArrowRobj::ParseError(msg.into()));
+    }
 
-        let res = unsafe { ffi::from_ffi(array, &schema)? };
-        let schema = Schema::try_from(&schema)?;
+    T::from_arrow_robj_with(&convert(robj)?, options)
+}
 
-        let res_arrays = res
-            .child_data()
-            .into_iter()
-            .map(|xi| make_array(xi.clone()))
-            .collect::<Vec<_>>();
+/// Calls an R function with a single argument and checks the class of the result
+///
+/// Prevents infinite recursion when an S3 method returns an unexpected object.
+fn call_nanoarrow(fname: &str, robj: &Robj, class: &str) -> Result<Robj, ErrArrowRobj> {
+    let res = r_function(fname)
+        .and_then(|f| f.call(pairlist!(robj)))
+        .map_err(r_error)?;
+
+    if !res.inherits(class) {
+        return Err(ErrArrowRobj::ParseError(format!(
+            "`{fname}()` did not return a `{class}`"
+        )));
+    }
 
-        let res = RecordBatch::try_new(schema.into(), res_arrays)?;
+    Ok(res)
+}
 
-        Ok(res)
+/// Converts an R object into a `nanoarrow_schema`
+///
+/// Calls `nanoarrow::as_nanoarrow_schema()`. If the object has no method, its
+/// schema is inferred with `nanoarrow::infer_nanoarrow_schema()`, e.g. for a
+/// `data.frame`. When both fail the error names both causes.
+///
+/// Requires `{nanoarrow}` to be installed.
+pub fn as_nanoarrow_schema(robj: &Robj) -> Result<Robj, ErrArrowRobj> {
+    if robj.inherits("nanoarrow_schema") {
+        return Ok(robj.clone());
     }
+
+    call_nanoarrow("nanoarrow::as_nanoarrow_schema", robj, "nanoarrow_schema").or_else(|e| {
+        call_nanoarrow(
+            "nanoarrow::infer_nanoarrow_schema",
+            robj,
+            "nanoarrow_schema",
+        )
+        .map_err(|infer_e| {
+            ErrArrowRobj::ParseError(format!(
+                "unable to convert to a `nanoarrow_schema`: {e}; inferring the schema failed as well: {infer_e}"
+            ))
+        })
+    })
 }
 
-impl FromArrowRobj for ArrowArrayStreamReader {
-    fn from_arrow_robj(robj: &Robj) -> Result<Self, ErrArrowRobj> {
-        // TODO arrow::RecordBatchStreamWriter
-        if !robj.inherits("nanoarrow_array_stream") {
-            return Err(ErrArrowRobj::ParseError(
-                "did not find `nanoarrow_array_stream`".into(),
-            ));
-        }
-        // we need to allocate an empty schema and fetch it from the record batch
-        let stream = ffi_stream::FFI_ArrowArrayStream::empty();
-        let c_stream_ptr = &stream as *const FFI_ArrowArrayStream as usize;
+/// Converts an R object into a `nanoarrow_array`
+///
+/// Calls `nanoarrow::as_nanoarrow_array()`, e.g. for atomic vectors and
+/// `data.frame`s.
+///
+/// Requires `{nanoarrow}` to be installed.
+pub fn as_nanoarrow_array(robj: &Robj) -> Result<Robj, ErrArrowRobj> {
+    if robj.inherits("nanoarrow_array") {
+        return Ok(robj.clone());
+    }
 
-        let _ = nanoarrow_export(robj, c_stream_ptr.to_string());
+    call_nanoarrow("nanoarrow::as_nanoarrow_array", robj, "nanoarrow_array")
+}
 
-        ArrowArrayStreamReader::try_new(stream)
+/// Converts an R object into a `nanoarrow_array_stream`
+///
+/// ADBC statements (`adbc_statement`) are executed and duckdb results
+/// (`duckdb_result`) are fetched as a `RecordBatchReader`. Every other object
+/// is passed to `nanoarrow::as_nanoarrow_array_stream()` so that any class with
+/// an `as_nanoarrow_array_stream()` S3 method can be imported.
+///
+/// Requires `{nanoarrow}` to be installed.
+pub fn as_nanoarrow_array_stream(robj: &Robj) -> Result<Robj, ErrArrowRobj> {
+    if robj.inherits("nanoarrow_array_stream") {
+        return Ok(robj.clone());
+    }
+
+    if robj.inherits("adbc_statement") {
+        let stream = allocate_array_stream(pairlist!()).map_err(r_error)?;
+
+        r_function("adbcdrivermanager::adbc_statement_execute_query")
+            .and_then(|f| f.call(pairlist!(robj, &stream)))
+            .map_err(r_error)?;
+
+        return Ok(stream);
     }
+
+    if robj.inherits("duckdb_result") {
+        let reader = r_function("duckdb::duckdb_fetch_record_batch")
+            .and_then(|f| f.call(pairlist!(robj)))
+            .map_err(r_error)?;
+
+        return as_nanoarrow_array_stream(&reader);
+    }
+
+    call_nanoarrow(
+        "nanoarrow::as_nanoarrow_array_stream",
+        robj,
+        "nanoarrow_array_stream",
+    )
+}
+
+/// Moves the C struct at `ptr` into a `{nanoarrow}` R object released by an export
+///
+/// Used by `ImportMode::Share`. Does nothing if `robj` is still valid, i.e. the
+/// export copied the data rather than moving it.
+pub(crate) fn restore_pointer(robj: &Robj, ptr: usize) -> Result<(), ErrArrowRobj> {
+    #[cfg(feature = "nanoarrow")]
+    let is_valid = r_function("nanoarrow::nanoarrow_pointer_is_valid")
+        .and_then(|f| f.call(pairlist!(robj)))
+        .map_err(r_error)?
+        .as_bool()
+        == Some(true);
+    #[cfg(not(feature = "nanoarrow"))]
+    let is_valid = native::pointer_is_valid(robj);
+
+    if is_valid {
+        return Ok(());
+    }
+
+    move_pointer(pairlist!(ptr.to_string(), robj)).map_err(r_error)?;
+
+    Ok(())
+}
+
+/// Collects a stream and moves a stream over the same batches back into `robj`
+///
+/// Holds every batch in memory, see `ImportMode::Share`.
+fn share_stream(
+    robj: &Robj,
+    reader: ArrowArrayStreamReader,
+) -> Result<ArrowArrayStreamReader, ErrArrowRobj> {
+    let schema = reader.schema();
+    let batches = reader.collect::<Result<Vec<_>, _>>()?;
+
+    let restored = batches.clone().into_iter().map(Ok);
+    let restored: Box<dyn RecordBatchReader + Send> =
+        Box::new(RecordBatchIterator::new(restored, schema.clone()));
+    let mut restored = FFI_ArrowArrayStream::new(restored);
+    restore_pointer(robj, &mut restored as *mut FFI_ArrowArrayStream as usize)?;
+
+    let batches: Box<dyn RecordBatchReader + Send> = Box::new(RecordBatchIterator::new(
+        batches.into_iter().map(Ok),
+        schema,
+    ));
+    ArrowArrayStreamReader::try_new(FFI_ArrowArrayStream::new(batches))
+}
+
+/// Converts an error raised by an R function call into an `ErrArrowRobj`
+pub(crate) fn r_error(e: Error) -> ErrArrowRobj {
+    ErrArrowRobj::ExternalError(e.to_string().into())
 }
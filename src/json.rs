@@ -0,0 +1,171 @@
+//! Read and write newline delimited JSON files as R array streams
+//!
+//! Requires the `json` feature.
+//!
+//! ```ignore
+//! #[extendr]
+//! fn read_ndjson_stream(path: &str) -> Result<Robj> {
+//!     read_ndjson(path, None, &JsonReadOptions::default())
+//! }
+//! ```
+//!
+//! Like the `csv` module, the returned `nanoarrow_array_stream` is lazy and
+//! the schema is inferred when one is not provided.
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Seek, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use arrow::{
+    datatypes::Schema,
+    ffi_stream::ArrowArrayStreamReader,
+    json::{reader::infer_json_schema, LineDelimitedWriter, ReaderBuilder},
+    record_batch::RecordBatchReader,
+};
+use extendr_api::prelude::*;
+
+use crate::{
+    from::{ErrArrowRobj, FromArrowRobj},
+    to::{arrow_error, IntoArrowRobj},
+};
+
+/// Options used when reading a newline delimited JSON file
+#[derive(Debug, Clone)]
+pub struct JsonReadOptions {
+    pub batch_size: usize,
+    /// Number of records used for schema inference. `None` reads the whole file.
+    pub max_infer_records: Option<usize>,
+}
+
+impl Default for JsonReadOptions {
+    fn default() -> Self {
+        Self {
+            batch_size: 1024,
+            max_infer_records: Some(1000),
+        }
+    }
+}
+
+/// Create a `RecordBatchReader` over a newline delimited JSON file
+///
+/// `schema` can be any `Robj` supported by `Schema::from_arrow_robj()`. If it
+/// is `None` the schema is inferred from the file.
+pub fn ndjson_reader<P: AsRef<Path>>(
+    path: P,
+    schema: Option<&Robj>,
+    options: &JsonReadOptions,
+) -> std::result::Result<Box<dyn RecordBatchReader + Send>, ErrArrowRobj> {
+    let mut file = BufReader::new(File::open(path)?);
+
+    let schema = match schema {
+        Some(robj) => Schema::from_arrow_robj(robj)?,
+        None => {
+            let (schema, _) = infer_json_schema(&mut file, options.max_infer_records)?;
+            file.rewind()?;
+            schema
+        }
+    };
+
+    let reader = ReaderBuilder::new(Arc::new(schema))
+        .with_batch_size(options.batch_size)
+        .build(file)?;
+
+    Ok(Box::new(reader))
+}
+
+/// Read a newline delimited JSON file into a lazy `nanoarrow_array_stream`
+pub fn read_ndjson<P: AsRef<Path>>(
+    path: P,
+    schema: Option<&Robj>,
+    options: &JsonReadOptions,
+) -> Result<Robj> {
+    ndjson_reader(path, schema, options)
+        .map_err(arrow_error)?
+        .into_arrow_robj()
+}
+
+/// Write every batch of an imported stream to a newline delimited JSON file
+pub fn write_ndjson<P: AsRef<Path>>(
+    reader: ArrowArrayStreamReader,
+    path: P,
+) -> std::result::Result<(), ErrArrowRobj> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut writer = LineDelimitedWriter::new(file);
+
+    for batch in reader {
+        writer.write(&batch?)?;
+    }
+
+    writer.finish()?;
+    writer.into_inner().flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::{datatypes::DataType, ffi_stream::FFI_ArrowArrayStream};
+
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("arrow-extendr-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn infers_schema_and_reads_lazily() {
+        let path = temp_path("infer.ndjson");
+        std::fs::write(
+            &path,
+            "{\"a\":1,\"b\":\"x\"}\n{\"a\":2,\"b\":\"y\"}\n{\"a\":3}\n",
+        )
+        .unwrap();
+
+        let options = JsonReadOptions {
+            batch_size: 2,
+            ..Default::default()
+        };
+        let reader = ndjson_reader(&path, None, &options).unwrap();
+
+        let schema = reader.schema();
+        assert_eq!(
+            schema.field_with_name("a").unwrap().data_type(),
+            &DataType::Int64
+        );
+        assert_eq!(
+            schema.field_with_name("b").unwrap().data_type(),
+            &DataType::Utf8
+        );
+
+        let rows = reader
+            .map(|batch| batch.unwrap().num_rows())
+            .collect::<Vec<_>>();
+        assert_eq!(rows, vec![2, 1]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn writes_every_batch_of_a_stream() {
+        let input = temp_path("input.ndjson");
+        let output = temp_path("output.ndjson");
+        std::fs::write(&input, "{\"a\":1}\n{\"a\":2}\n").unwrap();
+
+        let options = JsonReadOptions {
+            batch_size: 1,
+            ..Default::default()
+        };
+        let reader = ndjson_reader(&input, None, &options).unwrap();
+        let stream = ArrowArrayStreamReader::try_new(FFI_ArrowArrayStream::new(reader)).unwrap();
+
+        write_ndjson(stream, &output).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            "{\"a\":1}\n{\"a\":2}\n"
+        );
+
+        std::fs::remove_file(input).unwrap();
+        std::fs::remove_file(output).unwrap();
+    }
+}
@@ -60,5 +60,34 @@
 //! #> Found 143 rows
 //! #> [1] 2959
 //! ```
+pub mod backend;
+pub mod batches;
+pub mod callback;
+pub mod chunked;
+pub mod device;
+pub mod factor;
 pub mod from;
+pub mod metadata;
+pub mod native;
+pub mod polars;
+pub mod prefetch;
+pub mod raw;
+pub mod rewrite;
 pub mod to;
+pub mod versions;
+pub mod views;
+
+#[cfg(feature = "r-arrow")]
+pub mod arrow_r6;
+#[cfg(feature = "async")]
+pub mod async_stream;
+#[cfg(feature = "compute")]
+pub mod compute;
+#[cfg(feature = "csv")]
+pub mod csv;
+#[cfg(feature = "datafusion")]
+pub mod datafusion;
+#[cfg(feature = "json")]
+pub mod json;
+#[cfg(feature = "rayon")]
+pub mod parallel;
@@ -0,0 +1,386 @@
+//! Read and write key/value metadata of schemas and fields
+//!
+//! Imports and exports keep the metadata of `Schema` and `Field`, including
+//! the schema metadata of a `RecordBatch` imported from a struct
+//! `nanoarrow_array`. The functions below read it from R objects without
+//! importing any data, and write it to `{nanoarrow}` objects without copying.
+//!
+//! |         function        |                             R objects                              |
+//! | ----------------------- | ------------------------------------------------------------------ |
+//! | `schema_metadata()`     | any object with a schema, e.g. `nanoarrow_array`, streams, `Table` |
+//! | `field_metadata()`      | any object with a schema, e.g. `nanoarrow_array`, streams, `Table` |
+//! | `set_schema_metadata()` | `nanoarrow_schema` or `nanoarrow_array`                            |
+//! | `set_field_metadata()`  | `nanoarrow_schema` or `nanoarrow_array`                            |
+//!
+//! Streams are read with `Schema::from_arrow_robj_shared()` so they are not
+//! consumed. Writing returns a new `nanoarrow_schema`, or the same
+//! `nanoarrow_array` with its schema replaced.
+//!
+//! When the batches of a `Vec<RecordBatch>` have different metadata, the
+//! exported stream uses a single schema chosen by `MetadataConflict`, see
+//! `ExportOptions` and `unify_metadata()`.
+//!
+//! ```ignore
+//! fn tag(x: Robj) -> Result<Robj> {
+//!     let mut metadata = schema_metadata(&x).map_err(arrow_error)?;
+//!     metadata.insert("source".into(), "rust".into());
+//!     set_schema_metadata(&x, metadata).map_err(arrow_error)
+//! }
+//! ```
+use std::{collections::HashMap, sync::Arc};
+
+use arrow::{
+    datatypes::{DataType, Field, Schema},
+    ffi::FFI_ArrowSchema,
+    record_batch::RecordBatch,
+};
+use extendr_api::prelude::*;
+
+use crate::{
+    from::{nanoarrow_array_schema, r_error, ErrArrowRobj, FromArrowRobj},
+    raw,
+    to::{set_array_schema, MetadataConflict},
+};
+
+/// Reads the schema of an R object without consuming it
+///
+/// The schema of a `nanoarrow_array` or `nanoarrow_schema` is read as a
+/// `Field` so that non-struct types are accepted. Its fields are the children
+/// of a struct, or the field itself otherwise.
+fn read_schema(robj: &Robj) -> std::result::Result<Schema, ErrArrowRobj> {
+    let schema = if robj.inherits("nanoarrow_array") {
+        nanoarrow_array_schema(robj).map_err(r_error)?
+    } else if robj.inherits("nanoarrow_schema") {
+        robj.clone()
+    } else {
+        return Schema::from_arrow_robj_shared(robj);
+    };
+
+    let field = Field::from_arrow_robj_shared(&schema)?;
+    let fields = match field.data_type() {
+        DataType::Struct(fields) => fields.clone(),
+        _ => vec![field.clone()].into(),
+    };
+
+    Ok(Schema::new(fields).with_metadata(field.metadata().clone()))
+}
+
+/// Reads the schema metadata of an R object
+pub fn schema_metadata(robj: &Robj) -> std::result::Result<HashMap<String, String>, ErrArrowRobj> {
+    Ok(read_schema(robj)?.metadata().clone())
+}
+
+/// Reads the metadata of the field `name` of an R object
+pub fn field_metadata(
+    robj: &Robj,
+    name: &str,
+) -> std::result::Result<HashMap<String, String>, ErrArrowRobj> {
+    Ok(read_schema(robj)?.field_with_name(name)?.metadata().clone())
+}
+
+/// Replaces the schema of a `nanoarrow_schema` or `nanoarrow_array` with `f(field)`
+///
+/// The schema is read with `Field::from_arrow_robj_shared()`, which keeps the
+/// ordered flags of nested dictionaries, so only what `f` changes differs.
+fn update_schema(
+    robj: &Robj,
+    f: impl FnOnce(Field) -> std::result::Result<Field, ErrArrowRobj>,
+) -> std::result::Result<Robj, ErrArrowRobj> {
+    let schema = if robj.inherits("nanoarrow_array") {
+        nanoarrow_array_schema(robj).map_err(r_error)?
+    } else if robj.inherits("nanoarrow_schema") {
+        robj.clone()
+    } else {
+        return Err(ErrArrowRobj::InvalidArgumentError(
+            "metadata can only be written to a `nanoarrow_schema` or `nanoarrow_array`".into(),
+        ));
+    };
+
+    let field = f(Field::from_arrow_robj_shared(&schema)?)?;
+    let mut ffi_schema = FFI_ArrowSchema::try_from(&field)?;
+    let new_schema =
+        unsafe { raw::schema_into_robj((&mut ffi_schema as *mut FFI_ArrowSchema).cast()) }
+            .map_err(r_error)?;
+
+    if robj.inherits("nanoarrow_array") {
+        set_array_schema(robj, &new_schema);
+        return Ok(robj.clone());
+    }
+
+    Ok(new_schema)
+}
+
+/// Replaces the schema metadata of a `nanoarrow_schema` or `nanoarrow_array`
+pub fn set_schema_metadata(
+    robj: &Robj,
+    metadata: HashMap<String, String>,
+) -> std::result::Result<Robj, ErrArrowRobj> {
+    update_schema(robj, |field| Ok(field.with_metadata(metadata)))
+}
+
+/// Replaces the metadata of the field `name` of a struct `nanoarrow_schema` or `nanoarrow_array`
+pub fn set_field_metadata(
+    robj: &Robj,
+    name: &str,
+    metadata: HashMap<String, String>,
+) -> std::result::Result<Robj, ErrArrowRobj> {
+    update_schema(robj, |field| with_field_metadata(field, name, metadata))
+}
+
+/// Replaces the metadata of the child `name` of a struct `field`
+fn with_field_metadata(
+    field: Field,
+    name: &str,
+    metadata: HashMap<String, String>,
+) -> std::result::Result<Field, ErrArrowRobj> {
+    let DataType::Struct(fields) = field.data_type() else {
+        return Err(ErrArrowRobj::InvalidArgumentError(format!(
+            "expected a struct to set the metadata of field `{name}`, found {}",
+            field.data_type()
+        )));
+    };
+
+    if !fields.iter().any(|f| f.name() == name) {
+        return Err(ErrArrowRobj::SchemaError(format!(
+            "unable to find field `{name}`"
+        )));
+    }
+
+    let fields = fields
+        .iter()
+        .map(|f| {
+            if f.name() == name {
+                Arc::new(f.as_ref().clone().with_metadata(metadata.clone()))
+            } else {
+                f.clone()
+            }
+        })
+        .collect::<Vec<_>>();
+
+    Ok(field
+        .clone()
+        .with_data_type(DataType::Struct(fields.into())))
+}
+
+/// Combines the metadata of `metadata` into one map according to `conflict`
+fn merge_metadata<'a>(
+    mut metadata: impl Iterator<Item = &'a HashMap<String, String>>,
+    conflict: MetadataConflict,
+    what: &str,
+) -> std::result::Result<HashMap<String, String>, ErrArrowRobj> {
+    let mut merged = metadata.next().cloned().unwrap_or_default();
+
+    for other in metadata {
+        match conflict {
+            MetadataConflict::First => {}
+            MetadataConflict::Merge => {
+                for (key, value) in other {
+                    merged.entry(key.clone()).or_insert_with(|| value.clone());
+                }
+            }
+            MetadataConflict::Error if other != &merged => {
+                return Err(ErrArrowRobj::SchemaError(format!(
+                    "batches have different {what} metadata"
+                )));
+            }
+            MetadataConflict::Error => {}
+        }
+    }
+
+    Ok(merged)
+}
+
+/// Gives every batch the same schema and field metadata according to `conflict`
+///
+/// With `MetadataConflict::Merge` earlier batches win when a key has different
+/// values. Batches must otherwise have the same fields.
+pub fn unify_metadata(
+    batches: Vec<RecordBatch>,
+    conflict: MetadataConflict,
+) -> std::result::Result<Vec<RecordBatch>, ErrArrowRobj> {
+    let Some(first) = batches.first().map(|batch| batch.schema()) else {
+        return Ok(batches);
+    };
+
+    if batches.iter().all(|batch| batch.schema() == first) {
+        return Ok(batches);
+    }
+
+    if batches
+        .iter()
+        .any(|batch| batch.num_columns() != first.fields().len())
+    {
+        return Err(ErrArrowRobj::SchemaError(
+            "batches have a different number of columns".into(),
+        ));
+    }
+
+    let fields = first
+        .fields()
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            let metadata = batches
+                .iter()
+                .map(|batch| batch.schema_ref().field(i).metadata());
+            let metadata =
+                merge_metadata(metadata, conflict, &format!("field `{}`", field.name()))?;
+            Ok(field.as_ref().clone().with_metadata(metadata))
+        })
+        .collect::<std::result::Result<Vec<_>, ErrArrowRobj>>()?;
+
+    let metadata = merge_metadata(
+        batches.iter().map(|batch| batch.schema_ref().metadata()),
+        conflict,
+        "schema",
+    )?;
+    let schema = Arc::new(Schema::new(fields).with_metadata(metadata));
+
+    batches
+        .into_iter()
+        .map(|batch| RecordBatch::try_new(schema.clone(), batch.columns().to_vec()))
+        .collect()
+}
+
+/// Converts metadata into a named R character vector
+pub fn metadata_to_robj(metadata: &HashMap<String, String>) -> Result<Robj> {
+    let mut keys = metadata.keys().collect::<Vec<_>>();
+    keys.sort();
+
+    let mut robj: Robj =
+        Strings::from_values(keys.iter().map(|key| metadata[*key].as_str())).into();
+    robj.set_names(keys.iter().map(|key| key.as_str()))?;
+
+    Ok(robj)
+}
+
+/// Converts a named R character vector or list of strings into metadata
+pub fn metadata_from_robj(robj: &Robj) -> Result<HashMap<String, String>> {
+    let Some(names) = robj.names() else {
+        return Ok(HashMap::new());
+    };
+
+    let values = if let Some(list) = robj.as_list() {
+        list.values()
+            .map(|value| {
+                value
+                    .as_str()
+                    .map(str::to_string)
+                    .ok_or_else(|| Error::Other("metadata values must be strings".into()))
+            })
+            .collect::<Result<Vec<_>>>()?
+    } else {
+        robj.as_str_vector()
+            .ok_or_else(|| Error::Other("metadata must be a named character vector".into()))?
+            .into_iter()
+            .map(str::to_string)
+            .collect()
+    };
+
+    Ok(names.map(str::to_string).zip(values).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::array::{ArrayRef, Int32Array};
+
+    use super::*;
+    use crate::from::field_from_ffi;
+
+    fn metadata(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn batch(schema: &[(&str, &str)], field: &[(&str, &str)]) -> RecordBatch {
+        let field = Field::new("x", DataType::Int32, false).with_metadata(metadata(field));
+        let schema = Schema::new(vec![field]).with_metadata(metadata(schema));
+        let column: ArrayRef = Arc::new(Int32Array::from(vec![1, 2]));
+        RecordBatch::try_new(Arc::new(schema), vec![column]).unwrap()
+    }
+
+    #[test]
+    fn merges_metadata_by_conflict() {
+        let maps = [metadata(&[("a", "1")]), metadata(&[("a", "2"), ("b", "3")])];
+
+        let first = merge_metadata(maps.iter(), MetadataConflict::First, "schema").unwrap();
+        assert_eq!(first, maps[0]);
+
+        let merged = merge_metadata(maps.iter(), MetadataConflict::Merge, "schema").unwrap();
+        assert_eq!(merged, metadata(&[("a", "1"), ("b", "3")]));
+
+        let error = merge_metadata(maps.iter(), MetadataConflict::Error, "schema").unwrap_err();
+        assert!(error.to_string().contains("different schema metadata"));
+    }
+
+    #[test]
+    fn unifies_schema_and_field_metadata_of_batches() {
+        let batches = vec![
+            batch(&[("source", "a")], &[("unit", "m")]),
+            batch(&[("source", "b"), ("rows", "2")], &[]),
+        ];
+
+        let unified = unify_metadata(batches.clone(), MetadataConflict::Merge).unwrap();
+        for batch in &unified {
+            assert_eq!(
+                batch.schema().metadata(),
+                &metadata(&[("source", "a"), ("rows", "2")])
+            );
+            assert_eq!(
+                batch.schema().field(0).metadata(),
+                &metadata(&[("unit", "m")])
+            );
+        }
+
+        let error = unify_metadata(batches, MetadataConflict::Error).unwrap_err();
+        assert!(error.to_string().contains("different field `x` metadata"));
+    }
+
+    #[test]
+    fn keeps_nested_ordered_dictionaries_when_setting_field_metadata() {
+        let ordered = Field::new_dict(
+            "item",
+            DataType::Dictionary(Box::new(DataType::Int8), Box::new(DataType::Utf8)),
+            true,
+            0,
+            true,
+        );
+        let list = Field::new("levels", DataType::List(Arc::new(ordered)), true);
+        let field = Field::new_struct(
+            "",
+            vec![list, Field::new("y", DataType::Int32, true)],
+            false,
+        )
+        .with_metadata(metadata(&[("source", "r")]));
+
+        // the schema goes through the C data interface as in `update_schema()`
+        let imported = field_from_ffi(&FFI_ArrowSchema::try_from(&field).unwrap()).unwrap();
+        assert_eq!(imported, field);
+
+        let updated = with_field_metadata(imported, "y", metadata(&[("unit", "m")])).unwrap();
+        let round_trip = field_from_ffi(&FFI_ArrowSchema::try_from(&updated).unwrap()).unwrap();
+        assert_eq!(round_trip, updated);
+
+        let DataType::Struct(fields) = round_trip.data_type() else {
+            panic!("expected a struct");
+        };
+        let DataType::List(item) = fields[0].data_type() else {
+            panic!("expected a list");
+        };
+        assert_eq!(item.dict_is_ordered(), Some(true));
+        assert_eq!(fields[1].metadata(), &metadata(&[("unit", "m")]));
+        assert_eq!(round_trip.metadata(), &metadata(&[("source", "r")]));
+    }
+
+    #[test]
+    fn errors_for_missing_fields() {
+        let field = Field::new_struct("", vec![Field::new("y", DataType::Int32, true)], false);
+        let error = with_field_metadata(field, "z", HashMap::new()).unwrap_err();
+        assert!(error.to_string().contains("unable to find field `z`"));
+
+        let field = Field::new("y", DataType::Int32, true);
+        assert!(with_field_metadata(field, "y", HashMap::new()).is_err());
+    }
+}
@@ -0,0 +1,525 @@
+//! Create and read `{nanoarrow}` objects without calling into `{nanoarrow}`
+//!
+//! `nanoarrow_schema`, `nanoarrow_array` and `nanoarrow_array_stream` objects
+//! are external pointers to the C data interface structs with a class
+//! attribute. The schema of a `nanoarrow_array` is stored in the tag of its
+//! external pointer. This module works with that layout directly so that no R
+//! package needs to be installed. Objects created here can be used by
+//! `{nanoarrow}` and vice versa.
+//!
+//! Arrays and streams, including `nanoarrow_device_array` and
+//! `nanoarrow_device_array_stream`, are moved out of the R object when
+//! exported and the R object is released afterwards. Schemas are deep copied as C structs so they
+//! stay valid and keep every flag, including formats arrow-rs cannot parse.
+//!
+//! Used by `Backend::Native`, and by the other backends that do not call
+//! `{nanoarrow}`.
+//!
+//! A few conversions still call `{nanoarrow}` with every backend because they
+//! need an R method that only `{nanoarrow}` or the other package provides:
+//!
+//! - objects of other classes passed to `FromArrowRobj`, which are converted
+//!   with `as_nanoarrow_schema()`, `as_nanoarrow_array()` or
+//!   `as_nanoarrow_array_stream()` unless `ImportOptions::strict` is set
+//! - `{polars}` objects, which are exported with `as_nanoarrow_array_stream()`,
+//!   see the `polars` module
+//! - allocating `nanoarrow_device_array` and `nanoarrow_device_array_stream`
+//!   objects and exporting regular arrays as device arrays, see the `device`
+//!   module
+use std::ffi::{c_char, c_void, CStr, CString};
+
+use arrow::{
+    ffi::{FFI_ArrowArray, FFI_ArrowSchema},
+    ffi_stream::FFI_ArrowArrayStream,
+};
+use extendr_api::{prelude::*, R_ExternalPtrAddr, SEXP};
+
+use crate::{
+    device::{FFI_ArrowDeviceArray, FFI_ArrowDeviceArrayStream},
+    raw::ArrowSchema,
+};
+
+// part of the R API but not re-exported by extendr. `SEXP`s are passed as
+// `*mut c_void` because `SEXPREC` is opaque
+extern "C" {
+    static R_NilValue: *mut c_void;
+    fn R_MakeExternalPtr(p: *mut c_void, tag: *mut c_void, prot: *mut c_void) -> *mut c_void;
+    fn R_ClearExternalPtr(s: *mut c_void);
+    fn R_SetExternalPtrTag(s: *mut c_void, tag: *mut c_void);
+}
+
+/// The memory owned by a schema created by `copy_schema()`
+struct SchemaCopy {
+    format: CString,
+    name: Option<CString>,
+    metadata: Option<Box<[u8]>>,
+    children: Box<[*mut ArrowSchema]>,
+}
+
+/// Deep copies a schema that has not been released
+///
+/// `FFI_ArrowSchema` keeps its fields private so the schema is read as a
+/// `raw::ArrowSchema`.
+///
+/// # Safety
+///
+/// `source` must be a valid `ArrowSchema`.
+unsafe fn copy_schema(source: &ArrowSchema) -> ArrowSchema {
+    let name = (!source.name.is_null()).then(|| CStr::from_ptr(source.name).to_owned());
+    let metadata = (!source.metadata.is_null()).then(|| copy_metadata(source.metadata));
+    let mut children = (0..source.n_children as usize)
+        .map(|i| Box::into_raw(Box::new(copy_schema(&**source.children.add(i)))))
+        .collect::<Box<[_]>>();
+    let dictionary = if source.dictionary.is_null() {
+        std::ptr::null_mut()
+    } else {
+        Box::into_raw(Box::new(copy_schema(&*source.dictionary)))
+    };
+
+    let mut copy = Box::new(SchemaCopy {
+        format: CStr::from_ptr(source.format).to_owned(),
+        name,
+        metadata,
+        children: Box::new([]),
+    });
+    let children_ptr = if children.is_empty() {
+        std::ptr::null_mut()
+    } else {
+        children.as_mut_ptr()
+    };
+    copy.children = children;
+
+    ArrowSchema {
+        format: copy.format.as_ptr(),
+        name: copy
+            .name
+            .as_ref()
+            .map_or(std::ptr::null(), |name| name.as_ptr()),
+        metadata: copy
+            .metadata
+            .as_ref()
+            .map_or(std::ptr::null(), |metadata| metadata.as_ptr().cast()),
+        flags: source.flags,
+        n_children: source.n_children,
+        children: children_ptr,
+        dictionary,
+        release: Some(release_schema_copy),
+        private_data: Box::into_raw(copy).cast(),
+    }
+}
+
+/// Copies metadata encoded as `int32` pair count, then length-prefixed keys and values
+unsafe fn copy_metadata(metadata: *const c_char) -> Box<[u8]> {
+    let read_i32 =
+        |offset: usize| std::ptr::read_unaligned(metadata.add(offset).cast::<i32>()) as usize;
+
+    let mut len = 4;
+    for _ in 0..read_i32(0) * 2 {
+        len += 4 + read_i32(len);
+    }
+
+    std::slice::from_raw_parts(metadata.cast::<u8>(), len).into()
+}
+
+unsafe extern "C" fn release_schema_copy(schema: *mut ArrowSchema) {
+    if schema.is_null() {
+        return;
+    }
+    let schema = &mut *schema;
+    let copy = Box::from_raw(schema.private_data.cast::<SchemaCopy>());
+
+    for child in copy.children.iter().chain([&schema.dictionary]) {
+        if child.is_null() {
+            continue;
+        }
+        if let Some(release) = (**child).release {
+            release(*child);
+        }
+        drop(Box::from_raw(*child));
+    }
+
+    schema.release = None;
+    schema.private_data = std::ptr::null_mut();
+}
+
+/// Allocates an empty `nanoarrow_schema`
+pub fn allocate_schema() -> Result<Robj> {
+    allocate(FFI_ArrowSchema::empty(), "nanoarrow_schema")
+}
+
+/// Allocates an empty `nanoarrow_array`
+pub fn allocate_array() -> Result<Robj> {
+    allocate(FFI_ArrowArray::empty(), "nanoarrow_array")
+}
+
+/// Allocates an empty `nanoarrow_array_stream`
+pub fn allocate_array_stream() -> Result<Robj> {
+    allocate(FFI_ArrowArrayStream::empty(), "nanoarrow_array_stream")
+}
+
+fn allocate<T>(value: T, class: &str) -> Result<Robj> {
+    let ptr = Box::into_raw(Box::new(value));
+
+    let mut robj = single_threaded(|| unsafe {
+        Robj::from_sexp(R_MakeExternalPtr(ptr.cast(), R_NilValue, R_NilValue).cast())
+    });
+
+    unsafe { robj.register_c_finalizer(Some(finalize::<T>)) };
+    robj.set_class([class])?;
+
+    Ok(robj)
+}
+
+// dropping the struct calls its release callback if it has not been moved
+extern "C" fn finalize<T>(x: SEXP) {
+    unsafe {
+        let ptr = R_ExternalPtrAddr(x).cast::<T>();
+
+        if !ptr.is_null() {
+            drop(Box::from_raw(ptr));
+            R_ClearExternalPtr(x.cast());
+        }
+    }
+}
+
+/// The C struct held by a `{nanoarrow}` object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PointerKind {
+    Schema,
+    Array,
+    ArrayStream,
+    DeviceArray,
+    DeviceArrayStream,
+}
+
+impl PointerKind {
+    // device classes first in case they also inherit the regular ones
+    const CLASSES: [(&'static str, PointerKind); 5] = [
+        ("nanoarrow_device_array", PointerKind::DeviceArray),
+        (
+            "nanoarrow_device_array_stream",
+            PointerKind::DeviceArrayStream,
+        ),
+        ("nanoarrow_schema", PointerKind::Schema),
+        ("nanoarrow_array", PointerKind::Array),
+        ("nanoarrow_array_stream", PointerKind::ArrayStream),
+    ];
+
+    fn of(robj: &Robj) -> Option<Self> {
+        Self::CLASSES
+            .iter()
+            .find(|(cls, _)| robj.inherits(cls))
+            .map(|(_, kind)| *kind)
+    }
+
+    /// Checks if the struct at `addr` has not been released
+    ///
+    /// # Safety
+    ///
+    /// `addr` must point to a struct of this kind.
+    unsafe fn is_valid(self, addr: usize) -> bool {
+        match self {
+            PointerKind::Schema => (*(addr as *const ArrowSchema)).release.is_some(),
+            PointerKind::Array => !(*(addr as *const FFI_ArrowArray)).is_released(),
+            PointerKind::ArrayStream => (*(addr as *const FFI_ArrowArrayStream)).release.is_some(),
+            PointerKind::DeviceArray => {
+                !(*(addr as *const FFI_ArrowDeviceArray)).array.is_released()
+            }
+            PointerKind::DeviceArrayStream => (*(addr as *const FFI_ArrowDeviceArrayStream))
+                .release
+                .is_some(),
+        }
+    }
+
+    /// Moves the struct at `source` to the empty struct at `dest`
+    ///
+    /// # Safety
+    ///
+    /// Both addresses must point to structs of this kind and `dest` must be empty.
+    unsafe fn move_to(self, source: usize, dest: usize) {
+        match self {
+            PointerKind::Schema => move_struct(
+                source as *mut FFI_ArrowSchema,
+                dest as *mut _,
+                FFI_ArrowSchema::empty(),
+            ),
+            PointerKind::Array => move_struct(
+                source as *mut FFI_ArrowArray,
+                dest as *mut _,
+                FFI_ArrowArray::empty(),
+            ),
+            PointerKind::ArrayStream => move_struct(
+                source as *mut FFI_ArrowArrayStream,
+                dest as *mut _,
+                FFI_ArrowArrayStream::empty(),
+            ),
+            PointerKind::DeviceArray => move_struct(
+                source as *mut FFI_ArrowDeviceArray,
+                dest as *mut _,
+                FFI_ArrowDeviceArray::empty(),
+            ),
+            PointerKind::DeviceArrayStream => move_struct(
+                source as *mut FFI_ArrowDeviceArrayStream,
+                dest as *mut _,
+                FFI_ArrowDeviceArrayStream::empty(),
+            ),
+        }
+    }
+}
+
+/// The address of the C struct of a `{nanoarrow}` object
+pub fn pointer_addr(robj: &Robj) -> Result<usize> {
+    pointer(robj).map(|(_, addr)| addr)
+}
+
+fn pointer(robj: &Robj) -> Result<(PointerKind, usize)> {
+    let kind = PointerKind::of(robj).filter(|_| robj.rtype() == Rtype::ExternalPtr);
+
+    let Some(kind) = kind else {
+        return Err(Error::Other(
+            "expected a `nanoarrow_schema`, `nanoarrow_array`, `nanoarrow_array_stream`, \
+             `nanoarrow_device_array` or `nanoarrow_device_array_stream`"
+                .into(),
+        ));
+    };
+
+    let addr = unsafe { R_ExternalPtrAddr(robj.get()) } as usize;
+
+    if addr == 0 {
+        return Err(Error::Other("external pointer is null".into()));
+    }
+
+    Ok((kind, addr))
+}
+
+/// Checks if the C struct of a `{nanoarrow}` object has not been released
+pub fn pointer_is_valid(robj: &Robj) -> bool {
+    let Ok((kind, addr)) = pointer(robj) else {
+        return false;
+    };
+
+    unsafe { kind.is_valid(addr) }
+}
+
+/// Exports the C struct of a `{nanoarrow}` object to the empty struct at `dest`
+///
+/// Schemas are copied, arrays and streams are moved.
+///
+/// # Safety
+///
+/// `dest` must point to an empty struct of the type matching the class of `source`.
+pub unsafe fn export_pointer(source: &Robj, dest: usize) -> Result<()> {
+    if !pointer_is_valid(source) {
+        return Err(Error::Other(
+            "source pointer has already been released".into(),
+        ));
+    }
+
+    let (kind, addr) = pointer(source)?;
+
+    if kind == PointerKind::Schema {
+        let copy = copy_schema(&*(addr as *const ArrowSchema));
+        std::ptr::write(dest as *mut ArrowSchema, copy);
+    } else {
+        kind.move_to(addr, dest);
+    }
+
+    Ok(())
+}
+
+/// Moves the C struct at `source` into an empty `{nanoarrow}` object
+///
+/// # Safety
+///
+/// `source` must point to a struct of the type matching the class of `dest`.
+/// It is marked as released afterwards.
+pub unsafe fn move_pointer(source: usize, dest: &Robj) -> Result<()> {
+    if pointer_is_valid(dest) {
+        return Err(Error::Other("destination pointer is not empty".into()));
+    }
+
+    let (kind, addr) = pointer(dest)?;
+    kind.move_to(source, addr);
+
+    Ok(())
+}
+
+// `dest` holds an empty struct so it is overwritten without being dropped
+unsafe fn move_struct<T>(source: *mut T, dest: *mut T, empty: T) {
+    let value = std::ptr::replace(source, empty);
+    std::ptr::write(dest, value);
+}
+
+/// Reads the schema of a `nanoarrow_array_stream` into a new `nanoarrow_schema`
+///
+/// Only calls `get_schema` so the stream can still be read.
+pub fn stream_schema(stream: &Robj) -> Result<Robj> {
+    if !stream.inherits("nanoarrow_array_stream") || !pointer_is_valid(stream) {
+        return Err(Error::Other(
+            "expected a `nanoarrow_array_stream` that has not been released".into(),
+        ));
+    }
+
+    let stream = pointer_addr(stream)? as *mut FFI_ArrowArrayStream;
+    let mut schema = FFI_ArrowSchema::empty();
+
+    let ret = unsafe {
+        let get_schema = (*stream)
+            .get_schema
+            .ok_or_else(|| Error::Other("stream has no `get_schema` callback".into()))?;
+        get_schema(stream, &mut schema)
+    };
+
+    if ret != 0 {
+        return Err(Error::Other(format!(
+            "cannot get the schema of the stream, error code {ret}"
+        )));
+    }
+
+    let robj = allocate_schema()?;
+    unsafe { move_pointer(&mut schema as *mut FFI_ArrowSchema as usize, &robj)? };
+
+    Ok(robj)
+}
+
+/// Attaches a `nanoarrow_schema` to a `nanoarrow_array`
+pub fn set_array_schema(array: &Robj, schema: &Robj) -> Result<()> {
+    if !array.inherits("nanoarrow_array") || !schema.inherits("nanoarrow_schema") {
+        return Err(Error::Other(
+            "expected a `nanoarrow_array` and a `nanoarrow_schema`".into(),
+        ));
+    }
+
+    single_threaded(|| unsafe { R_SetExternalPtrTag(array.get().cast(), schema.get().cast()) });
+
+    Ok(())
+}
+
+/// The `nanoarrow_schema` attached to a `nanoarrow_array`
+pub fn array_schema(array: &Robj) -> Result<Robj> {
+    let schema = unsafe { array.external_ptr_tag() };
+
+    if !schema.inherits("nanoarrow_schema") {
+        return Err(Error::Other("`nanoarrow_array` has no schema".into()));
+    }
+
+    Ok(schema)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::{
+        array::{Array, Int32Array},
+        datatypes::{DataType, Field},
+        record_batch::{RecordBatch, RecordBatchIterator},
+    };
+
+    use crate::device::DeviceArrayStreamReader;
+
+    use super::*;
+
+    #[test]
+    fn copies_schemas_with_nested_ordered_dictionaries() {
+        let dict = DataType::Dictionary(Box::new(DataType::Int8), Box::new(DataType::Utf8));
+        let inner = Field::new_dict("f", dict, true, 0, true)
+            .with_metadata([("k".to_string(), "v".to_string())].into());
+        let field = Field::new_struct("s", vec![inner], false)
+            .with_metadata([("a".to_string(), "".to_string())].into());
+        let source = FFI_ArrowSchema::try_from(&field).unwrap();
+
+        let mut dest = FFI_ArrowSchema::empty();
+        unsafe {
+            let copy = copy_schema(&*(&source as *const FFI_ArrowSchema).cast::<ArrowSchema>());
+            std::ptr::write(
+                (&mut dest as *mut FFI_ArrowSchema).cast::<ArrowSchema>(),
+                copy,
+            );
+        }
+        drop(source);
+
+        assert_eq!(dest.name(), Some("s"));
+        assert_eq!(dest.metadata().unwrap()["a"], "");
+        assert!(!dest.nullable());
+
+        let child = dest.child(0);
+        assert_eq!(child.format(), "c");
+        assert!(child.dictionary_ordered());
+        assert_eq!(child.metadata().unwrap()["k"], "v");
+        assert_eq!(child.dictionary().unwrap().format(), "u");
+
+        assert_eq!(
+            Field::try_from(&dest).unwrap().data_type(),
+            field.data_type()
+        );
+    }
+
+    #[test]
+    fn copies_formats_that_arrow_rs_cannot_parse() {
+        let field = Field::new("x", DataType::Int32, true);
+        let source = FFI_ArrowSchema::try_from(&field).unwrap();
+        let format = CString::new("+unknown").unwrap();
+
+        unsafe {
+            let raw = &*(&source as *const FFI_ArrowSchema).cast::<ArrowSchema>();
+            let mut patched = copy_schema(raw);
+            patched.format = format.as_ptr();
+
+            let copy = copy_schema(&patched);
+            assert_eq!(CStr::from_ptr(copy.format).to_str(), Ok("+unknown"));
+
+            let mut copy = copy;
+            (copy.release.unwrap())(&mut copy);
+            (patched.release.unwrap())(&mut patched);
+        }
+    }
+
+    #[test]
+    fn moves_device_arrays() {
+        let data = Int32Array::from(vec![1, 2, 3]).into_data();
+        let mut source = FFI_ArrowDeviceArray::new_cpu(FFI_ArrowArray::new(&data));
+        let mut dest = FFI_ArrowDeviceArray::empty();
+
+        let source_addr = &mut source as *mut FFI_ArrowDeviceArray as usize;
+        let dest_addr = &mut dest as *mut FFI_ArrowDeviceArray as usize;
+
+        unsafe {
+            assert!(PointerKind::DeviceArray.is_valid(source_addr));
+            assert!(!PointerKind::DeviceArray.is_valid(dest_addr));
+
+            PointerKind::DeviceArray.move_to(source_addr, dest_addr);
+
+            assert!(!PointerKind::DeviceArray.is_valid(source_addr));
+            assert!(PointerKind::DeviceArray.is_valid(dest_addr));
+        }
+
+        let array = std::mem::replace(&mut dest.array, FFI_ArrowArray::empty());
+        let schema = FFI_ArrowSchema::try_from(data.data_type()).unwrap();
+        let moved = unsafe { arrow::ffi::from_ffi(array, &schema) }.unwrap();
+        assert_eq!(moved, data);
+    }
+
+    #[test]
+    fn moves_device_array_streams() {
+        let column = Arc::new(Int32Array::from(vec![1, 2]));
+        let batch = RecordBatch::try_from_iter([("x", column as _)]).unwrap();
+        let batches = RecordBatchIterator::new(vec![Ok(batch.clone())], batch.schema());
+
+        let mut source = FFI_ArrowDeviceArrayStream::new(Box::new(batches));
+        let mut dest = FFI_ArrowDeviceArrayStream::empty();
+
+        let source_addr = &mut source as *mut FFI_ArrowDeviceArrayStream as usize;
+        let dest_addr = &mut dest as *mut FFI_ArrowDeviceArrayStream as usize;
+
+        unsafe {
+            PointerKind::DeviceArrayStream.move_to(source_addr, dest_addr);
+            assert!(!PointerKind::DeviceArrayStream.is_valid(source_addr));
+            assert!(PointerKind::DeviceArrayStream.is_valid(dest_addr));
+        }
+
+        let reader = DeviceArrayStreamReader::try_new(dest).unwrap();
+        let batches = reader.collect::<std::result::Result<Vec<_>, _>>().unwrap();
+        assert_eq!(batches, vec![batch]);
+    }
+}
@@ -0,0 +1,180 @@
+//! Process the batches of an imported stream in parallel
+//!
+//! Requires the `rayon` feature.
+//!
+//! Batches are pulled from R on the calling thread, `window` at a time, and
+//! then mapped in parallel on the rayon thread pool. Results keep the order of
+//! the input stream and at most `window` batches are held in memory.
+//!
+//! ```ignore
+//! #[extendr]
+//! fn add_one(stream: Robj) -> Result<Robj> {
+//!     let reader = ArrowArrayStreamReader::from_arrow_robj(&stream).map_err(arrow_error)?;
+//!     let schema = reader.schema();
+//!
+//!     MapBatchesReader::new(reader, schema, 8, |batch| {
+//!         // expensive work here
+//!         Ok(batch)
+//!     })
+//!     .into_arrow_robj()
+//! }
+//! ```
+use std::collections::VecDeque;
+
+use arrow::{
+    datatypes::SchemaRef,
+    ffi_stream::ArrowArrayStreamReader,
+    record_batch::{RecordBatch, RecordBatchReader},
+};
+use extendr_api::prelude::*;
+use rayon::prelude::*;
+
+use crate::{from::ErrArrowRobj, to::IntoArrowRobj};
+
+type BatchResult = std::result::Result<RecordBatch, ErrArrowRobj>;
+
+/// Maps every batch of `reader` in parallel and collects the results in order
+///
+/// Must be called on the R main thread.
+pub fn map_batches<F>(
+    reader: ArrowArrayStreamReader,
+    window: usize,
+    f: F,
+) -> std::result::Result<Vec<RecordBatch>, ErrArrowRobj>
+where
+    F: Fn(RecordBatch) -> BatchResult + Send + Sync + 'static,
+{
+    let schema = reader.schema();
+    MapBatchesReader::new(reader, schema, window, f).collect()
+}
+
+/// A `RecordBatchReader` that maps an imported stream in parallel
+///
+/// Input batches are only pulled when the output is read, so it can be
+/// returned to R as a lazy `nanoarrow_array_stream` with `into_arrow_robj()`.
+/// `schema` is the schema of the batches returned by `f`.
+pub struct MapBatchesReader<F> {
+    reader: ArrowArrayStreamReader,
+    schema: SchemaRef,
+    window: usize,
+    f: F,
+    output: VecDeque<BatchResult>,
+    done: bool,
+}
+
+impl<F> MapBatchesReader<F>
+where
+    F: Fn(RecordBatch) -> BatchResult + Send + Sync + 'static,
+{
+    pub fn new(reader: ArrowArrayStreamReader, schema: SchemaRef, window: usize, f: F) -> Self {
+        Self {
+            reader,
+            schema,
+            window: window.max(1),
+            f,
+            output: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    /// Pulls the next window of batches from R and maps them in parallel
+    ///
+    /// An error from R ends the stream after the batches pulled before it.
+    fn fill(&mut self) {
+        let mut input = Vec::with_capacity(self.window);
+        let mut error = None;
+
+        while input.len() < self.window {
+            match self.reader.next() {
+                Some(Ok(batch)) => input.push(batch),
+                Some(Err(e)) => {
+                    error = Some(e);
+                    self.done = true;
+                    break;
+                }
+                None => {
+                    self.done = true;
+                    break;
+                }
+            }
+        }
+
+        let f = &self.f;
+        let mapped: Vec<BatchResult> = input.into_par_iter().map(f).collect();
+        self.output.extend(mapped);
+        self.output.extend(error.map(Err));
+    }
+}
+
+impl<F> Iterator for MapBatchesReader<F>
+where
+    F: Fn(RecordBatch) -> BatchResult + Send + Sync + 'static,
+{
+    type Item = BatchResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.output.is_empty() && !self.done {
+            self.fill();
+        }
+
+        self.output.pop_front()
+    }
+}
+
+impl<F> RecordBatchReader for MapBatchesReader<F>
+where
+    F: Fn(RecordBatch) -> BatchResult + Send + Sync + 'static,
+{
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
+
+impl<F> IntoArrowRobj for MapBatchesReader<F>
+where
+    F: Fn(RecordBatch) -> BatchResult + Send + Sync + 'static,
+{
+    fn into_arrow_robj(self) -> Result<Robj> {
+        let reader: Box<dyn RecordBatchReader + Send> = Box::new(self);
+        reader.into_arrow_robj()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::{
+        array::Int32Array,
+        datatypes::{DataType, Field, Schema},
+        error::ArrowError,
+        ffi_stream::FFI_ArrowArrayStream,
+        record_batch::RecordBatchIterator,
+    };
+
+    use super::*;
+
+    #[test]
+    fn yields_pulled_batches_before_an_error() {
+        let schema = Arc::new(Schema::new(vec![Field::new("x", DataType::Int32, false)]));
+        let batch =
+            RecordBatch::try_new(schema.clone(), vec![Arc::new(Int32Array::from(vec![1, 2]))])
+                .unwrap();
+        let input = vec![
+            Ok(batch.clone()),
+            Ok(batch),
+            Err(ArrowError::ComputeError("boom".into())),
+        ];
+        let input = RecordBatchIterator::new(input, schema.clone());
+        let reader =
+            ArrowArrayStreamReader::try_new(FFI_ArrowArrayStream::new(Box::new(input))).unwrap();
+
+        // the error is pulled within the first window
+        let output = MapBatchesReader::new(reader, schema, 8, Ok).collect::<Vec<_>>();
+
+        assert_eq!(output.len(), 3);
+        assert_eq!(output[0].as_ref().unwrap().num_rows(), 2);
+        assert_eq!(output[1].as_ref().unwrap().num_rows(), 2);
+        assert!(output[2].as_ref().unwrap_err().to_string().contains("boom"));
+    }
+}
@@ -0,0 +1,195 @@
+//! Interop with `{polars}` R objects
+//!
+//! `RPolarsDataFrame`, `RPolarsLazyFrame` and `RPolarsSeries` objects are
+//! accepted by the `FromArrowRobj` impls for `RecordBatch`, `ArrayData`,
+//! `ArrowArrayStreamReader` and `Vec<RecordBatch>`. They are exported through
+//! the Arrow C stream interface via `nanoarrow::as_nanoarrow_array_stream()`.
+//! A `RPolarsLazyFrame` is collected first.
+//!
+//! The traits `ToPolarsRobj` and `IntoPolarsRobj` go the other way. Arrays
+//! are converted into a `RPolarsSeries`, and batches and streams into a
+//! `RPolarsDataFrame`. They work with the `{nanoarrow}` objects of the
+//! `nanoarrow` and `native` backends as well as the `{arrow}` R6 objects of
+//! the `r-arrow` backend.
+//!
+//! |            arrow-rs struct             |      R object      |
+//! | -------------------------------------- | ------------------ |
+//! | `ArrayData`, `PrimitiveArray<T>`       | `RPolarsSeries`    |
+//! | `RecordBatch`, `Vec<RecordBatch>`      | `RPolarsDataFrame` |
+//! | `RecordBatches`                        | `RPolarsDataFrame` |
+//! | `ArrowArrayStreamReader` (`Into` only) | `RPolarsDataFrame` |
+//! | `RecordBatchIterator<I>` (`Into` only) | `RPolarsDataFrame` |
+//!
+//! ```ignore
+//! fn batch_to_polars(batch: RecordBatch) -> Result<Robj> {
+//!     batch.to_polars_robj()
+//! }
+//! ```
+//!
+//! **Requires `polars` and `nanoarrow` to be installed**.
+use extendr_api::prelude::*;
+
+use arrow::{
+    array::{ArrayData, PrimitiveArray},
+    datatypes::ArrowPrimitiveType,
+    error::ArrowError,
+    ffi_stream::ArrowArrayStreamReader,
+    record_batch::{RecordBatch, RecordBatchIterator, RecordBatchReader},
+};
+
+use crate::{
+    backend::r_function,
+    batches::RecordBatches,
+    from::ErrArrowRobj,
+    to::{IntoArrowRobj, ToArrowRobj},
+};
+
+/// R classes of `{polars}` objects that can be imported
+pub const POLARS_CLASSES: [&str; 3] = ["RPolarsDataFrame", "RPolarsLazyFrame", "RPolarsSeries"];
+
+/// R classes that `as_polars()` converts into a `RPolarsDataFrame`
+const DATAFRAME_CLASSES: [&str; 4] = [
+    "nanoarrow_array_stream",
+    "RecordBatchReader",
+    "RecordBatch",
+    "Table",
+];
+
+/// R classes that `as_polars()` converts into a `RPolarsSeries`
+const SERIES_CLASSES: [&str; 3] = ["nanoarrow_array", "Array", "ChunkedArray"];
+
+/// Checks if an `Robj` is a `{polars}` object that can be imported
+pub fn is_polars(robj: &Robj) -> bool {
+    POLARS_CLASSES.iter().any(|cls| robj.inherits(cls))
+}
+
+/// Calls a method of a `{polars}` R6-like object with no arguments
+fn call_method(robj: &Robj, method: &str) -> std::result::Result<Robj, ErrArrowRobj> {
+    robj.dollar(method)
+        .and_then(|f| f.as_function().ok_or(Error::ExpectedFunction(f)))
+        .and_then(|f| f.call(pairlist!()))
+        .map_err(|e| ErrArrowRobj::ExternalError(e.to_string().into()))
+}
+
+/// Converts a `{polars}` object into a `nanoarrow_array_stream`
+///
+/// A `RPolarsSeries` becomes a single column stream named after the series.
+pub fn polars_to_stream(robj: &Robj) -> std::result::Result<Robj, ErrArrowRobj> {
+    let df = if robj.inherits("RPolarsLazyFrame") {
+        call_method(robj, "collect")?
+    } else if robj.inherits("RPolarsSeries") {
+        call_method(robj, "to_frame")?
+    } else if robj.inherits("RPolarsDataFrame") {
+        robj.clone()
+    } else {
+        return Err(ErrArrowRobj::ParseError(
+            "did not find a `RPolarsDataFrame`, `RPolarsLazyFrame`, or `RPolarsSeries`".into(),
+        ));
+    };
+
+    r_function("nanoarrow::as_nanoarrow_array_stream")
+        .and_then(|f| f.call(pairlist!(df)))
+        .map_err(|e| ErrArrowRobj::ExternalError(e.to_string().into()))
+}
+
+/// Calls `polars::as_polars_df()` or `polars::as_polars_series()`
+///
+/// `nanoarrow_array_stream`s and `{arrow}` `RecordBatchReader`s, `RecordBatch`es
+/// and `Table`s become a `RPolarsDataFrame`. `nanoarrow_array`s and `{arrow}`
+/// `Array`s and `ChunkedArray`s become a `RPolarsSeries`.
+pub fn as_polars(robj: Robj) -> Result<Robj> {
+    let f = if DATAFRAME_CLASSES.iter().any(|cls| robj.inherits(cls)) {
+        r_function("polars::as_polars_df")?
+    } else if SERIES_CLASSES.iter().any(|cls| robj.inherits(cls)) {
+        r_function("polars::as_polars_series")?
+    } else {
+        return Err(Error::Other(
+            "only `{nanoarrow}` and `{arrow}` arrays, batches and streams can be converted to polars"
+                .into(),
+        ));
+    };
+
+    f.call(pairlist!(robj))
+}
+
+/// Convert an Arrow array, batch or stream to a `{polars}` object
+///
+/// Does not consume `self`.
+pub trait ToPolarsRobj {
+    fn to_polars_robj(&self) -> Result<Robj>;
+}
+
+/// Convert an Arrow array, batch or stream to a `{polars}` object
+///
+/// Consumes `self`.
+pub trait IntoPolarsRobj {
+    fn into_polars_robj(self) -> Result<Robj>;
+}
+
+// macro to implement both traits for those that have `ToArrowRobj` implemented
+macro_rules! impl_polars {
+    ($t:ty) => {
+        impl ToPolarsRobj for $t {
+            fn to_polars_robj(&self) -> Result<Robj> {
+                as_polars(self.to_arrow_robj()?)
+            }
+        }
+
+        impl IntoPolarsRobj for $t {
+            fn into_polars_robj(self) -> Result<Robj> {
+                as_polars(self.into_arrow_robj()?)
+            }
+        }
+    };
+}
+
+impl_polars!(ArrayData);
+impl_polars!(RecordBatch);
+impl_polars!(RecordBatches);
+
+// macro doesn't permit generics
+impl<T: ArrowPrimitiveType> ToPolarsRobj for PrimitiveArray<T> {
+    fn to_polars_robj(&self) -> Result<Robj> {
+        as_polars(self.to_arrow_robj()?)
+    }
+}
+
+impl<T: ArrowPrimitiveType> IntoPolarsRobj for PrimitiveArray<T> {
+    fn into_polars_robj(self) -> Result<Robj> {
+        as_polars(self.into_arrow_robj()?)
+    }
+}
+
+impl ToPolarsRobj for Vec<RecordBatch> {
+    fn to_polars_robj(&self) -> Result<Robj> {
+        self.clone().into_polars_robj()
+    }
+}
+
+impl IntoPolarsRobj for Vec<RecordBatch> {
+    fn into_polars_robj(self) -> Result<Robj> {
+        as_polars(self.into_arrow_robj()?)
+    }
+}
+
+impl IntoPolarsRobj for ArrowArrayStreamReader {
+    fn into_polars_robj(self) -> Result<Robj> {
+        as_polars(self.into_arrow_robj()?)
+    }
+}
+
+impl IntoPolarsRobj for Box<dyn RecordBatchReader + Send> {
+    fn into_polars_robj(self) -> Result<Robj> {
+        as_polars(self.into_arrow_robj()?)
+    }
+}
+
+impl<I> IntoPolarsRobj for RecordBatchIterator<I>
+where
+    I: IntoIterator<Item = std::result::Result<RecordBatch, ArrowError>> + Send + 'static,
+    <I as IntoIterator>::IntoIter: Send,
+{
+    fn into_polars_robj(self) -> Result<Robj> {
+        as_polars(self.into_arrow_robj()?)
+    }
+}
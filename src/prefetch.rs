@@ -0,0 +1,199 @@
+//! Produce batches on a background thread while R consumes them
+//!
+//! Streams returned by `IntoArrowRobj` compute each batch on R's thread when
+//! R calls `get_next`. A `PrefetchReader` instead runs the producer on a
+//! worker thread and buffers up to `prefetch` batches, so Rust computes the
+//! next batches while R works on the current one. The producer blocks once the
+//! buffer is full.
+//!
+//! ```ignore
+//! #[extendr]
+//! fn expensive_stream() -> Result<Robj> {
+//!     let reader: Box<dyn RecordBatchReader + Send> = make_reader();
+//!     PrefetchReader::new(reader, 4).into_arrow_robj()
+//! }
+//! ```
+//!
+//! With a `prefetch` of 0 nothing is buffered: the producer computes at most
+//! one batch ahead and waits until R takes it.
+//!
+//! Errors returned by the producer are passed to R through the stream's
+//! `get_last_error` callback. The producer must not call into R.
+use std::{
+    sync::mpsc::{sync_channel, Receiver},
+    thread::{self, JoinHandle},
+};
+
+use arrow::{
+    datatypes::SchemaRef,
+    record_batch::{RecordBatch, RecordBatchReader},
+};
+use extendr_api::prelude::*;
+
+use crate::{
+    from::ErrArrowRobj,
+    to::{ExportOptions, IntoArrowRobj},
+};
+
+type BatchResult = std::result::Result<RecordBatch, ErrArrowRobj>;
+
+/// A `RecordBatchReader` fed by a producer running on a worker thread
+pub struct PrefetchReader {
+    schema: SchemaRef,
+    rx: Receiver<BatchResult>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl PrefetchReader {
+    /// Reads `reader` on a worker thread, buffering up to `prefetch` batches
+    pub fn new(reader: Box<dyn RecordBatchReader + Send>, prefetch: usize) -> Self {
+        Self::from_iter(reader.schema(), reader, prefetch)
+    }
+
+    /// Runs any iterator of batches on a worker thread
+    pub fn from_iter<I>(schema: SchemaRef, batches: I, prefetch: usize) -> Self
+    where
+        I: IntoIterator<Item = BatchResult> + Send + 'static,
+    {
+        let (tx, rx) = sync_channel(prefetch);
+
+        let worker = thread::spawn(move || {
+            for batch in batches {
+                let is_err = batch.is_err();
+
+                // the receiver is gone when R releases the stream early
+                if tx.send(batch).is_err() || is_err {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            schema,
+            rx,
+            worker: Some(worker),
+        }
+    }
+}
+
+impl Iterator for PrefetchReader {
+    type Item = BatchResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Ok(batch) = self.rx.recv() {
+            return Some(batch);
+        }
+
+        // the producer has finished, report it if it panicked
+        let worker = self.worker.take()?;
+        match worker.join() {
+            Ok(_) => None,
+            Err(_) => Some(Err(ErrArrowRobj::ExternalError(
+                "batch producer panicked".into(),
+            ))),
+        }
+    }
+}
+
+impl RecordBatchReader for PrefetchReader {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
+
+impl IntoArrowRobj for PrefetchReader {
+    fn into_arrow_robj(self) -> Result<Robj> {
+        self.into_arrow_robj_with(&ExportOptions::default())
+    }
+
+    fn into_arrow_robj_with(self, options: &ExportOptions) -> Result<Robj> {
+        let reader: Box<dyn RecordBatchReader + Send> = Box::new(self);
+        reader.into_arrow_robj_with(options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ffi::CStr,
+        sync::{mpsc, Arc},
+        time::Duration,
+    };
+
+    use arrow::{
+        array::Int32Array,
+        datatypes::{DataType, Field, Schema},
+        error::ArrowError,
+        ffi::FFI_ArrowArray,
+        ffi_stream::FFI_ArrowArrayStream,
+    };
+
+    use super::*;
+
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![Field::new("x", DataType::Int32, false)]))
+    }
+
+    fn batch(i: i32) -> BatchResult {
+        RecordBatch::try_new(schema(), vec![Arc::new(Int32Array::from(vec![i]))])
+    }
+
+    fn value(batch: &RecordBatch) -> i32 {
+        batch
+            .column(0)
+            .as_any()
+            .downcast_ref::<Int32Array>()
+            .unwrap()
+            .value(0)
+    }
+
+    #[test]
+    fn yields_batches_in_order() {
+        for prefetch in [0, 2, 10] {
+            let reader = PrefetchReader::from_iter(schema(), (0..5).map(batch), prefetch);
+            let values = reader.map(|b| value(&b.unwrap())).collect::<Vec<_>>();
+            assert_eq!(values, vec![0, 1, 2, 3, 4]);
+        }
+    }
+
+    #[test]
+    fn passes_producer_errors_to_get_last_error() {
+        let batches = vec![
+            batch(0),
+            Err(ArrowError::ComputeError("producer failed".into())),
+            batch(1),
+        ];
+        let reader = PrefetchReader::from_iter(schema(), batches, 1);
+        let mut stream = FFI_ArrowArrayStream::new(Box::new(reader));
+
+        let get_next = stream.get_next.unwrap();
+        let mut array = FFI_ArrowArray::empty();
+        assert_eq!(unsafe { get_next(&mut stream, &mut array) }, 0);
+        assert!(!array.is_released());
+
+        let mut array = FFI_ArrowArray::empty();
+        assert_ne!(unsafe { get_next(&mut stream, &mut array) }, 0);
+
+        let error = unsafe { CStr::from_ptr((stream.get_last_error.unwrap())(&mut stream)) };
+        assert!(error.to_string_lossy().contains("producer failed"));
+    }
+
+    #[test]
+    fn stops_the_worker_when_dropped() {
+        let (done_tx, done_rx) = mpsc::channel::<()>();
+
+        // endless, `done_tx` is dropped once the worker thread exits
+        let batches = std::iter::repeat_with(move || {
+            let _ = &done_tx;
+            batch(0)
+        });
+        let mut reader = PrefetchReader::from_iter(schema(), batches, 1);
+        assert!(reader.next().unwrap().is_ok());
+        drop(reader);
+
+        assert_eq!(
+            done_rx.recv_timeout(Duration::from_secs(10)),
+            Err(mpsc::RecvTimeoutError::Disconnected)
+        );
+    }
+}
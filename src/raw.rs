@@ -0,0 +1,367 @@
+//! Move raw C Data Interface structs between R objects and Rust
+//!
+//! This module does not depend on arrow-rs types. It defines `#[repr(C)]`
+//! structs that match the [Arrow C Data Interface](https://arrow.apache.org/docs/format/CDataInterface.html)
+//! and moves them in and out of R objects by pointer. Any Arrow implementation,
+//! e.g. `arrow2`, `nanoarrow-rs` or hand-written bindings, can use it by casting
+//! pointers to its own ABI-compatible structs. The `FromArrowRobj` and
+//! `ToArrowRobj` impls for arrow-rs are built on top of it.
+//!
+//! ```ignore
+//! fn schema_from_r(x: Robj) -> Result<MySchema> {
+//!     let mut schema = ArrowSchema::empty();
+//!     unsafe { schema_from_robj(&x, &mut schema)? };
+//!     MySchema::from_c(schema)
+//! }
+//! ```
+//!
+//! |       function       |                                 R objects                                 |
+//! | -------------------- | ------------------------------------------------------------------------- |
+//! | `schema_from_robj()` | `nanoarrow_schema`, `arrow::Field`, `arrow::DataType`, or `arrow::Schema` |
+//! | `array_from_robj()`  | `nanoarrow_array`, `arrow::Array`, or `arrow::RecordBatch`                |
+//! | `stream_from_robj()` | `nanoarrow_array_stream` or `arrow::RecordBatchReader`                    |
+//! | `schema_into_robj()` | `nanoarrow_schema`                                                        |
+//! | `array_into_robj()`  | `nanoarrow_array`                                                         |
+//! | `stream_into_robj()` | `nanoarrow_array_stream`                                                  |
+//!
+//! Every function moves ownership: the source struct is marked as released
+//! and the destination must be an empty struct. `nanoarrow_schema` and
+//! `nanoarrow_array` objects may be copied instead, in which case they stay
+//! valid in R.
+use std::ffi::{c_char, c_int, c_void};
+
+use extendr_api::prelude::*;
+
+use crate::{
+    from::{nanoarrow_array_schema, nanoarrow_export},
+    to::{allocate_array, allocate_array_stream, allocate_schema, move_pointer, set_array_schema},
+};
+
+/// ABI-compatible struct for `ArrowSchema`
+#[repr(C)]
+#[derive(Debug)]
+pub struct ArrowSchema {
+    pub format: *const c_char,
+    pub name: *const c_char,
+    pub metadata: *const c_char,
+    pub flags: i64,
+    pub n_children: i64,
+    pub children: *mut *mut ArrowSchema,
+    pub dictionary: *mut ArrowSchema,
+    pub release: Option<unsafe extern "C" fn(*mut ArrowSchema)>,
+    pub private_data: *mut c_void,
+}
+
+/// ABI-compatible struct for `ArrowArray`
+#[repr(C)]
+#[derive(Debug)]
+pub struct ArrowArray {
+    pub length: i64,
+    pub null_count: i64,
+    pub offset: i64,
+    pub n_buffers: i64,
+    pub n_children: i64,
+    pub buffers: *mut *const c_void,
+    pub children: *mut *mut ArrowArray,
+    pub dictionary: *mut ArrowArray,
+    pub release: Option<unsafe extern "C" fn(*mut ArrowArray)>,
+    pub private_data: *mut c_void,
+}
+
+/// ABI-compatible struct for `ArrowArrayStream`
+#[repr(C)]
+#[derive(Debug)]
+pub struct ArrowArrayStream {
+    pub get_schema: Option<unsafe extern "C" fn(*mut ArrowArrayStream, *mut ArrowSchema) -> c_int>,
+    pub get_next: Option<unsafe extern "C" fn(*mut ArrowArrayStream, *mut ArrowArray) -> c_int>,
+    pub get_last_error: Option<unsafe extern "C" fn(*mut ArrowArrayStream) -> *const c_char>,
+    pub release: Option<unsafe extern "C" fn(*mut ArrowArrayStream)>,
+    pub private_data: *mut c_void,
+}
+
+// the release callbacks are called when a struct that has not been moved is dropped
+macro_rules! impl_c_struct {
+    ($t:ident { $($field:ident: $value:expr),* }) => {
+        impl $t {
+            /// An empty, released struct to be filled by a producer
+            pub fn empty() -> Self {
+                Self {
+                    $($field: $value,)*
+                    release: None,
+                    private_data: std::ptr::null_mut(),
+                }
+            }
+
+            pub fn is_released(&self) -> bool {
+                self.release.is_none()
+            }
+        }
+
+        impl Drop for $t {
+            fn drop(&mut self) {
+                if let Some(release) = self.release {
+                    unsafe { release(self) }
+                }
+            }
+        }
+    };
+}
+
+impl_c_struct!(ArrowSchema {
+    format: std::ptr::null(),
+    name: std::ptr::null(),
+    metadata: std::ptr::null(),
+    flags: 0,
+    n_children: 0,
+    children: std::ptr::null_mut(),
+    dictionary: std::ptr::null_mut()
+});
+
+impl_c_struct!(ArrowArray {
+    length: 0,
+    null_count: 0,
+    offset: 0,
+    n_buffers: 0,
+    n_children: 0,
+    buffers: std::ptr::null_mut(),
+    children: std::ptr::null_mut(),
+    dictionary: std::ptr::null_mut()
+});
+
+impl_c_struct!(ArrowArrayStream {
+    get_schema: None,
+    get_next: None,
+    get_last_error: None
+});
+
+fn addr<T>(ptr: *mut T) -> String {
+    (ptr as usize).to_string()
+}
+
+/// Calls the `export_to_c()` method of an `{arrow}` R6 object
+fn arrow_export_to_c(robj: &Robj, args: Pairlist) -> Result<()> {
+    robj.dollar("export_to_c")?
+        .as_function()
+        .ok_or_else(|| Error::Other("`$export_to_c()` must be a function".into()))?
+        .call(args)?;
+
+    Ok(())
+}
+
+/// Moves the schema of an R object into `out`
+///
+/// # Safety
+///
+/// `out` must point to an empty `ArrowSchema` compatible struct.
+pub unsafe fn schema_from_robj(robj: &Robj, out: *mut ArrowSchema) -> Result<()> {
+    if robj.inherits("nanoarrow_schema") {
+        nanoarrow_export(robj, addr(out))?;
+    } else if ["Field", "DataType", "Schema"]
+        .iter()
+        .any(|cls| robj.inherits(cls))
+    {
+        arrow_export_to_c(robj, pairlist!(addr(out)))?;
+    } else {
+        return Err(Error::Other(
+            "expected a `nanoarrow_schema`, `Field`, `DataType` or `Schema`".into(),
+        ));
+    }
+
+    check_filled((*out).is_released())
+}
+
+/// Moves an array and its schema from an R object into `out_array` and `out_schema`
+///
+/// # Safety
+///
+/// `out_array` and `out_schema` must point to empty `ArrowArray` and
+/// `ArrowSchema` compatible structs.
+pub unsafe fn array_from_robj(
+    robj: &Robj,
+    out_array: *mut ArrowArray,
+    out_schema: *mut ArrowSchema,
+) -> Result<()> {
+    if robj.inherits("nanoarrow_array") {
+        let schema = nanoarrow_array_schema(robj)?;
+        nanoarrow_export(robj, addr(out_array))?;
+        nanoarrow_export(&schema, addr(out_schema))?;
+    } else if robj.inherits("Array") || robj.inherits("RecordBatch") {
+        arrow_export_to_c(robj, pairlist!(addr(out_array), addr(out_schema)))?;
+    } else {
+        return Err(Error::Other(
+            "expected a `nanoarrow_array`, `Array` or `RecordBatch`".into(),
+        ));
+    }
+
+    check_filled((*out_array).is_released() || (*out_schema).is_released())
+}
+
+/// Moves a stream from an R object into `out`
+///
+/// # Safety
+///
+/// `out` must point to an empty `ArrowArrayStream` compatible struct.
+pub unsafe fn stream_from_robj(robj: &Robj, out: *mut ArrowArrayStream) -> Result<()> {
+    if robj.inherits("nanoarrow_array_stream") {
+        nanoarrow_export(robj, addr(out))?;
+    } else if robj.inherits("RecordBatchReader") {
+        arrow_export_to_c(robj, pairlist!(addr(out)))?;
+    } else {
+        return Err(Error::Other(
+            "expected a `nanoarrow_array_stream` or `RecordBatchReader`".into(),
+        ));
+    }
+
+    check_filled((*out).is_released())
+}
+
+fn check_filled(is_released: bool) -> Result<()> {
+    if is_released {
+        return Err(Error::Other(
+            "the R object did not export a valid struct, it may have been released".into(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Moves a schema into a new `nanoarrow_schema`
+///
+/// # Safety
+///
+/// `schema` must point to a valid `ArrowSchema` compatible struct.
+pub unsafe fn schema_into_robj(schema: *mut ArrowSchema) -> Result<Robj> {
+    let robj = allocate_schema(pairlist!())?;
+    move_pointer(pairlist!(addr(schema), &robj))?;
+
+    Ok(robj)
+}
+
+/// Moves an array and its schema into a new `nanoarrow_array`
+///
+/// # Safety
+///
+/// `array` and `schema` must point to valid `ArrowArray` and `ArrowSchema`
+/// compatible structs.
+pub unsafe fn array_into_robj(array: *mut ArrowArray, schema: *mut ArrowSchema) -> Result<Robj> {
+    let robj = allocate_array(pairlist!())?;
+    let robj_schema = schema_into_robj(schema)?;
+
+    move_pointer(pairlist!(addr(array), &robj))?;
+    set_array_schema(&robj, &robj_schema);
+
+    Ok(robj)
+}
+
+/// Moves a stream into a new `nanoarrow_array_stream`
+///
+/// # Safety
+///
+/// `stream` must point to a valid `ArrowArrayStream` compatible struct.
+pub unsafe fn stream_into_robj(stream: *mut ArrowArrayStream) -> Result<Robj> {
+    let robj = allocate_array_stream(pairlist!())?;
+    move_pointer(pairlist!(addr(stream), &robj))?;
+
+    Ok(robj)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ffi::CStr,
+        mem::{align_of, size_of},
+        ptr,
+        sync::Arc,
+    };
+
+    use arrow::{
+        array::{Array, Int32Array},
+        datatypes::DataType,
+        ffi::{self, FFI_ArrowArray, FFI_ArrowSchema},
+        ffi_stream::{ArrowArrayStreamReader, FFI_ArrowArrayStream},
+        record_batch::{RecordBatch, RecordBatchIterator},
+    };
+
+    use super::*;
+
+    // moves `source` into an empty struct of another type with the same layout
+    unsafe fn move_as<A, B>(source: &mut A, empty_a: A, empty_b: B) -> B {
+        let mut dest = empty_b;
+        let value = ptr::replace(source, empty_a);
+        ptr::write((&mut dest as *mut B).cast::<A>(), value);
+        dest
+    }
+
+    #[test]
+    fn layouts_match_arrow_rs() {
+        assert_eq!(size_of::<ArrowSchema>(), size_of::<FFI_ArrowSchema>());
+        assert_eq!(align_of::<ArrowSchema>(), align_of::<FFI_ArrowSchema>());
+        assert_eq!(size_of::<ArrowArray>(), size_of::<FFI_ArrowArray>());
+        assert_eq!(align_of::<ArrowArray>(), align_of::<FFI_ArrowArray>());
+        assert_eq!(
+            size_of::<ArrowArrayStream>(),
+            size_of::<FFI_ArrowArrayStream>()
+        );
+        assert_eq!(
+            align_of::<ArrowArrayStream>(),
+            align_of::<FFI_ArrowArrayStream>()
+        );
+    }
+
+    #[test]
+    fn round_trips_schemas_through_raw_pointers() {
+        let mut source = FFI_ArrowSchema::try_from(&DataType::Int32).unwrap();
+        let raw = unsafe { move_as(&mut source, FFI_ArrowSchema::empty(), ArrowSchema::empty()) };
+
+        assert!(!raw.is_released());
+        assert_eq!(unsafe { CStr::from_ptr(raw.format) }.to_str(), Ok("i"));
+
+        let mut raw = raw;
+        let back = unsafe { move_as(&mut raw, ArrowSchema::empty(), FFI_ArrowSchema::empty()) };
+        assert!(raw.is_released());
+        assert_eq!(DataType::try_from(&back).unwrap(), DataType::Int32);
+    }
+
+    #[test]
+    fn round_trips_arrays_through_raw_pointers() {
+        let data = Int32Array::from(vec![Some(1), None, Some(3)]).into_data();
+        let mut source = FFI_ArrowArray::new(&data);
+        let mut raw = unsafe { move_as(&mut source, FFI_ArrowArray::empty(), ArrowArray::empty()) };
+
+        assert_eq!((raw.length, raw.null_count, raw.n_buffers), (3, 1, 2));
+
+        let back = unsafe { move_as(&mut raw, ArrowArray::empty(), FFI_ArrowArray::empty()) };
+        let schema = FFI_ArrowSchema::try_from(data.data_type()).unwrap();
+        assert_eq!(unsafe { ffi::from_ffi(back, &schema) }.unwrap(), data);
+    }
+
+    #[test]
+    fn round_trips_streams_through_raw_pointers() {
+        let column = Arc::new(Int32Array::from(vec![1, 2]));
+        let batch = RecordBatch::try_from_iter([("x", column as _)]).unwrap();
+        let reader = RecordBatchIterator::new(vec![Ok(batch.clone())], batch.schema());
+
+        let mut source = FFI_ArrowArrayStream::new(Box::new(reader));
+        let mut raw = unsafe {
+            move_as(
+                &mut source,
+                FFI_ArrowArrayStream::empty(),
+                ArrowArrayStream::empty(),
+            )
+        };
+        assert!(raw.get_next.is_some());
+
+        let back = unsafe {
+            move_as(
+                &mut raw,
+                ArrowArrayStream::empty(),
+                FFI_ArrowArrayStream::empty(),
+            )
+        };
+        let batches = ArrowArrayStreamReader::try_new(back)
+            .unwrap()
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(batches, vec![batch]);
+    }
+}
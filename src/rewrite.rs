@@ -0,0 +1,686 @@
+//! Rewrite types that R struggles with into R-friendly equivalents
+//!
+//! `{nanoarrow}` cannot convert every Arrow type into an R vector. The
+//! `TypePolicy` of `ExportOptions` in the `to` module selects which types are rewritten on export.
+//!
+//! |      policy flag      |            original type            |           rewritten to           |
+//! | --------------------- | ----------------------------------- | -------------------------------- |
+//! | `decode_run_end`      | `RunEndEncoded`                     | the type of its values           |
+//! | `shrink_large`        | `LargeUtf8`, `LargeBinary`          | `Utf8`, `Binary`                 |
+//! | `shrink_large`        | `LargeList`                         | `List`                           |
+//! | `shrink_large`        | `Decimal256` with precision <= 38   | `Decimal128`                     |
+//! | `decimal_to_double`   | `Decimal128`, `Decimal256`          | `Float64`                        |
+//!
+//! Large offsets are only shrunk when the data is known and fits in 32 bits,
+//! i.e. for arrays and record batches but not for streams, whose batches are
+//! exported one at a time with a fixed schema. Run-end encoded values must fit
+//! once decoded. `decimal_to_double` loses precision and is never enabled by
+//! `TypePolicy::r_friendly()`.
+//!
+//! Rewrites recurse into lists, fixed size lists and structs and into the
+//! values of run-end encoded arrays. Dictionary values and `Map` entries are
+//! left as they are, so types nested in them are exported unchanged.
+//!
+//! Every rewrite is recorded in the metadata of the field under
+//! [`ORIGINAL_TYPE_KEY`], e.g. `RunEndEncoded(Int32);LargeUtf8` for run-end
+//! encoded large strings. `restore_field()`, `restore_array()` and
+//! `restore_batch()` use it to convert back, and imports do so when
+//! `ImportOptions::restore_types` is set.
+use std::{str::FromStr, sync::Arc};
+
+use arrow::{
+    array::{
+        make_array, Array, ArrayData, ArrayRef, AsArray, FixedSizeListArray, GenericListArray,
+        Int64Array, OffsetSizeTrait, StructArray, UInt64Array,
+    },
+    compute::{cast, kernels::partition::partition, take},
+    datatypes::{DataType, Field, FieldRef, Fields, Int64Type, Schema, SchemaRef},
+    error::ArrowError,
+    record_batch::{RecordBatch, RecordBatchReader},
+};
+
+use crate::to::TypePolicy;
+
+/// The field metadata key that records the original types of a rewritten field
+pub const ORIGINAL_TYPE_KEY: &str = "arrow_extendr.original_type";
+
+/// Rewrites the type of a field and records the original type in its metadata
+///
+/// `chunks` are the arrays the field holds. Large offsets are only shrunk
+/// when they are given and every chunk fits. Returns an error when a chunk
+/// does not match the type of the field, e.g. malformed run ends.
+pub fn rewrite_field(
+    field: &Field,
+    chunks: Option<&[ArrayRef]>,
+    policy: &TypePolicy,
+) -> Result<Field, ArrowError> {
+    let mut original = Vec::new();
+    let data_type = rewrite_type(field.data_type(), chunks, policy, &mut original)?;
+
+    if original.is_empty() {
+        return Ok(field.clone().with_data_type(data_type));
+    }
+
+    let mut metadata = field.metadata().clone();
+    metadata.insert(ORIGINAL_TYPE_KEY.to_string(), original.join(";"));
+
+    Ok(field
+        .clone()
+        .with_data_type(data_type)
+        .with_metadata(metadata))
+}
+
+// `original` is the stack of types that were rewritten, outermost first
+fn rewrite_type(
+    data_type: &DataType,
+    chunks: Option<&[ArrayRef]>,
+    policy: &TypePolicy,
+    original: &mut Vec<String>,
+) -> Result<DataType, ArrowError> {
+    let data_type = match data_type {
+        DataType::RunEndEncoded(run_ends, values) if policy.decode_run_end => {
+            original.push(format!("RunEndEncoded({})", run_ends.data_type()));
+            // the values are repeated over their runs on export so their
+            // offsets must fit once decoded, not as they are stored
+            let values_chunks = chunks
+                .map(|chunks| {
+                    chunks
+                        .iter()
+                        .map(decode_run_end)
+                        .collect::<Result<Vec<_>, _>>()
+                })
+                .transpose()?;
+            rewrite_type(
+                values.data_type(),
+                values_chunks.as_deref(),
+                policy,
+                original,
+            )?
+        }
+        DataType::LargeUtf8 | DataType::LargeBinary
+            if policy.shrink_large && fits(chunks, large_offsets_fit) =>
+        {
+            original.push(data_type.to_string());
+            match data_type {
+                DataType::LargeUtf8 => DataType::Utf8,
+                _ => DataType::Binary,
+            }
+        }
+        DataType::LargeList(field) => {
+            let values_chunks = chunks.map(|chunks| child_chunks(chunks, 0)).transpose()?;
+            let field = Arc::new(rewrite_field(field, values_chunks.as_deref(), policy)?);
+
+            if policy.shrink_large && fits(chunks, large_offsets_fit) {
+                original.push("LargeList".to_string());
+                DataType::List(field)
+            } else {
+                DataType::LargeList(field)
+            }
+        }
+        DataType::List(field) => {
+            let values_chunks = chunks.map(|chunks| child_chunks(chunks, 0)).transpose()?;
+            DataType::List(Arc::new(rewrite_field(
+                field,
+                values_chunks.as_deref(),
+                policy,
+            )?))
+        }
+        DataType::FixedSizeList(field, size) => {
+            let values_chunks = chunks.map(|chunks| child_chunks(chunks, 0)).transpose()?;
+            DataType::FixedSizeList(
+                Arc::new(rewrite_field(field, values_chunks.as_deref(), policy)?),
+                *size,
+            )
+        }
+        DataType::Struct(fields) => DataType::Struct(
+            fields
+                .iter()
+                .enumerate()
+                .map(|(i, field)| {
+                    let column_chunks = chunks.map(|chunks| child_chunks(chunks, i)).transpose()?;
+                    rewrite_field(field, column_chunks.as_deref(), policy)
+                })
+                .collect::<Result<Fields, _>>()?,
+        ),
+        DataType::Decimal128(_, _) | DataType::Decimal256(_, _) if policy.decimal_to_double => {
+            original.push(data_type.to_string());
+            DataType::Float64
+        }
+        DataType::Decimal256(precision, scale) if policy.shrink_large && *precision <= 38 => {
+            original.push(data_type.to_string());
+            DataType::Decimal128(*precision, *scale)
+        }
+        other => other.clone(),
+    };
+
+    Ok(data_type)
+}
+
+// the i-th child of every chunk, e.g. the values of a list or a struct column
+fn child_chunks(chunks: &[ArrayRef], i: usize) -> Result<Vec<ArrayRef>, ArrowError> {
+    chunks
+        .iter()
+        .map(|chunk| {
+            let data = chunk.to_data();
+            let child = data.child_data().get(i).ok_or_else(|| {
+                ArrowError::InvalidArgumentError(format!(
+                    "{} array has no child {i}",
+                    chunk.data_type()
+                ))
+            })?;
+            Ok(make_array(child.clone()))
+        })
+        .collect()
+}
+
+fn fits(chunks: Option<&[ArrayRef]>, f: fn(&ArrayRef) -> bool) -> bool {
+    chunks.is_some_and(|chunks| chunks.iter().all(f))
+}
+
+// the data of large strings, binaries and lists is addressed by i64 offsets
+fn large_offsets_fit(array: &ArrayRef) -> bool {
+    let data = array.to_data();
+    let Some(offsets) = data.buffers().first() else {
+        return true;
+    };
+
+    let offsets = offsets.typed_data::<i64>();
+    let start = offsets.get(data.offset()).copied().unwrap_or(0);
+    let end = offsets
+        .get(data.offset() + data.len())
+        .copied()
+        .unwrap_or(start);
+
+    end - start <= i32::MAX as i64
+}
+
+/// Rewrites the fields of a `Schema`
+pub fn rewrite_schema(schema: &Schema, policy: &TypePolicy) -> Result<Schema, ArrowError> {
+    let fields = schema
+        .fields()
+        .iter()
+        .map(|field| rewrite_field(field, None, policy))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Schema::new(fields).with_metadata(schema.metadata().clone()))
+}
+
+/// Converts an array to the type returned by `rewrite_field()`
+pub fn rewrite_array(array: &ArrayRef, to: &DataType) -> Result<ArrayRef, ArrowError> {
+    if array.data_type() == to {
+        return Ok(array.clone());
+    }
+
+    match (array.data_type(), to) {
+        (DataType::RunEndEncoded(_, _), _) => rewrite_array(&decode_run_end(array)?, to),
+        (DataType::List(_), DataType::List(field)) => {
+            list_with_values::<i32>(array, field, rewrite_array)
+        }
+        (DataType::LargeList(_), DataType::LargeList(field)) => {
+            list_with_values::<i64>(array, field, rewrite_array)
+        }
+        (DataType::LargeList(_), DataType::List(field)) => {
+            let large = list_with_values::<i64>(array, field, rewrite_array)?;
+            cast(&large, to)
+        }
+        (DataType::FixedSizeList(_, _), DataType::FixedSizeList(field, _)) => {
+            fixed_size_list_with_values(array, field, rewrite_array)
+        }
+        (DataType::Struct(_), DataType::Struct(fields)) => {
+            struct_with_columns(array, fields, rewrite_array)
+        }
+        _ => cast(array, to),
+    }
+}
+
+/// Converts an array with a rewritten type back to `to`, usually from `restore_field()`
+pub fn restore_array(array: &ArrayRef, to: &DataType) -> Result<ArrayRef, ArrowError> {
+    if array.data_type() == to {
+        return Ok(array.clone());
+    }
+
+    match (array.data_type(), to) {
+        (_, DataType::RunEndEncoded(run_ends, values)) => {
+            let values = restore_array(array, values.data_type())?;
+            encode_run_end(&values, run_ends.data_type(), to)
+        }
+        (DataType::List(_), DataType::List(field)) => {
+            list_with_values::<i32>(array, field, restore_array)
+        }
+        (DataType::LargeList(_), DataType::LargeList(field)) => {
+            list_with_values::<i64>(array, field, restore_array)
+        }
+        (DataType::List(_), DataType::LargeList(field)) => {
+            let list = list_with_values::<i32>(array, field, restore_array)?;
+            cast(&list, to)
+        }
+        (DataType::FixedSizeList(_, _), DataType::FixedSizeList(field, _)) => {
+            fixed_size_list_with_values(array, field, restore_array)
+        }
+        (DataType::Struct(_), DataType::Struct(fields)) => {
+            struct_with_columns(array, fields, restore_array)
+        }
+        _ => cast(array, to),
+    }
+}
+
+type Convert = fn(&ArrayRef, &DataType) -> Result<ArrayRef, ArrowError>;
+
+fn list_with_values<O: OffsetSizeTrait>(
+    array: &ArrayRef,
+    field: &FieldRef,
+    convert: Convert,
+) -> Result<ArrayRef, ArrowError> {
+    let array = array.as_list::<O>();
+    let values = convert(array.values(), field.data_type())?;
+
+    Ok(Arc::new(GenericListArray::<O>::try_new(
+        field.clone(),
+        array.offsets().clone(),
+        values,
+        array.nulls().cloned(),
+    )?))
+}
+
+fn fixed_size_list_with_values(
+    array: &ArrayRef,
+    field: &FieldRef,
+    convert: Convert,
+) -> Result<ArrayRef, ArrowError> {
+    let array = array.as_fixed_size_list();
+    let values = convert(array.values(), field.data_type())?;
+
+    Ok(Arc::new(FixedSizeListArray::try_new(
+        field.clone(),
+        array.value_length(),
+        values,
+        array.nulls().cloned(),
+    )?))
+}
+
+fn struct_with_columns(
+    array: &ArrayRef,
+    fields: &Fields,
+    convert: Convert,
+) -> Result<ArrayRef, ArrowError> {
+    let array = array.as_struct();
+    let columns = array
+        .columns()
+        .iter()
+        .zip(fields.iter())
+        .map(|(column, field)| convert(column, field.data_type()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Arc::new(StructArray::try_new(
+        fields.clone(),
+        columns,
+        array.nulls().cloned(),
+    )?))
+}
+
+// repeats each value of a run-end encoded array over its run
+fn decode_run_end(array: &ArrayRef) -> Result<ArrayRef, ArrowError> {
+    let children = child_chunks(std::slice::from_ref(array), 0)?
+        .into_iter()
+        .zip(child_chunks(std::slice::from_ref(array), 1)?);
+    let Some((run_ends, values)) = children.last() else {
+        return Err(ArrowError::InvalidArgumentError(
+            "run-end encoded array has no children".into(),
+        ));
+    };
+
+    let run_ends = cast(&run_ends, &DataType::Int64)?;
+    let run_ends = run_ends.as_primitive::<Int64Type>();
+
+    let mut indices = Vec::with_capacity(array.len());
+    let mut physical = 0;
+
+    // run ends imported over FFI are not validated
+    for logical in array.offset()..array.offset() + array.len() {
+        while physical < run_ends.len() && run_ends.value(physical) <= logical as i64 {
+            physical += 1;
+        }
+        if physical >= run_ends.len() || physical >= values.len() {
+            return Err(ArrowError::InvalidArgumentError(format!(
+                "run ends do not cover index {logical} of a run-end encoded array"
+            )));
+        }
+        indices.push(physical as u64);
+    }
+
+    take(&values, &UInt64Array::from(indices), None)
+}
+
+// consecutive equal values, including nulls, become a single run
+fn encode_run_end(
+    values: &ArrayRef,
+    run_ends_type: &DataType,
+    to: &DataType,
+) -> Result<ArrayRef, ArrowError> {
+    let ranges = if values.is_empty() {
+        vec![]
+    } else {
+        partition(std::slice::from_ref(values))?.ranges()
+    };
+
+    let starts = UInt64Array::from_iter_values(ranges.iter().map(|r| r.start as u64));
+    let run_ends = Int64Array::from_iter_values(ranges.iter().map(|r| r.end as i64));
+    let run_ends = cast(&run_ends, run_ends_type)?;
+    let values = take(values, &starts, None)?;
+
+    let data = ArrayData::builder(to.clone())
+        .len(ranges.last().map(|r| r.end).unwrap_or(0))
+        .add_child_data(run_ends.to_data())
+        .add_child_data(values.to_data())
+        .build()?;
+
+    Ok(make_array(data))
+}
+
+/// Restores the original type of a field recorded by `rewrite_field()`
+pub fn restore_field(field: &Field) -> Result<Field, ArrowError> {
+    let data_type = match field.data_type() {
+        DataType::List(child) => DataType::List(Arc::new(restore_field(child)?)),
+        DataType::LargeList(child) => DataType::LargeList(Arc::new(restore_field(child)?)),
+        DataType::FixedSizeList(child, size) => {
+            DataType::FixedSizeList(Arc::new(restore_field(child)?), *size)
+        }
+        DataType::Struct(fields) => DataType::Struct(
+            fields
+                .iter()
+                .map(|f| restore_field(f))
+                .collect::<Result<Fields, _>>()?,
+        ),
+        other => other.clone(),
+    };
+
+    let mut metadata = field.metadata().clone();
+    let Some(original) = metadata.remove(ORIGINAL_TYPE_KEY) else {
+        return Ok(field.clone().with_data_type(data_type));
+    };
+
+    let data_type = original
+        .split(';')
+        .rev()
+        .try_fold(data_type, |current, original| {
+            original_type(&current, original)
+        })?;
+
+    Ok(field
+        .clone()
+        .with_data_type(data_type)
+        .with_metadata(metadata))
+}
+
+// undoes a single rewrite recorded as `original`
+fn original_type(current: &DataType, original: &str) -> Result<DataType, ArrowError> {
+    if original == "LargeList" {
+        let DataType::List(field) = current else {
+            return Err(ArrowError::ParseError(format!(
+                "cannot restore a `LargeList` from {current}"
+            )));
+        };
+        return Ok(DataType::LargeList(field.clone()));
+    }
+
+    if let Some(run_ends) = original
+        .strip_prefix("RunEndEncoded(")
+        .and_then(|s| s.strip_suffix(')'))
+    {
+        return Ok(DataType::RunEndEncoded(
+            Arc::new(Field::new("run_ends", DataType::from_str(run_ends)?, false)),
+            Arc::new(Field::new("values", current.clone(), true)),
+        ));
+    }
+
+    DataType::from_str(original)
+}
+
+/// Restores the original types of the columns of a `RecordBatch`
+pub fn restore_batch(batch: &RecordBatch) -> Result<RecordBatch, ArrowError> {
+    let schema = batch.schema();
+    let fields = schema
+        .fields()
+        .iter()
+        .map(|field| restore_field(field))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let columns = batch
+        .columns()
+        .iter()
+        .zip(&fields)
+        .map(|(column, field)| restore_array(column, field.data_type()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let schema = Schema::new(fields).with_metadata(schema.metadata().clone());
+    RecordBatch::try_new(Arc::new(schema), columns)
+}
+
+/// Rewrites every batch with the same schema, using the data of all batches
+pub fn rewrite_batches(
+    batches: &[RecordBatch],
+    policy: &TypePolicy,
+) -> Result<Vec<RecordBatch>, ArrowError> {
+    let Some(first) = batches.first() else {
+        return Ok(vec![]);
+    };
+
+    let schema = first.schema();
+    let fields = schema
+        .fields()
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            let chunks = batches
+                .iter()
+                .map(|batch| batch.column(i).clone())
+                .collect::<Vec<_>>();
+            rewrite_field(field, Some(&chunks), policy)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let schema = Arc::new(Schema::new(fields).with_metadata(schema.metadata().clone()));
+
+    batches
+        .iter()
+        .map(|batch| rewrite_batch(batch, schema.clone()))
+        .collect()
+}
+
+/// Converts the columns of a `RecordBatch` to the types of a rewritten `schema`
+pub fn rewrite_batch(batch: &RecordBatch, schema: SchemaRef) -> Result<RecordBatch, ArrowError> {
+    let columns = batch
+        .columns()
+        .iter()
+        .zip(schema.fields().iter())
+        .map(|(column, field)| rewrite_array(column, field.data_type()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    RecordBatch::try_new(schema, columns)
+}
+
+/// A `RecordBatchReader` that rewrites the types of each batch
+///
+/// The data is not known up front so large offsets are not shrunk.
+pub struct RewriteReader {
+    inner: Box<dyn RecordBatchReader + Send>,
+    schema: SchemaRef,
+}
+
+impl RewriteReader {
+    pub fn try_new(
+        inner: Box<dyn RecordBatchReader + Send>,
+        policy: &TypePolicy,
+    ) -> Result<Self, ArrowError> {
+        let schema = Arc::new(rewrite_schema(&inner.schema(), policy)?);
+        Ok(Self { inner, schema })
+    }
+}
+
+impl Iterator for RewriteReader {
+    type Item = Result<RecordBatch, ArrowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner
+            .next()
+            .map(|batch| rewrite_batch(&batch?, self.schema.clone()))
+    }
+}
+
+impl RecordBatchReader for RewriteReader {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::{
+        array::{
+            Decimal256Array, Int16Array, Int32Array, LargeListArray, LargeStringArray, RunArray,
+            StringArray,
+        },
+        datatypes::{i256, Int16Type, Int32Type},
+    };
+
+    use super::*;
+
+    fn round_trip(column: ArrayRef, policy: &TypePolicy) -> (RecordBatch, RecordBatch) {
+        let batch = RecordBatch::try_from_iter_with_nullable([("x", column, true)]).unwrap();
+        let rewritten = rewrite_batches(std::slice::from_ref(&batch), policy)
+            .unwrap()
+            .remove(0);
+        let restored = restore_batch(&rewritten).unwrap();
+        assert_eq!(restored.schema(), batch.schema());
+        assert_eq!(restored.column(0).to_data(), batch.column(0).to_data());
+        (rewritten, restored)
+    }
+
+    #[test]
+    fn round_trips_run_end_encoded_large_strings() {
+        let values = LargeStringArray::from(vec![Some("a"), None, Some("bc")]);
+        let run_ends = Int16Array::from(vec![2, 3, 6]);
+        let array = RunArray::<Int16Type>::try_new(&run_ends, &values).unwrap();
+
+        let (rewritten, _) = round_trip(Arc::new(array), &TypePolicy::r_friendly());
+
+        let field = rewritten.schema().field(0).clone();
+        assert_eq!(field.data_type(), &DataType::Utf8);
+        assert_eq!(
+            field.metadata()[ORIGINAL_TYPE_KEY],
+            "RunEndEncoded(Int16);LargeUtf8"
+        );
+        assert_eq!(
+            rewritten.column(0).as_string::<i32>(),
+            &StringArray::from(vec![
+                Some("a"),
+                Some("a"),
+                None,
+                Some("bc"),
+                Some("bc"),
+                Some("bc")
+            ])
+        );
+    }
+
+    #[test]
+    fn checks_the_decoded_size_of_run_end_encoded_values() {
+        let values = LargeStringArray::from(vec!["abc", "de"]);
+        let run_ends = Int32Array::from(vec![4, 5]);
+        let array: ArrayRef = Arc::new(RunArray::<Int32Type>::try_new(&run_ends, &values).unwrap());
+
+        let decoded = decode_run_end(&array).unwrap();
+        assert_eq!(decoded.len(), 5);
+        assert_eq!(decoded.as_string::<i64>().value_offsets()[5], 14);
+
+        let field = Field::new("x", array.data_type().clone(), true);
+        let rewritten = rewrite_field(&field, Some(&[array]), &TypePolicy::r_friendly()).unwrap();
+        assert_eq!(rewritten.data_type(), &DataType::Utf8);
+
+        // without the data the size is unknown and the values stay large
+        let rewritten = rewrite_field(&field, None, &TypePolicy::r_friendly()).unwrap();
+        assert_eq!(rewritten.data_type(), &DataType::LargeUtf8);
+    }
+
+    #[test]
+    fn round_trips_nested_large_lists() {
+        let values = LargeStringArray::from(vec!["a", "b", "c"]);
+        let field = Arc::new(Field::new("item", DataType::LargeUtf8, true));
+        let list = LargeListArray::try_new(
+            field,
+            arrow::buffer::OffsetBuffer::new(vec![0i64, 2, 2, 3].into()),
+            Arc::new(values),
+            None,
+        )
+        .unwrap();
+        let column: ArrayRef = Arc::new(list);
+        let structs = StructArray::try_from(vec![("list", column)]).unwrap();
+
+        let (rewritten, _) = round_trip(Arc::new(structs), &TypePolicy::r_friendly());
+
+        let DataType::Struct(fields) = rewritten.schema().field(0).data_type().clone() else {
+            panic!("expected a struct");
+        };
+        let DataType::List(item) = fields[0].data_type() else {
+            panic!("expected a list");
+        };
+        assert_eq!(item.data_type(), &DataType::Utf8);
+        assert_eq!(fields[0].metadata()[ORIGINAL_TYPE_KEY], "LargeList");
+    }
+
+    #[test]
+    fn round_trips_decimals() {
+        let array = Decimal256Array::from(vec![Some(i256::from_i128(12345)), None])
+            .with_precision_and_scale(20, 2)
+            .unwrap();
+
+        let (rewritten, _) = round_trip(Arc::new(array), &TypePolicy::r_friendly());
+        assert_eq!(
+            rewritten.schema().field(0).data_type(),
+            &DataType::Decimal128(20, 2)
+        );
+    }
+
+    #[test]
+    fn keeps_large_types_of_streams() {
+        let schema = Schema::new(vec![Field::new("x", DataType::LargeUtf8, true)]);
+        let rewritten = rewrite_schema(&schema, &TypePolicy::r_friendly()).unwrap();
+        assert_eq!(rewritten.field(0).data_type(), &DataType::LargeUtf8);
+        assert_eq!(
+            restore_field(rewritten.field(0)).unwrap(),
+            schema.field(0).clone()
+        );
+    }
+
+    #[test]
+    fn errors_for_run_ends_that_do_not_cover_the_array() {
+        let run_ends = Int32Array::from(vec![2, 3]);
+        let values = StringArray::from(vec!["a", "b"]);
+        let data_type = DataType::RunEndEncoded(
+            Arc::new(Field::new("run_ends", DataType::Int32, false)),
+            Arc::new(Field::new("values", DataType::Utf8, true)),
+        );
+        // run ends imported over FFI are not validated
+        let data = unsafe {
+            ArrayData::builder(data_type)
+                .len(5)
+                .add_child_data(run_ends.into_data())
+                .add_child_data(values.into_data())
+                .build_unchecked()
+        };
+        let array = make_array(data);
+
+        let error = decode_run_end(&array).unwrap_err();
+        assert!(error.to_string().contains("do not cover index 3"));
+
+        let field = Field::new("x", array.data_type().clone(), true);
+        assert!(rewrite_field(&field, Some(&[array]), &TypePolicy::r_friendly()).is_err());
+    }
+
+    #[test]
+    fn errors_for_missing_child_data() {
+        let array: ArrayRef = Arc::new(Int32Array::from(vec![1, 2]));
+        let error = child_chunks(&[array], 0).unwrap_err();
+        assert!(error.to_string().contains("Int32 array has no child 0"));
+    }
+}
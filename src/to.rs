@@ -6,6 +6,16 @@
 //!
 //! Prefer `to_arrow_robj()` for all structs except `ArrowArrayStreamReader`.
 //!
+//! With the arrow-only backend the same methods return `{arrow}` R6 objects
+//! instead, see the `backend` module.
+//!
+//! `to_arrow_robj_with()` and `into_arrow_robj_with()` take `ExportOptions`.
+//! View types such as `Utf8View` are cast to their non-view equivalent when
+//! the R package of the backend cannot read them, see the `views` module.
+//!
+//! Schema and field metadata is always exported. `MetadataConflict` selects
+//! the metadata of a `Vec<RecordBatch>` whose batches disagree.
+//!
 //! ```ignore
 //! fn array_to_robj() -> Result<Robj> {
 //!     let array = Int32Array::from(vec![Some(1), None, Some(3)]);
@@ -13,79 +23,247 @@
 //! }
 //! ```
 //!
-//! |      arrow-rs struct     |         R object        |
-//! | -------------------------| ----------------------- |
-//! | `ArrayData`              |`nanoarrow_array`        |
-//! | `PrimitiveArray<T>`      |`nanoarrow_array`        |
-//! | `Field`                  |`nanoarrow_schema`       |
-//! | `DataType`               |`nanoarrow_schema`       |
-//! | `Schema`                 |`nanoarrow_schema`       |
-//! | `RecordBatch`            |`nanoarrow_array_stream` |
-//! | `ArrowArrayStreamReader` |`nanoarrow_array_stream` |
+//! |          arrow-rs struct           |         R object         |
+//! | ---------------------------------- | ------------------------ |
+//! | `ArrayData`                        | `nanoarrow_array`        |
+//! | `PrimitiveArray<T>`                | `nanoarrow_array`        |
+//! | `Field`                            | `nanoarrow_schema`       |
+//! | `DataType`                         | `nanoarrow_schema`       |
+//! | `Schema`                           | `nanoarrow_schema`       |
+//! | `Scalar<ArrayRef>`                 | `nanoarrow_array`        |
+//! | `RecordBatch`                      | `nanoarrow_array_stream` |
+//! | `RecordBatch` (`ToArrowArrayRobj`) | `nanoarrow_array`        |
+//! | `ArrowArrayStreamReader`           | `nanoarrow_array_stream` |
 //!
+use std::sync::Arc;
+
 use arrow::{
-    array::{Array, ArrayData, PrimitiveArray},
+    array::{make_array, Array, ArrayData, ArrayRef, Datum, PrimitiveArray, Scalar, StructArray},
     datatypes::{ArrowPrimitiveType, DataType, Field, Schema, SchemaBuilder},
     error::ArrowError,
     ffi::{to_ffi, FFI_ArrowArray, FFI_ArrowSchema},
-    ffi_stream::{ArrowArrayStreamReader, FFI_ArrowArrayStream},
+    ffi_stream::ArrowArrayStreamReader,
     record_batch::{RecordBatch, RecordBatchIterator, RecordBatchReader},
 };
 use extendr_api::prelude::*;
 
+use crate::{
+    backend::supports_views,
+    metadata::unify_metadata,
+    rewrite::{rewrite_array, rewrite_batches, rewrite_field, rewrite_schema, RewriteReader},
+    views::{
+        cast_batch_views, cast_view_field, cast_view_schema, cast_view_type, cast_views,
+        has_list_views, has_views, CastViewsReader,
+    },
+};
+
+#[cfg(feature = "nanoarrow")]
+use crate::backend::r_function;
+#[cfg(not(feature = "nanoarrow"))]
+use crate::native;
+#[cfg(any(feature = "nanoarrow", feature = "native", not(feature = "r-arrow")))]
+use crate::raw;
+
 /// Calls `nanoarrow::nanoarrow_allocate_array()`
 ///
-/// Requires `{nanoarrow}` to be installed.
+/// Requires `{nanoarrow}` to be installed, or a backend other than `nanoarrow`.
 pub fn allocate_array(args: Pairlist) -> Result<Robj> {
-    R!("nanoarrow::nanoarrow_allocate_array")
-        .expect("`nanoarrow` must be installed")
-        .as_function()
-        .expect("`nanoarrow_allocate_array()` must be available")
-        .call(args)
+    #[cfg(feature = "nanoarrow")]
+    {
+        r_function("nanoarrow::nanoarrow_allocate_array")?.call(args)
+    }
+    #[cfg(not(feature = "nanoarrow"))]
+    {
+        let _ = args;
+        native::allocate_array()
+    }
 }
 
 /// Calls `nanoarrow::nanoarrow_allocate_array_stream()`
 ///
-/// Requires `{nanoarrow}` to be installed.
+/// Requires `{nanoarrow}` to be installed, or a backend other than `nanoarrow`.
 pub fn allocate_array_stream(args: Pairlist) -> Result<Robj> {
-    R!("nanoarrow::nanoarrow_allocate_array_stream")
-        .expect("`nanoarrow` must be installed")
-        .as_function()
-        .expect("`nanoarrow_allocate_array()` must be available")
-        .call(args)
+    #[cfg(feature = "nanoarrow")]
+    {
+        r_function("nanoarrow::nanoarrow_allocate_array_stream")?.call(args)
+    }
+    #[cfg(not(feature = "nanoarrow"))]
+    {
+        let _ = args;
+        native::allocate_array_stream()
+    }
 }
 
 /// Calls `nanoarrow::nanoarrow_allocate_schema()`
 ///
-/// Requires `{nanoarrow}` to be installed.
+/// Requires `{nanoarrow}` to be installed, or a backend other than `nanoarrow`.
 pub fn allocate_schema(args: Pairlist) -> Result<Robj> {
-    R!("nanoarrow::nanoarrow_allocate_schema")
-        .expect("`nanoarrow` must be installed")
-        .as_function()
-        .expect("`nanoarrow_allocate_schema()` must be available")
-        .call(args)
+    #[cfg(feature = "nanoarrow")]
+    {
+        r_function("nanoarrow::nanoarrow_allocate_schema")?.call(args)
+    }
+    #[cfg(not(feature = "nanoarrow"))]
+    {
+        let _ = args;
+        native::allocate_schema()
+    }
 }
 
 /// Calls `nanoarrow::nanoarrow_pointer_move()`
 ///
-/// Requires `{nanoarrow}` to be installed.
+/// `args` are the address of the source struct as a string and the
+/// destination object. Requires `{nanoarrow}` to be installed, or a backend other
+/// than `nanoarrow`.
 pub fn move_pointer(args: Pairlist) -> Result<Robj> {
-    R!("nanoarrow::nanoarrow_pointer_move")
-        .expect("`nanoarrow` must be installed")
-        .as_function()
-        .expect("`nanoarrow_pointer_move()` must be available")
-        .call(args)
+    #[cfg(feature = "nanoarrow")]
+    {
+        r_function("nanoarrow::nanoarrow_pointer_move")?.call(args)
+    }
+    #[cfg(not(feature = "nanoarrow"))]
+    {
+        let mut args = args.values();
+
+        let (Some(src), Some(dest)) = (args.next(), args.next()) else {
+            return Err(Error::Other(
+                "expected a source address and a destination".into(),
+            ));
+        };
+
+        let src = src
+            .as_str()
+            .and_then(|addr| addr.parse::<usize>().ok())
+            .ok_or_else(|| Error::Other("source must be an address string".into()))?;
+
+        unsafe { native::move_pointer(src, &dest)? };
+        Ok(dest)
+    }
 }
 
 /// Calls `nanoarrow::nanoarrow_array_set_schema()`
 ///
-/// Requires `{nanoarrow}` to be installed.
+/// Requires `{nanoarrow}` to be installed, or a backend other than `nanoarrow`.
 pub fn set_array_schema(arr: &Robj, schema: &Robj) {
-    let _ = R!("nanoarrow::nanoarrow_array_set_schema")
-        .expect("`nanoarrow` must be installed")
-        .as_function()
-        .expect("`nanoarrow_array_set_schema()` must be available")
-        .call(pairlist!(arr, schema));
+    #[cfg(feature = "nanoarrow")]
+    let _ = r_function("nanoarrow::nanoarrow_array_set_schema")
+        .and_then(|f| f.call(pairlist!(arr, schema)));
+    #[cfg(not(feature = "nanoarrow"))]
+    let _ = native::set_array_schema(arr, schema);
+}
+
+/// Converts an `ArrowError` into an extendr `Error`
+///
+/// Useful for returning `ErrArrowRobj`s from `#[extendr]` functions.
+pub fn arrow_error(e: ArrowError) -> Error {
+    Error::Other(e.to_string())
+}
+
+/// How view types such as `Utf8View` are exported, see the `views` module
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ViewPolicy {
+    /// Views are cast when the R package used by the backend cannot read them
+    #[default]
+    Auto,
+    /// Views are exported as is, except list views which arrow-rs cannot export
+    Keep,
+    /// Views are always cast to their non-view equivalent
+    Cast,
+}
+
+impl ViewPolicy {
+    /// Checks if the view types in `data_type` must be cast before export
+    pub fn should_cast(&self, data_type: &DataType) -> bool {
+        if !has_views(data_type) {
+            return false;
+        }
+
+        match self {
+            ViewPolicy::Auto => has_list_views(data_type) || !supports_views(),
+            ViewPolicy::Keep => has_list_views(data_type),
+            ViewPolicy::Cast => true,
+        }
+    }
+}
+
+/// Which types are rewritten on export, see the `rewrite` module
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TypePolicy {
+    /// Decodes run-end encoded arrays into their values
+    pub decode_run_end: bool,
+    /// Uses 32-bit offsets for large strings, binaries and lists when they fit,
+    /// and `Decimal128` for `Decimal256` when the precision allows it
+    pub shrink_large: bool,
+    /// Casts decimals to `Float64`, which loses precision
+    pub decimal_to_double: bool,
+}
+
+impl TypePolicy {
+    /// Decodes run-end encoded arrays and shrinks large types
+    pub fn r_friendly() -> Self {
+        Self {
+            decode_run_end: true,
+            shrink_large: true,
+            decimal_to_double: false,
+        }
+    }
+
+    pub fn with_decode_run_end(mut self, decode_run_end: bool) -> Self {
+        self.decode_run_end = decode_run_end;
+        self
+    }
+
+    pub fn with_shrink_large(mut self, shrink_large: bool) -> Self {
+        self.shrink_large = shrink_large;
+        self
+    }
+
+    pub fn with_decimal_to_double(mut self, decimal_to_double: bool) -> Self {
+        self.decimal_to_double = decimal_to_double;
+        self
+    }
+
+    /// Checks if no type is rewritten
+    pub fn is_noop(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// How differing metadata across the batches of a `Vec<RecordBatch>` is exported
+///
+/// Applies to both schema and field metadata, see `metadata::unify_metadata()`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MetadataConflict {
+    /// The metadata of the first batch is used
+    #[default]
+    First,
+    /// The metadata of every batch is combined, earlier batches win on conflicts
+    Merge,
+    /// Differing metadata is an error
+    Error,
+}
+
+/// Options that control how an arrow-rs struct is exported
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExportOptions {
+    pub views: ViewPolicy,
+    pub types: TypePolicy,
+    pub metadata_conflict: MetadataConflict,
+}
+
+impl ExportOptions {
+    pub fn with_views(mut self, views: ViewPolicy) -> Self {
+        self.views = views;
+        self
+    }
+
+    pub fn with_types(mut self, types: TypePolicy) -> Self {
+        self.types = types;
+        self
+    }
+
+    pub fn with_metadata_conflict(mut self, metadata_conflict: MetadataConflict) -> Self {
+        self.metadata_conflict = metadata_conflict;
+        self
+    }
 }
 
 /// Convert an Arrow struct to an `Robj`
@@ -93,107 +271,291 @@ pub fn set_array_schema(arr: &Robj, schema: &Robj) {
 /// Does not consume `self`. Takes an arrow-rs struct and converts it into
 /// a `{nanoarrow}` S3 object of class `nanoarrow_array`, `nanoarrow_array_stream`, or `nanoarrow_schema`.
 ///
-/// **Requires `nanoarrow` to be available**.
+/// **Requires `nanoarrow` to be available**, unless another backend is
+/// selected, see the `backend` module.
 pub trait ToArrowRobj {
     fn to_arrow_robj(&self) -> Result<Robj>;
+
+    /// Exports with `options`
+    ///
+    /// Ignores `options` unless overridden by structs that can hold view types.
+    fn to_arrow_robj_with(&self, options: &ExportOptions) -> Result<Robj> {
+        let _ = options;
+        self.to_arrow_robj()
+    }
 }
 
 impl ToArrowRobj for ArrayData {
     fn to_arrow_robj(&self) -> Result<Robj> {
+        self.to_arrow_robj_with(&ExportOptions::default())
+    }
+
+    fn to_arrow_robj_with(&self, options: &ExportOptions) -> Result<Robj> {
+        let mut array = make_array(self.clone());
+
+        if options.views.should_cast(array.data_type()) {
+            array = cast_views(&array).map_err(arrow_error)?;
+        }
+
+        let field = rewrite_field(
+            &Field::new("", array.data_type().clone(), true),
+            Some(&[array.clone()]),
+            &options.types,
+        )
+        .map_err(arrow_error)?;
+
+        // rewritten types are recorded in the metadata of the field
+        if !field.metadata().is_empty() {
+            let array = rewrite_array(&array, field.data_type()).map_err(arrow_error)?;
+            let ffi_array = FFI_ArrowArray::new(&array.to_data());
+            let ffi_schema = FFI_ArrowSchema::try_from(&field).map_err(arrow_error)?;
+            return ffi_to_array_robj(ffi_array, ffi_schema);
+        }
+
         // take array data and prepare for FFI
-        let (ffi_array, ffi_schema) = to_ffi(self).expect("success converting arrow data");
+        let (ffi_array, ffi_schema) = to_ffi(&array.to_data()).map_err(arrow_error)?;
+        ffi_to_array_robj(ffi_array, ffi_schema)
+    }
+}
 
-        // extract array pointer. we need it as a string to be used by arrow R package
-        let ffi_array_ptr = &ffi_array as *const FFI_ArrowArray as usize;
-        let arry_addr_chr = ffi_array_ptr.to_string();
+/// Moves an `FFI_ArrowArray` and its `FFI_ArrowSchema` into a `nanoarrow_array`
+#[cfg(any(feature = "nanoarrow", feature = "native", not(feature = "r-arrow")))]
+fn ffi_to_array_robj(
+    mut ffi_array: FFI_ArrowArray,
+    mut ffi_schema: FFI_ArrowSchema,
+) -> Result<Robj> {
+    unsafe {
+        raw::array_into_robj(
+            (&mut ffi_array as *mut FFI_ArrowArray).cast(),
+            (&mut ffi_schema as *mut FFI_ArrowSchema).cast(),
+        )
+    }
+}
 
-        // same deal but with the schema
-        let ffi_schema_ptr = &ffi_schema as *const FFI_ArrowSchema as usize;
-        let schema_addr_chr = ffi_schema_ptr.to_string();
+/// Moves an `FFI_ArrowArray` and its `FFI_ArrowSchema` into an `arrow::Array`
+#[cfg(all(
+    feature = "r-arrow",
+    not(any(feature = "nanoarrow", feature = "native"))
+))]
+fn ffi_to_array_robj(ffi_array: FFI_ArrowArray, ffi_schema: FFI_ArrowSchema) -> Result<Robj> {
+    crate::arrow_r6::array_to_r6("Array", ffi_array, ffi_schema)
+}
 
-        // allocate empty array and schema
-        let arr_to_fill = allocate_array(pairlist!())?;
-        let schema_to_fill = allocate_schema(pairlist!())?;
+/// Moves an `FFI_ArrowSchema` into a `nanoarrow_schema`
+///
+/// `class` is the `{arrow}` R6 class used by the arrow-only backend.
+#[cfg(any(feature = "nanoarrow", feature = "native", not(feature = "r-arrow")))]
+fn ffi_to_schema_robj(mut ffi_schema: FFI_ArrowSchema, _class: &str) -> Result<Robj> {
+    unsafe { raw::schema_into_robj((&mut ffi_schema as *mut FFI_ArrowSchema).cast()) }
+}
 
-        // move pointers
-        let _ = move_pointer(pairlist!(arry_addr_chr, &arr_to_fill));
-        let _ = move_pointer(pairlist!(schema_addr_chr, &schema_to_fill));
+/// Moves an `FFI_ArrowSchema` into an `arrow::Schema`, `arrow::Field` or `arrow::DataType`
+#[cfg(all(
+    feature = "r-arrow",
+    not(any(feature = "nanoarrow", feature = "native"))
+))]
+fn ffi_to_schema_robj(ffi_schema: FFI_ArrowSchema, class: &str) -> Result<Robj> {
+    crate::arrow_r6::schema_to_r6(class, ffi_schema)
+}
+
+/// Exports a `RecordBatchReader` as a `nanoarrow_array_stream`
+#[cfg(any(feature = "nanoarrow", feature = "native", not(feature = "r-arrow")))]
+fn reader_to_stream_robj(reader: Box<dyn RecordBatchReader + Send>) -> Result<Robj> {
+    use arrow::ffi_stream::FFI_ArrowArrayStream;
 
-        set_array_schema(&arr_to_fill, &schema_to_fill);
+    let mut stream = FFI_ArrowArrayStream::new(reader);
+    unsafe { raw::stream_into_robj((&mut stream as *mut FFI_ArrowArrayStream).cast()) }
+}
 
-        Ok(arr_to_fill)
+/// Exports a `RecordBatchReader` as an `arrow::RecordBatchReader`
+#[cfg(all(
+    feature = "r-arrow",
+    not(any(feature = "nanoarrow", feature = "native"))
+))]
+fn reader_to_stream_robj(reader: Box<dyn RecordBatchReader + Send>) -> Result<Robj> {
+    crate::arrow_r6::reader_to_r6(reader)
+}
+
+/// Exports a `RecordBatchReader`, casting the view types of its batches if needed
+fn export_reader(
+    reader: Box<dyn RecordBatchReader + Send>,
+    options: &ExportOptions,
+) -> Result<Robj> {
+    let schema = reader.schema();
+
+    let reader: Box<dyn RecordBatchReader + Send> = if options
+        .views
+        .should_cast(&DataType::Struct(schema.fields().clone()))
+    {
+        Box::new(CastViewsReader::new(reader))
+    } else {
+        reader
+    };
+
+    if options.types.is_noop() {
+        return reader_to_stream_robj(reader);
     }
+
+    let reader = RewriteReader::try_new(reader, &options.types).map_err(arrow_error)?;
+    reader_to_stream_robj(Box::new(reader))
 }
 
-impl<T: ArrowPrimitiveType> ToArrowRobj for PrimitiveArray<T> {
-    fn to_arrow_robj(&self) -> Result<Robj> {
-        let data = self.into_data();
-        data.to_arrow_robj()
+/// Applies the metadata, view and type policies of `options` to batches whose data is known
+///
+/// Unlike `export_reader()`, large offsets can be shrunk.
+fn prepare_batches(
+    batches: Vec<RecordBatch>,
+    options: &ExportOptions,
+) -> std::result::Result<Vec<RecordBatch>, ArrowError> {
+    let batches = unify_metadata(batches, options.metadata_conflict)?;
+    let Some(schema) = batches.first().map(|batch| batch.schema()) else {
+        return Ok(batches);
+    };
+
+    let batches = if options
+        .views
+        .should_cast(&DataType::Struct(schema.fields().clone()))
+    {
+        let schema = Arc::new(cast_view_schema(&schema));
+        batches
+            .iter()
+            .map(|batch| cast_batch_views(batch, schema.clone()))
+            .collect::<std::result::Result<Vec<_>, _>>()?
+    } else {
+        batches
+    };
+
+    if options.types.is_noop() {
+        return Ok(batches);
     }
+
+    rewrite_batches(&batches, &options.types)
 }
 
-impl ToArrowRobj for Field {
-    fn to_arrow_robj(&self) -> Result<Robj> {
-        let ffi_schema = FFI_ArrowSchema::try_from(self).expect("Field is FFI compatible");
-        let ffi_schema_ptr = &ffi_schema as *const FFI_ArrowSchema as usize;
-        let schema_addr_chr = ffi_schema_ptr.to_string();
+/// Convert a `RecordBatch` into a struct `nanoarrow_array`
+///
+/// Unlike `to_arrow_robj()`, which returns a single batch `nanoarrow_array_stream`,
+/// the result can be passed to `as.data.frame()` directly. Schema metadata is kept.
+///
+/// **Requires `nanoarrow` to be available**.
+pub trait ToArrowArrayRobj {
+    fn to_arrow_array_robj(&self) -> Result<Robj>;
 
-        // allocate the schema
-        let schema_to_fill = allocate_schema(pairlist!())?;
+    /// Exports with `options`
+    fn to_arrow_array_robj_with(&self, options: &ExportOptions) -> Result<Robj> {
+        let _ = options;
+        self.to_arrow_array_robj()
+    }
+}
+
+impl ToArrowArrayRobj for RecordBatch {
+    fn to_arrow_array_robj(&self) -> Result<Robj> {
+        self.to_arrow_array_robj_with(&ExportOptions::default())
+    }
 
-        // fill the schema with the FFI_ArrowSchema
-        let _ = move_pointer(pairlist!(schema_addr_chr, &schema_to_fill));
+    fn to_arrow_array_robj_with(&self, options: &ExportOptions) -> Result<Robj> {
+        let batch = prepare_batches(vec![self.clone()], options)
+            .map_err(arrow_error)?
+            .remove(0);
 
-        Ok(schema_to_fill)
+        let data = StructArray::from(batch.clone()).into_data();
+        let ffi_array = FFI_ArrowArray::new(&data);
+        let ffi_schema = FFI_ArrowSchema::try_from(batch.schema().as_ref()).map_err(arrow_error)?;
+
+        ffi_to_array_robj(ffi_array, ffi_schema)
     }
 }
 
-impl ToArrowRobj for Schema {
+impl<T: ArrowPrimitiveType> ToArrowRobj for PrimitiveArray<T> {
     fn to_arrow_robj(&self) -> Result<Robj> {
-        let ffi_schema = FFI_ArrowSchema::try_from(self).expect("valid Schema");
-
-        // allocate and get pntr address
-        let ffi_schema_ptr = &ffi_schema as *const FFI_ArrowSchema as usize;
-        let schema_addr_chr = ffi_schema_ptr.to_string();
+        self.to_arrow_robj_with(&ExportOptions::default())
+    }
 
-        // allocate the schema
-        let schema_to_fill = allocate_schema(pairlist!())?;
+    fn to_arrow_robj_with(&self, options: &ExportOptions) -> Result<Robj> {
+        self.to_data().to_arrow_robj_with(options)
+    }
+}
 
-        // fill the schema with the FFI_ArrowSchema
-        let _ = move_pointer(pairlist!(schema_addr_chr, &schema_to_fill));
+/// Exported as a length-1 `nanoarrow_array`
+impl ToArrowRobj for Scalar<ArrayRef> {
+    fn to_arrow_robj(&self) -> Result<Robj> {
+        self.to_arrow_robj_with(&ExportOptions::default())
+    }
 
-        Ok(schema_to_fill)
+    fn to_arrow_robj_with(&self, options: &ExportOptions) -> Result<Robj> {
+        let (array, _) = self.get();
+        array.to_data().to_arrow_robj_with(options)
     }
 }
 
-impl ToArrowRobj for DataType {
+impl ToArrowRobj for Field {
     fn to_arrow_robj(&self) -> Result<Robj> {
-        let ffi_schema = FFI_ArrowSchema::try_from(self).expect("valid Schema");
+        self.to_arrow_robj_with(&ExportOptions::default())
+    }
 
-        let ffi_schema_ptr = &ffi_schema as *const FFI_ArrowSchema as usize;
-        let schema_addr_chr = ffi_schema_ptr.to_string();
+    fn to_arrow_robj_with(&self, options: &ExportOptions) -> Result<Robj> {
+        let field = if options.views.should_cast(self.data_type()) {
+            cast_view_field(self)
+        } else {
+            self.clone()
+        };
+        let field = rewrite_field(&field, None, &options.types).map_err(arrow_error)?;
+        let ffi_schema = FFI_ArrowSchema::try_from(&field);
+        ffi_to_schema_robj(ffi_schema.map_err(arrow_error)?, "Field")
+    }
+}
 
-        // allocate the schema
-        let schema_to_fill = allocate_schema(pairlist!())?;
+impl ToArrowRobj for Schema {
+    fn to_arrow_robj(&self) -> Result<Robj> {
+        self.to_arrow_robj_with(&ExportOptions::default())
+    }
 
-        // fill the schema with the FFI_ArrowSchema
-        let _ = move_pointer(pairlist!(schema_addr_chr, &schema_to_fill));
+    fn to_arrow_robj_with(&self, options: &ExportOptions) -> Result<Robj> {
+        let schema = if options
+            .views
+            .should_cast(&DataType::Struct(self.fields().clone()))
+        {
+            cast_view_schema(self)
+        } else {
+            self.clone()
+        };
+        let schema = rewrite_schema(&schema, &options.types).map_err(arrow_error)?;
+        let ffi_schema = FFI_ArrowSchema::try_from(&schema);
+        ffi_to_schema_robj(ffi_schema.map_err(arrow_error)?, "Schema")
+    }
+}
 
-        Ok(schema_to_fill)
+impl ToArrowRobj for DataType {
+    fn to_arrow_robj(&self) -> Result<Robj> {
+        self.to_arrow_robj_with(&ExportOptions::default())
+    }
+
+    fn to_arrow_robj_with(&self, options: &ExportOptions) -> Result<Robj> {
+        let data_type = if options.views.should_cast(self) {
+            cast_view_type(self)
+        } else {
+            self.clone()
+        };
+
+        // the original type can only be recorded in the metadata of a field
+        let field = rewrite_field(&Field::new("", data_type, true), None, &options.types)
+            .map_err(arrow_error)?;
+        let ffi_schema = if field.metadata().is_empty() {
+            FFI_ArrowSchema::try_from(field.data_type())
+        } else {
+            FFI_ArrowSchema::try_from(&field)
+        };
+        ffi_to_schema_robj(ffi_schema.map_err(arrow_error)?, "DataType")
     }
 }
 
 impl ToArrowRobj for RecordBatch {
     fn to_arrow_robj(&self) -> Result<Robj> {
-        let reader = RecordBatchIterator::new(vec![Ok(self.clone())], self.schema().clone());
-        let reader: Box<dyn RecordBatchReader + Send> = Box::new(reader);
-        let mut stream = FFI_ArrowArrayStream::new(reader);
-        let stream_ptr = (&mut stream) as *mut FFI_ArrowArrayStream as usize;
-
-        let stream_to_fill = allocate_array_stream(pairlist!())?;
-        let _ = move_pointer(pairlist!(stream_ptr.to_string(), &stream_to_fill));
+        self.to_arrow_robj_with(&ExportOptions::default())
+    }
 
-        Ok(stream_to_fill)
+    fn to_arrow_robj_with(&self, options: &ExportOptions) -> Result<Robj> {
+        vec![self.clone()].into_arrow_robj_with(options)
     }
 }
 
@@ -202,9 +564,18 @@ impl ToArrowRobj for RecordBatch {
 /// Consumes `self`. Takes an arrow-rs struct and converts it into
 /// a `{nanoarrow}` S3 object of class `nanoarrow_array`, `nanoarrow_array_stream`, or `nanoarrow_schema`.
 ///
-/// **Requires `nanoarrow` to be available**.
-pub trait IntoArrowRobj {
+/// **Requires `nanoarrow` to be available**, unless another backend is
+/// selected, see the `backend` module.
+pub trait IntoArrowRobj: Sized {
     fn into_arrow_robj(self) -> Result<Robj>;
+
+    /// Exports with `options`
+    ///
+    /// Ignores `options` unless overridden by structs that can hold view types.
+    fn into_arrow_robj_with(self, options: &ExportOptions) -> Result<Robj> {
+        let _ = options;
+        self.into_arrow_robj()
+    }
 }
 
 // macro to implement `IntoArrowRobj` for those that have `ToArrowRobj` implemented
@@ -214,6 +585,10 @@ macro_rules! impl_into_arrow {
             fn into_arrow_robj(self) -> Result<Robj> {
                 self.to_arrow_robj()
             }
+
+            fn into_arrow_robj_with(self, options: &ExportOptions) -> Result<Robj> {
+                self.to_arrow_robj_with(options)
+            }
         }
     };
 }
@@ -224,63 +599,73 @@ impl_into_arrow!(Schema);
 impl_into_arrow!(DataType);
 impl_into_arrow!(RecordBatch);
 
-// macro doesn't permit generics
-impl<T: ArrowPrimitiveType> IntoArrowRobj for PrimitiveArray<T> {
+impl IntoArrowRobj for Scalar<ArrayRef> {
     fn into_arrow_robj(self) -> Result<Robj> {
         self.to_arrow_robj()
     }
-}
 
-/// Function that will take an ArrowArrayStreamReader and turn into Robj
-fn to_arrow_robj_stream_reader(reader: ArrowArrayStreamReader) -> Result<Robj> {
-    let reader: Box<dyn RecordBatchReader + Send> = Box::new(reader);
-    let mut stream = FFI_ArrowArrayStream::new(reader);
-    let stream_ptr = (&mut stream) as *mut FFI_ArrowArrayStream as usize;
+    fn into_arrow_robj_with(self, options: &ExportOptions) -> Result<Robj> {
+        self.to_arrow_robj_with(options)
+    }
+}
 
-    let stream_to_fill = allocate_array_stream(pairlist!())?;
-    let _ = move_pointer(pairlist!(stream_ptr.to_string(), &stream_to_fill));
+// macro doesn't permit generics
+impl<T: ArrowPrimitiveType> IntoArrowRobj for PrimitiveArray<T> {
+    fn into_arrow_robj(self) -> Result<Robj> {
+        self.to_arrow_robj()
+    }
 
-    Ok(stream_to_fill)
+    fn into_arrow_robj_with(self, options: &ExportOptions) -> Result<Robj> {
+        self.to_arrow_robj_with(options)
+    }
 }
 
 impl IntoArrowRobj for ArrowArrayStreamReader {
     fn into_arrow_robj(self) -> Result<Robj> {
-        to_arrow_robj_stream_reader(self)
+        self.into_arrow_robj_with(&ExportOptions::default())
+    }
+
+    fn into_arrow_robj_with(self, options: &ExportOptions) -> Result<Robj> {
+        export_reader(Box::new(self), options)
     }
 }
 
 impl IntoArrowRobj for Box<dyn RecordBatchReader + Send> {
     fn into_arrow_robj(self) -> Result<Robj> {
-        let mut stream = FFI_ArrowArrayStream::new(self);
-        let stream_ptr = (&mut stream) as *mut FFI_ArrowArrayStream as usize;
-
-        let stream_to_fill = allocate_array_stream(pairlist!())?;
-        let _ = move_pointer(pairlist!(stream_ptr.to_string(), &stream_to_fill));
+        self.into_arrow_robj_with(&ExportOptions::default())
+    }
 
-        Ok(stream_to_fill)
+    fn into_arrow_robj_with(self, options: &ExportOptions) -> Result<Robj> {
+        export_reader(self, options)
     }
 }
 
+/// An empty vector is exported as a stream without columns, use
+/// `batches::RecordBatches` to give it a schema
 impl IntoArrowRobj for Vec<RecordBatch> {
     fn into_arrow_robj(self) -> Result<Robj> {
+        self.into_arrow_robj_with(&ExportOptions::default())
+    }
+
+    fn into_arrow_robj_with(self, options: &ExportOptions) -> Result<Robj> {
         // if there is an empty vector we create an empty RecordBatch
         if self.is_empty() {
             let sb = SchemaBuilder::new();
             let schema = sb.finish();
             let empty_iter = vec![].into_iter();
             let rb = arrow::record_batch::RecordBatchIterator::new(empty_iter, schema.into());
-            return rb.into_arrow_robj();
+            return rb.into_arrow_robj_with(options);
         }
 
-        let schema = self[0].schema();
+        let batches = prepare_batches(self, options).map_err(arrow_error)?;
+        let schema = batches[0].schema();
 
-        let res = self.into_iter().map(Ok::<RecordBatch, ArrowError>);
+        let res = batches.into_iter().map(Ok::<RecordBatch, ArrowError>);
 
         let rbit = arrow::record_batch::RecordBatchIterator::new(res, schema);
 
-        let reader: Box<dyn RecordBatchReader + Send> = Box::new(rbit);
-
-        reader.into_arrow_robj()
+        // the batches are already prepared
+        reader_to_stream_robj(Box::new(rbit))
     }
 }
 
@@ -290,7 +675,11 @@ where
     <I as IntoIterator>::IntoIter: Send,
 {
     fn into_arrow_robj(self) -> Result<Robj> {
+        self.into_arrow_robj_with(&ExportOptions::default())
+    }
+
+    fn into_arrow_robj_with(self, options: &ExportOptions) -> Result<Robj> {
         let reader: Box<dyn RecordBatchReader + Send> = Box::new(self);
-        reader.into_arrow_robj()
+        reader.into_arrow_robj_with(options)
     }
 }
@@ -0,0 +1,548 @@
+//! Conversions for other major versions of arrow-rs
+//!
+//! arrow-extendr is built against one major version of arrow-rs, the `arrow`
+//! dependency. Crates that depend on a different major version can still use
+//! the traits in this crate by enabling the matching feature. The types of
+//! the other version are converted to and from the base version, either
+//! through the C Data Interface, whose structs have the same layout in every
+//! version, or by sharing the underlying buffers. No data is copied.
+//!
+//! |  feature   |   module   | arrow-rs |
+//! | ---------- | ---------- | -------- |
+//! | `arrow-52` | `arrow_52` | 52       |
+//! | `arrow-54` | `arrow_54` | 54       |
+//!
+//! Only the crates that make up the C Data Interface and the C Stream
+//! Interface are needed so the following types are supported for each
+//! version.
+//!
+//! |     arrow-rs struct      |                      traits                     |
+//! | ------------------------ | ----------------------------------------------- |
+//! | `Field`                  | `FromArrowRobj`, `ToArrowRobj`, `IntoArrowRobj` |
+//! | `DataType`               | `FromArrowRobj`, `ToArrowRobj`, `IntoArrowRobj` |
+//! | `Schema`                 | `FromArrowRobj`, `ToArrowRobj`, `IntoArrowRobj` |
+//! | `ArrayData`              | `FromArrowRobj`, `ToArrowRobj`, `IntoArrowRobj` |
+//! | `RecordBatch`            | `FromArrowRobj`, `ToArrowRobj`, `IntoArrowRobj` |
+//! | `ArrowArrayStreamReader` | `FromArrowRobj`, `IntoArrowRobj`                |
+//!
+//! Streams are passed between versions as an `FFI_ArrowArrayStream`, so
+//! batches are converted one at a time as they are read.
+//!
+//! ```ignore
+//! use arrow_52::array::ArrayData;
+//!
+//! fn array_from_r(x: Robj) -> Result<ArrayData> {
+//!     ArrayData::from_arrow_robj(&x).map_err(arrow_error)
+//! }
+//! ```
+use std::mem::{size_of, ManuallyDrop};
+
+use crate::from::ErrArrowRobj;
+
+/// Moves a C Data Interface struct into the same struct of another arrow-rs version
+///
+/// Returns an error, and releases `a`, if the structs differ in size.
+///
+/// # Safety
+///
+/// `A` and `B` must be the same `#[repr(C)]` struct.
+pub unsafe fn cast_ffi<A, B>(a: A) -> std::result::Result<B, ErrArrowRobj> {
+    if size_of::<A>() != size_of::<B>() {
+        return Err(ErrArrowRobj::CDataInterface(format!(
+            "cannot cast a C struct of {} bytes into one of {} bytes",
+            size_of::<A>(),
+            size_of::<B>()
+        )));
+    }
+
+    let a = ManuallyDrop::new(a);
+    Ok(std::ptr::read(&*a as *const A as *const B))
+}
+
+// implements the conversions for a version given the crates that provide its
+// `arrow-array`, `arrow-buffer`, `arrow-data` and `arrow-schema`
+macro_rules! arrow_version {
+    ($(#[$meta:meta])* $name:ident, $array:ident, $buffer:ident, $data:ident, $schema:ident) => {
+        $(#[$meta])*
+        pub mod $name {
+            use std::{ptr::NonNull, sync::Arc};
+
+            use arrow::{
+                array::{Array, ArrayData},
+                buffer::Buffer,
+                datatypes::{DataType, Field, Schema},
+                ffi::{self, FFI_ArrowArray, FFI_ArrowSchema},
+                ffi_stream::{ArrowArrayStreamReader, FFI_ArrowArrayStream},
+                record_batch::{RecordBatch, RecordBatchReader},
+            };
+            use $array::Array as _;
+            use extendr_api::prelude::*;
+
+            use super::cast_ffi;
+            use crate::{
+                from::{ErrArrowRobj, FromArrowRobj, ImportOptions},
+                to::{arrow_error, ExportOptions, IntoArrowRobj, ToArrowRobj},
+            };
+
+            fn version_error(e: $schema::ArrowError) -> ErrArrowRobj {
+                ErrArrowRobj::CDataInterface(e.to_string())
+            }
+
+            /// Converts a `DataType` of the base version
+            pub fn data_type_from_base(
+                data_type: &DataType,
+            ) -> std::result::Result<$schema::DataType, ErrArrowRobj> {
+                let ffi_schema = FFI_ArrowSchema::try_from(data_type)?;
+                let ffi_schema: $schema::ffi::FFI_ArrowSchema = unsafe { cast_ffi(ffi_schema)? };
+                $schema::DataType::try_from(&ffi_schema).map_err(version_error)
+            }
+
+            /// Converts a `DataType` into the base version
+            pub fn data_type_to_base(
+                data_type: &$schema::DataType,
+            ) -> std::result::Result<DataType, ErrArrowRobj> {
+                let ffi_schema =
+                    $schema::ffi::FFI_ArrowSchema::try_from(data_type).map_err(version_error)?;
+                let ffi_schema: FFI_ArrowSchema = unsafe { cast_ffi(ffi_schema)? };
+                DataType::try_from(&ffi_schema)
+            }
+
+            /// Converts a `Field` of the base version
+            pub fn field_from_base(
+                field: &Field,
+            ) -> std::result::Result<$schema::Field, ErrArrowRobj> {
+                let ffi_schema = FFI_ArrowSchema::try_from(field)?;
+                let ffi_schema: $schema::ffi::FFI_ArrowSchema = unsafe { cast_ffi(ffi_schema)? };
+                $schema::Field::try_from(&ffi_schema).map_err(version_error)
+            }
+
+            /// Converts a `Field` into the base version
+            pub fn field_to_base(
+                field: &$schema::Field,
+            ) -> std::result::Result<Field, ErrArrowRobj> {
+                let ffi_schema =
+                    $schema::ffi::FFI_ArrowSchema::try_from(field).map_err(version_error)?;
+                let ffi_schema: FFI_ArrowSchema = unsafe { cast_ffi(ffi_schema)? };
+                Field::try_from(&ffi_schema)
+            }
+
+            /// Converts a `Schema` of the base version
+            pub fn schema_from_base(
+                schema: &Schema,
+            ) -> std::result::Result<$schema::Schema, ErrArrowRobj> {
+                let ffi_schema = FFI_ArrowSchema::try_from(schema)?;
+                let ffi_schema: $schema::ffi::FFI_ArrowSchema = unsafe { cast_ffi(ffi_schema)? };
+                $schema::Schema::try_from(&ffi_schema).map_err(version_error)
+            }
+
+            /// Converts a `Schema` into the base version
+            pub fn schema_to_base(
+                schema: &$schema::Schema,
+            ) -> std::result::Result<Schema, ErrArrowRobj> {
+                let ffi_schema =
+                    $schema::ffi::FFI_ArrowSchema::try_from(schema).map_err(version_error)?;
+                let ffi_schema: FFI_ArrowSchema = unsafe { cast_ffi(ffi_schema)? };
+                Schema::try_from(&ffi_schema)
+            }
+
+            // the new buffer keeps the original alive
+            fn buffer_from_base(buffer: &Buffer) -> $buffer::Buffer {
+                let ptr = NonNull::new(buffer.as_ptr() as *mut u8).unwrap_or(NonNull::dangling());
+                unsafe {
+                    $buffer::Buffer::from_custom_allocation(ptr, buffer.len(), Arc::new(buffer.clone()))
+                }
+            }
+
+            /// Converts an `ArrayData` of the base version, sharing its buffers
+            pub fn array_data_from_base(
+                data: &ArrayData,
+            ) -> std::result::Result<$data::ArrayData, ErrArrowRobj> {
+                let nulls = data.nulls().map(|nulls| {
+                    let inner = nulls.inner();
+                    $buffer::NullBuffer::new($buffer::BooleanBuffer::new(
+                        buffer_from_base(inner.inner()),
+                        inner.offset(),
+                        inner.len(),
+                    ))
+                });
+
+                let child_data = data
+                    .child_data()
+                    .iter()
+                    .map(array_data_from_base)
+                    .collect::<std::result::Result<Vec<_>, _>>()?;
+
+                $data::ArrayData::builder(data_type_from_base(data.data_type())?)
+                    .len(data.len())
+                    .offset(data.offset())
+                    .buffers(data.buffers().iter().map(buffer_from_base).collect())
+                    .child_data(child_data)
+                    .nulls(nulls)
+                    .build()
+                    .map_err(version_error)
+            }
+
+            /// Converts an `ArrayData` into the base version through the C Data Interface
+            pub fn array_data_to_base(
+                data: &$data::ArrayData,
+            ) -> std::result::Result<ArrayData, ErrArrowRobj> {
+                let ffi_array = $data::ffi::FFI_ArrowArray::new(data);
+                let ffi_schema = $schema::ffi::FFI_ArrowSchema::try_from(data.data_type())
+                    .map_err(version_error)?;
+
+                let ffi_array: FFI_ArrowArray = unsafe { cast_ffi(ffi_array)? };
+                let ffi_schema: FFI_ArrowSchema = unsafe { cast_ffi(ffi_schema)? };
+
+                unsafe { ffi::from_ffi(ffi_array, &ffi_schema) }
+            }
+
+            /// Converts a `RecordBatch` of the base version, sharing its buffers
+            pub fn record_batch_from_base(
+                batch: &RecordBatch,
+            ) -> std::result::Result<$array::RecordBatch, ErrArrowRobj> {
+                let schema = schema_from_base(batch.schema_ref())?;
+                let columns = batch
+                    .columns()
+                    .iter()
+                    .map(|column| {
+                        array_data_from_base(&column.to_data()).map($array::make_array)
+                    })
+                    .collect::<std::result::Result<Vec<_>, _>>()?;
+
+                $array::RecordBatch::try_new(Arc::new(schema), columns).map_err(version_error)
+            }
+
+            /// Converts a `RecordBatch` into the base version through the C Data Interface
+            pub fn record_batch_to_base(
+                batch: &$array::RecordBatch,
+            ) -> std::result::Result<RecordBatch, ErrArrowRobj> {
+                let schema = schema_to_base(batch.schema_ref())?;
+                let columns = batch
+                    .columns()
+                    .iter()
+                    .map(|column| array_data_to_base(&column.to_data()).map(arrow::array::make_array))
+                    .collect::<std::result::Result<Vec<_>, _>>()?;
+
+                RecordBatch::try_new(Arc::new(schema), columns)
+            }
+
+            /// Converts a stream of the base version through the C Stream Interface
+            pub fn reader_from_base(
+                reader: Box<dyn RecordBatchReader + Send>,
+            ) -> std::result::Result<$array::ffi_stream::ArrowArrayStreamReader, ErrArrowRobj> {
+                let stream = FFI_ArrowArrayStream::new(reader);
+                let stream: $array::ffi_stream::FFI_ArrowArrayStream =
+                    unsafe { cast_ffi(stream)? };
+                $array::ffi_stream::ArrowArrayStreamReader::try_new(stream).map_err(version_error)
+            }
+
+            /// Converts a stream into the base version through the C Stream Interface
+            pub fn reader_to_base(
+                reader: Box<dyn $array::RecordBatchReader + Send>,
+            ) -> std::result::Result<ArrowArrayStreamReader, ErrArrowRobj> {
+                let stream = $array::ffi_stream::FFI_ArrowArrayStream::new(reader);
+                let stream: FFI_ArrowArrayStream = unsafe { cast_ffi(stream)? };
+                ArrowArrayStreamReader::try_new(stream)
+            }
+
+            impl FromArrowRobj for $schema::DataType {
+                fn from_arrow_robj(robj: &Robj) -> std::result::Result<Self, ErrArrowRobj> {
+                    Self::from_arrow_robj_with(robj, &ImportOptions::default())
+                }
+
+                fn from_arrow_robj_with(
+                    robj: &Robj,
+                    options: &ImportOptions,
+                ) -> std::result::Result<Self, ErrArrowRobj> {
+                    data_type_from_base(&DataType::from_arrow_robj_with(robj, options)?)
+                }
+            }
+
+            impl FromArrowRobj for $schema::Field {
+                fn from_arrow_robj(robj: &Robj) -> std::result::Result<Self, ErrArrowRobj> {
+                    Self::from_arrow_robj_with(robj, &ImportOptions::default())
+                }
+
+                fn from_arrow_robj_with(
+                    robj: &Robj,
+                    options: &ImportOptions,
+                ) -> std::result::Result<Self, ErrArrowRobj> {
+                    field_from_base(&Field::from_arrow_robj_with(robj, options)?)
+                }
+            }
+
+            impl FromArrowRobj for $schema::Schema {
+                fn from_arrow_robj(robj: &Robj) -> std::result::Result<Self, ErrArrowRobj> {
+                    Self::from_arrow_robj_with(robj, &ImportOptions::default())
+                }
+
+                fn from_arrow_robj_with(
+                    robj: &Robj,
+                    options: &ImportOptions,
+                ) -> std::result::Result<Self, ErrArrowRobj> {
+                    schema_from_base(&Schema::from_arrow_robj_with(robj, options)?)
+                }
+            }
+
+            impl FromArrowRobj for $data::ArrayData {
+                fn from_arrow_robj(robj: &Robj) -> std::result::Result<Self, ErrArrowRobj> {
+                    Self::from_arrow_robj_with(robj, &ImportOptions::default())
+                }
+
+                fn from_arrow_robj_with(
+                    robj: &Robj,
+                    options: &ImportOptions,
+                ) -> std::result::Result<Self, ErrArrowRobj> {
+                    array_data_from_base(&ArrayData::from_arrow_robj_with(robj, options)?)
+                }
+            }
+
+            impl FromArrowRobj for $array::RecordBatch {
+                fn from_arrow_robj(robj: &Robj) -> std::result::Result<Self, ErrArrowRobj> {
+                    Self::from_arrow_robj_with(robj, &ImportOptions::default())
+                }
+
+                fn from_arrow_robj_with(
+                    robj: &Robj,
+                    options: &ImportOptions,
+                ) -> std::result::Result<Self, ErrArrowRobj> {
+                    record_batch_from_base(&RecordBatch::from_arrow_robj_with(robj, options)?)
+                }
+            }
+
+            impl FromArrowRobj for $array::ffi_stream::ArrowArrayStreamReader {
+                fn from_arrow_robj(robj: &Robj) -> std::result::Result<Self, ErrArrowRobj> {
+                    Self::from_arrow_robj_with(robj, &ImportOptions::default())
+                }
+
+                fn from_arrow_robj_with(
+                    robj: &Robj,
+                    options: &ImportOptions,
+                ) -> std::result::Result<Self, ErrArrowRobj> {
+                    let reader = ArrowArrayStreamReader::from_arrow_robj_with(robj, options)?;
+                    reader_from_base(Box::new(reader))
+                }
+            }
+
+            impl ToArrowRobj for $schema::DataType {
+                fn to_arrow_robj(&self) -> Result<Robj> {
+                    self.to_arrow_robj_with(&ExportOptions::default())
+                }
+
+                fn to_arrow_robj_with(&self, options: &ExportOptions) -> Result<Robj> {
+                    data_type_to_base(self)
+                        .map_err(arrow_error)?
+                        .to_arrow_robj_with(options)
+                }
+            }
+
+            impl ToArrowRobj for $schema::Field {
+                fn to_arrow_robj(&self) -> Result<Robj> {
+                    self.to_arrow_robj_with(&ExportOptions::default())
+                }
+
+                fn to_arrow_robj_with(&self, options: &ExportOptions) -> Result<Robj> {
+                    field_to_base(self)
+                        .map_err(arrow_error)?
+                        .to_arrow_robj_with(options)
+                }
+            }
+
+            impl ToArrowRobj for $schema::Schema {
+                fn to_arrow_robj(&self) -> Result<Robj> {
+                    self.to_arrow_robj_with(&ExportOptions::default())
+                }
+
+                fn to_arrow_robj_with(&self, options: &ExportOptions) -> Result<Robj> {
+                    schema_to_base(self)
+                        .map_err(arrow_error)?
+                        .to_arrow_robj_with(options)
+                }
+            }
+
+            impl ToArrowRobj for $data::ArrayData {
+                fn to_arrow_robj(&self) -> Result<Robj> {
+                    self.to_arrow_robj_with(&ExportOptions::default())
+                }
+
+                fn to_arrow_robj_with(&self, options: &ExportOptions) -> Result<Robj> {
+                    array_data_to_base(self)
+                        .map_err(arrow_error)?
+                        .to_arrow_robj_with(options)
+                }
+            }
+
+            impl ToArrowRobj for $array::RecordBatch {
+                fn to_arrow_robj(&self) -> Result<Robj> {
+                    self.to_arrow_robj_with(&ExportOptions::default())
+                }
+
+                fn to_arrow_robj_with(&self, options: &ExportOptions) -> Result<Robj> {
+                    record_batch_to_base(self)
+                        .map_err(arrow_error)?
+                        .to_arrow_robj_with(options)
+                }
+            }
+
+            impl IntoArrowRobj for $array::RecordBatch {
+                fn into_arrow_robj(self) -> Result<Robj> {
+                    self.to_arrow_robj()
+                }
+
+                fn into_arrow_robj_with(self, options: &ExportOptions) -> Result<Robj> {
+                    self.to_arrow_robj_with(options)
+                }
+            }
+
+            impl IntoArrowRobj for $array::ffi_stream::ArrowArrayStreamReader {
+                fn into_arrow_robj(self) -> Result<Robj> {
+                    self.into_arrow_robj_with(&ExportOptions::default())
+                }
+
+                fn into_arrow_robj_with(self, options: &ExportOptions) -> Result<Robj> {
+                    reader_to_base(Box::new(self))
+                        .map_err(arrow_error)?
+                        .into_arrow_robj_with(options)
+                }
+            }
+
+            impl IntoArrowRobj for $schema::DataType {
+                fn into_arrow_robj(self) -> Result<Robj> {
+                    self.to_arrow_robj()
+                }
+
+                fn into_arrow_robj_with(self, options: &ExportOptions) -> Result<Robj> {
+                    self.to_arrow_robj_with(options)
+                }
+            }
+
+            impl IntoArrowRobj for $schema::Field {
+                fn into_arrow_robj(self) -> Result<Robj> {
+                    self.to_arrow_robj()
+                }
+
+                fn into_arrow_robj_with(self, options: &ExportOptions) -> Result<Robj> {
+                    self.to_arrow_robj_with(options)
+                }
+            }
+
+            impl IntoArrowRobj for $schema::Schema {
+                fn into_arrow_robj(self) -> Result<Robj> {
+                    self.to_arrow_robj()
+                }
+
+                fn into_arrow_robj_with(self, options: &ExportOptions) -> Result<Robj> {
+                    self.to_arrow_robj_with(options)
+                }
+            }
+
+            impl IntoArrowRobj for $data::ArrayData {
+                fn into_arrow_robj(self) -> Result<Robj> {
+                    self.to_arrow_robj()
+                }
+
+                fn into_arrow_robj_with(self, options: &ExportOptions) -> Result<Robj> {
+                    self.to_arrow_robj_with(options)
+                }
+            }
+        }
+    };
+}
+
+arrow_version!(
+    /// Conversions for arrow-rs 52
+    #[cfg(feature = "arrow-52")]
+    arrow_52,
+    arrow_array_52,
+    arrow_buffer_52,
+    arrow_data_52,
+    arrow_schema_52
+);
+
+arrow_version!(
+    /// Conversions for arrow-rs 54
+    #[cfg(feature = "arrow-54")]
+    arrow_54,
+    arrow_array_54,
+    arrow_buffer_54,
+    arrow_data_54,
+    arrow_schema_54
+);
+
+// the same tests for every version
+#[cfg(test)]
+macro_rules! version_tests {
+    ($(#[$meta:meta])* $name:ident, $version:ident) => {
+        $(#[$meta])*
+        mod $name {
+            use std::sync::Arc;
+
+            use arrow::{
+                array::{Int32Array, StringArray},
+                datatypes::{DataType, Field, Schema},
+                record_batch::{RecordBatch, RecordBatchIterator, RecordBatchReader},
+            };
+
+            use super::$version::*;
+
+            fn batch() -> RecordBatch {
+                let schema = Schema::new(vec![
+                    Field::new("x", DataType::Int32, false),
+                    Field::new("y", DataType::Utf8, true),
+                ])
+                .with_metadata([("k".to_string(), "v".to_string())].into());
+
+                RecordBatch::try_new(
+                    Arc::new(schema),
+                    vec![
+                        Arc::new(Int32Array::from(vec![1, 2, 3])),
+                        Arc::new(StringArray::from(vec![Some("a"), None, Some("c")])),
+                    ],
+                )
+                .unwrap()
+            }
+
+            #[test]
+            fn round_trips_a_record_batch() {
+                let batch = batch();
+
+                let other = record_batch_from_base(&batch).unwrap();
+                assert_eq!(other.num_rows(), 3);
+                assert_eq!(other.schema().metadata()["k"], "v");
+
+                assert_eq!(record_batch_to_base(&other).unwrap(), batch);
+            }
+
+            #[test]
+            fn round_trips_a_stream() {
+                let batch = batch();
+                let schema = batch.schema();
+                let reader =
+                    RecordBatchIterator::new(vec![Ok(batch.clone()), Ok(batch.clone())], schema);
+
+                let other = reader_from_base(Box::new(reader)).unwrap();
+                let reader = reader_to_base(Box::new(other)).unwrap();
+                assert_eq!(reader.schema(), batch.schema());
+
+                // arrow-rs stream readers do not attach the schema metadata to batches
+                let batches = reader.collect::<Result<Vec<_>, _>>().unwrap();
+                assert_eq!(batches.len(), 2);
+                assert!(batches.iter().all(|b| b.columns() == batch.columns()));
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+version_tests!(
+    #[cfg(feature = "arrow-52")]
+    tests_52,
+    arrow_52
+);
+
+#[cfg(test)]
+version_tests!(
+    #[cfg(feature = "arrow-54")]
+    tests_54,
+    arrow_54
+);
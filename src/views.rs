@@ -0,0 +1,577 @@
+//! Cast Arrow view types for R packages that do not support them
+//!
+//! `Utf8View`, `BinaryView`, `ListView` and `LargeListView` store an offset
+//! and a size, or an inline prefix, for each element instead of a single
+//! offsets buffer. Older versions of `{nanoarrow}` and `{arrow}` cannot read
+//! them, so by default they are cast on export when the R package used by the
+//! backend does not support them, see `ExportOptions` and `ViewPolicy`.
+//!
+//! |     view type     |          cast to          |
+//! | ----------------- | ------------------------- |
+//! | `Utf8View`        | `Utf8` or `LargeUtf8`     |
+//! | `BinaryView`      | `Binary` or `LargeBinary` |
+//! | `ListView`        | `List`                    |
+//! | `LargeListView`   | `LargeList`               |
+//!
+//! The large variants of `Utf8` and `Binary` are only used for arrays whose
+//! data does not fit in 32-bit offsets. Streams use the types of their schema.
+//!
+//! arrow-rs 53 cannot move `ListView` and `LargeListView` through the C Data
+//! Interface so they must be cast, whatever the policy.
+use std::sync::Arc;
+
+use arrow::{
+    array::{
+        make_array, Array, ArrayRef, AsArray, FixedSizeListArray, GenericListArray,
+        GenericListViewArray, MapArray, OffsetSizeTrait, StructArray, UInt64Array, UnionArray,
+    },
+    buffer::OffsetBuffer,
+    compute::{cast, take},
+    datatypes::{DataType, Field, FieldRef, Schema, SchemaRef},
+    error::ArrowError,
+    record_batch::{RecordBatch, RecordBatchReader},
+};
+
+/// Checks if a `DataType` is or contains a view type
+pub fn has_views(data_type: &DataType) -> bool {
+    match data_type {
+        DataType::Utf8View
+        | DataType::BinaryView
+        | DataType::ListView(_)
+        | DataType::LargeListView(_) => true,
+        DataType::List(field)
+        | DataType::LargeList(field)
+        | DataType::FixedSizeList(field, _)
+        | DataType::Map(field, _) => has_views(field.data_type()),
+        DataType::Struct(fields) => fields.iter().any(|f| has_views(f.data_type())),
+        DataType::Union(fields, _) => fields.iter().any(|(_, f)| has_views(f.data_type())),
+        DataType::Dictionary(_, value) => has_views(value),
+        DataType::RunEndEncoded(_, values) => has_views(values.data_type()),
+        _ => false,
+    }
+}
+
+/// Replaces every view type in a `DataType` with its non-view equivalent
+pub fn cast_view_type(data_type: &DataType) -> DataType {
+    match data_type {
+        DataType::Utf8View => DataType::Utf8,
+        DataType::BinaryView => DataType::Binary,
+        DataType::ListView(field) | DataType::List(field) => {
+            DataType::List(cast_view_field_ref(field))
+        }
+        DataType::LargeListView(field) | DataType::LargeList(field) => {
+            DataType::LargeList(cast_view_field_ref(field))
+        }
+        DataType::FixedSizeList(field, size) => {
+            DataType::FixedSizeList(cast_view_field_ref(field), *size)
+        }
+        DataType::Map(field, sorted) => DataType::Map(cast_view_field_ref(field), *sorted),
+        DataType::Struct(fields) => {
+            DataType::Struct(fields.iter().map(cast_view_field_ref).collect())
+        }
+        DataType::Union(fields, mode) => DataType::Union(
+            fields
+                .iter()
+                .map(|(id, f)| (id, cast_view_field_ref(f)))
+                .collect(),
+            *mode,
+        ),
+        DataType::Dictionary(key, value) => {
+            DataType::Dictionary(key.clone(), Box::new(cast_view_type(value)))
+        }
+        DataType::RunEndEncoded(run_ends, values) => {
+            DataType::RunEndEncoded(run_ends.clone(), cast_view_field_ref(values))
+        }
+        other => other.clone(),
+    }
+}
+
+/// The non-view equivalent of a `Field`
+pub fn cast_view_field(field: &Field) -> Field {
+    field
+        .clone()
+        .with_data_type(cast_view_type(field.data_type()))
+}
+
+fn cast_view_field_ref(field: &FieldRef) -> FieldRef {
+    Arc::new(cast_view_field(field))
+}
+
+/// Replaces every view type in the fields of a `Schema`
+pub fn cast_view_schema(schema: &Schema) -> Schema {
+    let fields = schema
+        .fields()
+        .iter()
+        .map(cast_view_field_ref)
+        .collect::<Vec<_>>();
+    Schema::new(fields).with_metadata(schema.metadata().clone())
+}
+
+/// Casts the view types of an array
+///
+/// A top-level `Utf8View` or `BinaryView` array whose data does not fit in
+/// 32-bit offsets becomes `LargeUtf8` or `LargeBinary`.
+pub fn cast_views(array: &ArrayRef) -> Result<ArrayRef, ArrowError> {
+    let to = match array.data_type() {
+        DataType::Utf8View if view_data_len(array) > i32::MAX as usize => DataType::LargeUtf8,
+        DataType::BinaryView if view_data_len(array) > i32::MAX as usize => DataType::LargeBinary,
+        data_type => cast_view_type(data_type),
+    };
+
+    cast_views_to(array, &to)
+}
+
+// the total length of the values of a `Utf8View` or `BinaryView` array
+fn view_data_len(array: &ArrayRef) -> usize {
+    array
+        .as_binary_view_opt()
+        .map(|a| a.iter().flatten().map(|v| v.len()).sum())
+        .or_else(|| {
+            array
+                .as_string_view_opt()
+                .map(|a| a.iter().flatten().map(|v| v.len()).sum())
+        })
+        .unwrap_or(0)
+}
+
+/// Casts the view types of an array to `to`, usually from `cast_view_type()`
+///
+/// `compute::cast()` is used except for list views, which it does not
+/// support, and the nested types that contain them.
+pub fn cast_views_to(array: &ArrayRef, to: &DataType) -> Result<ArrayRef, ArrowError> {
+    if !has_list_views(array.data_type()) {
+        return cast(array, to);
+    }
+
+    match (array.data_type(), to) {
+        (DataType::ListView(_), DataType::List(field)) => list_view_to_list::<i32>(array, field),
+        (DataType::LargeListView(_), DataType::LargeList(field)) => {
+            list_view_to_list::<i64>(array, field)
+        }
+        (DataType::List(_), DataType::List(field)) => list_to_list::<i32>(array, field),
+        (DataType::LargeList(_), DataType::LargeList(field)) => list_to_list::<i64>(array, field),
+        (DataType::Struct(_), DataType::Struct(fields)) => {
+            let array = array.as_struct();
+            let columns = array
+                .columns()
+                .iter()
+                .zip(fields.iter())
+                .map(|(column, field)| cast_views_to(column, field.data_type()))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Arc::new(StructArray::try_new(
+                fields.clone(),
+                columns,
+                array.nulls().cloned(),
+            )?))
+        }
+        (DataType::FixedSizeList(_, _), DataType::FixedSizeList(field, size)) => {
+            let array = array.as_fixed_size_list();
+            let values = cast_views_to(array.values(), field.data_type())?;
+            Ok(Arc::new(FixedSizeListArray::try_new(
+                field.clone(),
+                *size,
+                values,
+                array.nulls().cloned(),
+            )?))
+        }
+        (DataType::Map(_, _), DataType::Map(field, sorted)) => {
+            let array = array.as_map();
+            let entries: ArrayRef = Arc::new(array.entries().clone());
+            let entries = cast_views_to(&entries, field.data_type())?;
+            Ok(Arc::new(MapArray::try_new(
+                field.clone(),
+                array.offsets().clone(),
+                entries.as_struct().clone(),
+                array.nulls().cloned(),
+                *sorted,
+            )?))
+        }
+        (DataType::Union(_, _), DataType::Union(fields, _)) => {
+            let array = array.as_union();
+            let children = fields
+                .iter()
+                .map(|(id, field)| cast_views_to(array.child(id), field.data_type()))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Arc::new(UnionArray::try_new(
+                fields.clone(),
+                array.type_ids().clone(),
+                array.offsets().cloned(),
+                children,
+            )?))
+        }
+        (DataType::Dictionary(_, _), DataType::Dictionary(_, value_type)) => {
+            let array = array.as_any_dictionary();
+            let values = cast_views_to(array.values(), value_type)?;
+            Ok(array.with_values(values))
+        }
+        (DataType::RunEndEncoded(_, _), DataType::RunEndEncoded(_, values_field)) => {
+            let data = array.to_data();
+            let run_ends = data.child_data()[0].clone();
+            let values = make_array(data.child_data()[1].clone());
+            let values = cast_views_to(&values, values_field.data_type())?;
+            let data = data
+                .into_builder()
+                .data_type(to.clone())
+                .child_data(vec![run_ends, values.to_data()])
+                .build()?;
+            Ok(make_array(data))
+        }
+        (from, to) => Err(ArrowError::CastError(format!(
+            "cannot cast list views in {from} to {to}"
+        ))),
+    }
+}
+
+/// Checks if a `DataType` is or contains a `ListView` or `LargeListView`
+///
+/// Searches the same nested types as `cast_view_type()`.
+pub fn has_list_views(data_type: &DataType) -> bool {
+    match data_type {
+        DataType::ListView(_) | DataType::LargeListView(_) => true,
+        DataType::List(field)
+        | DataType::LargeList(field)
+        | DataType::FixedSizeList(field, _)
+        | DataType::Map(field, _) => has_list_views(field.data_type()),
+        DataType::Struct(fields) => fields.iter().any(|f| has_list_views(f.data_type())),
+        DataType::Union(fields, _) => fields.iter().any(|(_, f)| has_list_views(f.data_type())),
+        DataType::Dictionary(_, value) => has_list_views(value),
+        DataType::RunEndEncoded(_, values) => has_list_views(values.data_type()),
+        _ => false,
+    }
+}
+
+// gathers the values of each list view into contiguous lists
+fn list_view_to_list<O: OffsetSizeTrait>(
+    array: &ArrayRef,
+    field: &FieldRef,
+) -> Result<ArrayRef, ArrowError> {
+    let array = array
+        .as_any()
+        .downcast_ref::<GenericListViewArray<O>>()
+        .ok_or_else(|| ArrowError::CastError("expected a list view array".into()))?;
+
+    let mut indices = Vec::new();
+    let mut lengths = Vec::with_capacity(array.len());
+
+    for i in 0..array.len() {
+        if array.is_null(i) {
+            lengths.push(0);
+            continue;
+        }
+
+        let offset = array.value_offset(i).as_usize();
+        let size = array.value_size(i).as_usize();
+        indices.extend((offset..offset + size).map(|j| j as u64));
+        lengths.push(size);
+    }
+
+    let values = take(array.values(), &UInt64Array::from(indices), None)?;
+    let values = cast_views_to(&values, field.data_type())?;
+
+    Ok(Arc::new(GenericListArray::<O>::try_new(
+        field.clone(),
+        OffsetBuffer::from_lengths(lengths),
+        values,
+        array.nulls().cloned(),
+    )?))
+}
+
+fn list_to_list<O: OffsetSizeTrait>(
+    array: &ArrayRef,
+    field: &FieldRef,
+) -> Result<ArrayRef, ArrowError> {
+    let array = array.as_list::<O>();
+    let values = cast_views_to(array.values(), field.data_type())?;
+
+    Ok(Arc::new(GenericListArray::<O>::try_new(
+        field.clone(),
+        array.offsets().clone(),
+        values,
+        array.nulls().cloned(),
+    )?))
+}
+
+/// Casts the view types of every column of a `RecordBatch` to the types in `schema`
+pub fn cast_batch_views(batch: &RecordBatch, schema: SchemaRef) -> Result<RecordBatch, ArrowError> {
+    let columns = batch
+        .columns()
+        .iter()
+        .zip(schema.fields().iter())
+        .map(|(column, field)| cast_views_to(column, field.data_type()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    RecordBatch::try_new(schema, columns)
+}
+
+/// A `RecordBatchReader` that casts the view types of each batch
+pub struct CastViewsReader {
+    inner: Box<dyn RecordBatchReader + Send>,
+    schema: SchemaRef,
+}
+
+impl CastViewsReader {
+    pub fn new(inner: Box<dyn RecordBatchReader + Send>) -> Self {
+        let schema = Arc::new(cast_view_schema(&inner.schema()));
+        Self { inner, schema }
+    }
+}
+
+impl Iterator for CastViewsReader {
+    type Item = Result<RecordBatch, ArrowError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner
+            .next()
+            .map(|batch| cast_batch_views(&batch?, self.schema.clone()))
+    }
+}
+
+impl RecordBatchReader for CastViewsReader {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::{
+        array::{
+            BinaryArray, BinaryViewArray, DictionaryArray, Int32Array, Int8Array,
+            LargeListViewArray, ListArray, ListViewArray, RunArray, StringArray, StringViewArray,
+        },
+        buffer::{NullBuffer, ScalarBuffer},
+        datatypes::{Int32Type, Int8Type, UnionFields},
+        ffi::{from_ffi, to_ffi},
+    };
+
+    use super::*;
+
+    const LONG: &str = "a string longer than twelve bytes";
+
+    // [[3, 4], null, [], [1, 2, 3]] with out of order and overlapping offsets
+    fn list_view_parts() -> (FieldRef, ArrayRef, Option<NullBuffer>) {
+        let field = Arc::new(Field::new("item", DataType::Int32, true));
+        let values: ArrayRef = Arc::new(Int32Array::from(vec![1, 2, 3, 4]));
+        let nulls = Some(NullBuffer::from(vec![true, false, true, true]));
+        (field, values, nulls)
+    }
+
+    fn expected_list() -> ListArray {
+        ListArray::from_iter_primitive::<Int32Type, _, _>(vec![
+            Some(vec![Some(3), Some(4)]),
+            None,
+            Some(vec![]),
+            Some(vec![Some(1), Some(2), Some(3)]),
+        ])
+    }
+
+    #[test]
+    fn casts_string_views() {
+        let array: ArrayRef = Arc::new(StringViewArray::from(vec![Some("a"), None, Some(LONG)]));
+        assert!(has_views(array.data_type()));
+
+        let cast = cast_views(&array).unwrap();
+        assert_eq!(cast.data_type(), &DataType::Utf8);
+        assert_eq!(
+            cast.as_string::<i32>(),
+            &StringArray::from(vec![Some("a"), None, Some(LONG)])
+        );
+    }
+
+    #[test]
+    fn casts_binary_views() {
+        let values: Vec<Option<&[u8]>> = vec![Some(b"ab"), Some(LONG.as_bytes()), None];
+        let array: ArrayRef = Arc::new(BinaryViewArray::from(values.clone()));
+
+        let cast = cast_views(&array).unwrap();
+        assert_eq!(cast.data_type(), &DataType::Binary);
+        assert_eq!(cast.as_binary::<i32>(), &BinaryArray::from(values));
+    }
+
+    #[test]
+    fn casts_list_views_to_lists() {
+        let (field, values, nulls) = list_view_parts();
+        let array: ArrayRef = Arc::new(
+            ListViewArray::try_new(
+                field,
+                ScalarBuffer::from(vec![2, 0, 0, 0]),
+                ScalarBuffer::from(vec![2, 0, 0, 3]),
+                values,
+                nulls,
+            )
+            .unwrap(),
+        );
+        assert!(has_list_views(array.data_type()));
+
+        let cast = cast_views(&array).unwrap();
+        assert_eq!(cast.as_list::<i32>(), &expected_list());
+    }
+
+    #[test]
+    fn casts_large_list_views_to_large_lists() {
+        let (field, values, nulls) = list_view_parts();
+        let array: ArrayRef = Arc::new(
+            LargeListViewArray::try_new(
+                field,
+                ScalarBuffer::from(vec![2i64, 0, 0, 0]),
+                ScalarBuffer::from(vec![2i64, 0, 0, 3]),
+                values,
+                nulls,
+            )
+            .unwrap(),
+        );
+
+        let cast = cast_views(&array).unwrap();
+        assert_eq!(cast.data_type(), &cast_view_type(array.data_type()));
+
+        let cast = cast.as_list::<i64>();
+        assert_eq!(cast.len(), 4);
+        assert!(cast.is_null(1));
+        assert_eq!(
+            cast.value(3).as_primitive::<Int32Type>(),
+            &Int32Array::from(vec![1, 2, 3])
+        );
+    }
+
+    #[test]
+    fn casts_views_nested_in_structs() {
+        let strings: ArrayRef = Arc::new(StringViewArray::from(vec!["x", LONG]));
+        let array: ArrayRef = Arc::new(StructArray::from(vec![(
+            Arc::new(Field::new("s", DataType::Utf8View, false)),
+            strings,
+        )]));
+
+        let to = cast_view_type(array.data_type());
+        assert_eq!(
+            to,
+            DataType::Struct(vec![Field::new("s", DataType::Utf8, false)].into())
+        );
+
+        let cast = cast_views_to(&array, &to).unwrap();
+        assert_eq!(
+            cast.as_struct().column(0).as_string::<i32>(),
+            &StringArray::from(vec!["x", LONG])
+        );
+    }
+
+    fn list_view() -> ArrayRef {
+        let (field, values, nulls) = list_view_parts();
+        Arc::new(
+            ListViewArray::try_new(
+                field,
+                ScalarBuffer::from(vec![2, 0, 0, 0]),
+                ScalarBuffer::from(vec![2, 0, 0, 3]),
+                values,
+                nulls,
+            )
+            .unwrap(),
+        )
+    }
+
+    // checks that `array` has list views and casts it like `cast_views()`
+    fn cast_nested(array: ArrayRef) -> ArrayRef {
+        assert!(has_list_views(array.data_type()));
+        let cast = cast_views(&array).unwrap();
+        assert!(!has_views(cast.data_type()));
+        cast
+    }
+
+    #[test]
+    fn casts_list_views_nested_in_fixed_size_lists() {
+        let field = Arc::new(Field::new("item", list_view().data_type().clone(), true));
+        let array = FixedSizeListArray::try_new(field, 2, list_view(), None).unwrap();
+
+        let cast = cast_nested(Arc::new(array));
+        let values = cast.as_fixed_size_list().values().clone();
+        assert_eq!(values.as_list::<i32>(), &expected_list());
+    }
+
+    #[test]
+    fn casts_list_views_nested_in_maps() {
+        let keys: ArrayRef = Arc::new(StringArray::from(vec!["a", "b", "c", "d"]));
+        let entries = StructArray::from(vec![
+            (Arc::new(Field::new("keys", DataType::Utf8, false)), keys),
+            (
+                Arc::new(Field::new("values", list_view().data_type().clone(), true)),
+                list_view(),
+            ),
+        ]);
+        let field = Arc::new(Field::new("entries", entries.data_type().clone(), false));
+        let array = MapArray::try_new(
+            field,
+            OffsetBuffer::from_lengths([2, 2]),
+            entries,
+            None,
+            false,
+        )
+        .unwrap();
+
+        let cast = cast_nested(Arc::new(array));
+        let values = cast.as_map().values().clone();
+        assert_eq!(values.as_list::<i32>(), &expected_list());
+    }
+
+    #[test]
+    fn casts_list_views_nested_in_unions() {
+        let field = Field::new("l", list_view().data_type().clone(), true);
+        let fields = UnionFields::new([0], [field]);
+        let array =
+            UnionArray::try_new(fields, vec![0i8; 4].into(), None, vec![list_view()]).unwrap();
+
+        let cast = cast_nested(Arc::new(array));
+        assert_eq!(cast.as_union().child(0).as_list::<i32>(), &expected_list());
+    }
+
+    #[test]
+    fn casts_list_views_nested_in_dictionaries() {
+        let array =
+            DictionaryArray::<Int8Type>::try_new(Int8Array::from(vec![3, 0]), list_view()).unwrap();
+
+        let cast = cast_nested(Arc::new(array));
+        let values = cast.as_any_dictionary().values().clone();
+        assert_eq!(values.as_list::<i32>(), &expected_list());
+    }
+
+    #[test]
+    fn casts_list_views_nested_in_run_end_encoded_arrays() {
+        let run_ends = Int32Array::from(vec![2, 3, 5, 6]);
+        let array = RunArray::<Int32Type>::try_new(&run_ends, &list_view()).unwrap();
+
+        let cast = cast_nested(Arc::new(array));
+        let values = make_array(cast.to_data().child_data()[1].clone());
+        assert_eq!(values.as_list::<i32>(), &expected_list());
+        assert_eq!(cast.len(), 6);
+    }
+
+    #[test]
+    fn casts_the_schema_and_batches_of_a_reader() {
+        let schema = Arc::new(Schema::new(vec![Field::new("s", DataType::Utf8View, true)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(StringViewArray::from(vec![Some(LONG), None]))],
+        )
+        .unwrap();
+        let inner = arrow::record_batch::RecordBatchIterator::new(vec![Ok(batch)], schema);
+
+        let reader = CastViewsReader::new(Box::new(inner));
+        assert_eq!(reader.schema().field(0).data_type(), &DataType::Utf8);
+
+        let batches = reader.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(
+            batches[0].column(0).as_string::<i32>(),
+            &StringArray::from(vec![Some(LONG), None])
+        );
+    }
+
+    #[test]
+    fn imports_string_views_through_the_c_data_interface() {
+        // what `ArrayData::from_arrow_robj()` does once R has exported the structs
+        let array = StringViewArray::from(vec![Some("a"), None, Some(LONG)]);
+        let (ffi_array, ffi_schema) = to_ffi(&array.to_data()).unwrap();
+        assert_eq!(ffi_schema.format(), "vu");
+
+        let data = unsafe { from_ffi(ffi_array, &ffi_schema) }.unwrap();
+        assert_eq!(data.data_type(), &DataType::Utf8View);
+        assert_eq!(StringViewArray::from(data), array);
+    }
+}